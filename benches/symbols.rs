@@ -0,0 +1,44 @@
+#![feature(test)]
+
+extern crate test;
+
+use std::collections::HashMap;
+
+use jirsp::eval::{eval, GlobalNamespace};
+use jirsp::symbol::Symbol;
+use jirsp::tokenize::tokenize_all;
+use jirsp::value::Value;
+use test::{black_box, Bencher};
+
+// Run with cargo bench. Looking up a global by its interned symbol should beat the string keyed
+// map that symbols replaced, and calls by name shouldn't have to look at the name at all
+
+#[bench]
+fn resolve_unqualified_symbol(bencher: &mut Bencher) {
+    let namespace = GlobalNamespace::default();
+    let symbol = Symbol::intern(b"reduce");
+    bencher.iter(|| namespace.resolve(black_box(symbol)).unwrap());
+}
+
+#[bench]
+fn resolve_qualified_symbol(bencher: &mut Bencher) {
+    let namespace = GlobalNamespace::default();
+    let symbol = Symbol::intern(b"core/reduce");
+    bencher.iter(|| namespace.resolve(black_box(symbol)).unwrap());
+}
+
+#[bench]
+fn string_keyed_baseline(bencher: &mut Bencher) {
+    let namespace = GlobalNamespace::default();
+    let globals: HashMap<String, Value> = namespace.names().into_iter()
+        .map(|symbol| (symbol.name().to_string(), namespace.get(symbol).unwrap()))
+        .collect();
+    bencher.iter(|| globals.get(black_box("reduce")).cloned());
+}
+
+#[bench]
+fn named_calls(bencher: &mut Bencher) {
+    let mut namespace = GlobalNamespace::default();
+    let nodes = tokenize_all(b"(reduce + (map (fn [x] (* x (- x 1))) (range 100)))", "bench".into()).unwrap();
+    bencher.iter(|| eval(&nodes[0], &mut namespace).unwrap());
+}
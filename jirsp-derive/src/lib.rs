@@ -0,0 +1,246 @@
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::{format_ident, quote};
+use syn::{parse_macro_input, parse_quote, Data, DeriveInput, Error, Fields, Generics, Ident, Path, Variant};
+
+// Structs with named fields become maps keyed by the field names as keywords, newtype structs are
+// their one field. Enum variants are tagged by keywords: unit variants are just the keyword, the
+// others are maps with the variant's keyword under :type, and their fields or, for newtype
+// variants, the one field under :value. Names are kebab-cased, so port_number is :port-number and
+// DarkRed is :dark-red
+#[proc_macro_derive(IntoValue)]
+pub fn derive_into_value(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    into_value(&input).unwrap_or_else(Error::into_compile_error).into()
+}
+
+#[proc_macro_derive(FromValue)]
+pub fn derive_from_value(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    from_value(&input).unwrap_or_else(Error::into_compile_error).into()
+}
+
+const TAG: &str = "type";
+const NEWTYPE_FIELD: &str = "value";
+
+fn kebab_case(ident: &Ident) -> String {
+    let name = ident.to_string();
+    let name = name.trim_start_matches("r#");
+    let mut kebab = String::new();
+    for (index, c) in name.chars().enumerate() {
+        if c == '_' {
+            kebab.push('-');
+        } else if c.is_uppercase() {
+            if index > 0 && !kebab.ends_with('-') {
+                kebab.push('-');
+            }
+            kebab.extend(c.to_lowercase());
+        } else {
+            kebab.push(c);
+        }
+    }
+    kebab
+}
+
+fn keyword(name: &str) -> TokenStream2 {
+    quote! { ::jirsp::value::Value::Keyword(::jirsp::symbol::Symbol::intern(#name.as_bytes())) }
+}
+
+// Every type parameter has to convert too
+fn bounded(generics: &Generics, bound: Path) -> Generics {
+    let mut generics = generics.clone();
+    let parameters: Vec<Ident> = generics.type_params().map(|parameter| parameter.ident.clone()).collect();
+    let where_clause = generics.make_where_clause();
+    for parameter in parameters {
+        where_clause.predicates.push(parse_quote!(#parameter: #bound));
+    }
+    generics
+}
+
+fn map(entries: Vec<TokenStream2>) -> TokenStream2 {
+    quote! {{
+        let entries: ::std::vec::Vec<(::jirsp::value::Value, ::jirsp::value::Value)> = ::std::vec![#(#entries),*];
+        ::jirsp::value::Value::Map(entries.into_iter().collect())
+    }}
+}
+
+// The fields of a struct or variant as (binding, keyword name) pairs
+fn named_fields(fields: &Fields) -> Vec<(Ident, String)> {
+    fields.iter().filter_map(|field| field.ident.clone()).map(|ident| {
+        let name = kebab_case(&ident);
+        (ident, name)
+    }).collect()
+}
+
+fn field_entries(fields: &[(Ident, String)]) -> Vec<TokenStream2> {
+    fields.iter().map(|(ident, name)| {
+        let key = keyword(name);
+        quote! { (#key, ::jirsp::convert::IntoValue::into_value(#ident)) }
+    }).collect()
+}
+
+enum Shape {
+    Unit,
+    Named(Vec<(Ident, String)>),
+    Newtype,
+}
+
+fn shape(fields: &Fields, tagged: bool) -> Result<Shape, Error> {
+    match fields {
+        Fields::Unit => Ok(Shape::Unit),
+        Fields::Unnamed(unnamed) if unnamed.unnamed.len() == 1 => Ok(Shape::Newtype),
+        Fields::Named(_) => {
+            let fields = named_fields(fields);
+            match fields.iter().find(|(_, name)| tagged && name == TAG) {
+                Some((ident, _)) => Err(Error::new(ident.span(), "a field named type would clash with the tag of the variant")),
+                None => Ok(Shape::Named(fields)),
+            }
+        }
+        Fields::Unnamed(unnamed) => Err(Error::new_spanned(unnamed, "only tuples with exactly one field can be converted")),
+    }
+}
+
+fn variants(data: &syn::DataEnum) -> Result<Vec<(&Variant, String, Shape)>, Error> {
+    data.variants.iter().map(|variant| Ok((variant, kebab_case(&variant.ident), shape(&variant.fields, true)?))).collect()
+}
+
+fn into_value(input: &DeriveInput) -> Result<TokenStream2, Error> {
+    let ident = &input.ident;
+    let generics = bounded(&input.generics, parse_quote!(::jirsp::convert::IntoValue));
+    let (impl_generics, type_generics, where_clause) = generics.split_for_impl();
+    let body = match &input.data {
+        Data::Struct(data) => match shape(&data.fields, false)? {
+            Shape::Named(fields) => {
+                let bindings = fields.iter().map(|(ident, _)| ident);
+                let map = map(field_entries(&fields));
+                quote! {
+                    let Self { #(#bindings),* } = self;
+                    #map
+                }
+            }
+            Shape::Newtype => quote! { ::jirsp::convert::IntoValue::into_value(self.0) },
+            Shape::Unit => quote! { ::jirsp::value::Value::Nil },
+        },
+        Data::Enum(data) => {
+            let arms = variants(data)?.into_iter().map(|(variant, name, shape)| {
+                let variant = &variant.ident;
+                let (tag_key, tag) = (keyword(TAG), keyword(&name));
+                match shape {
+                    Shape::Unit => quote! { Self::#variant => #tag },
+                    Shape::Named(fields) => {
+                        let bindings = fields.iter().map(|(ident, _)| ident);
+                        let entries = [vec![quote! { (#tag_key, #tag) }], field_entries(&fields)].concat();
+                        let map = map(entries);
+                        quote! { Self::#variant { #(#bindings),* } => #map }
+                    }
+                    Shape::Newtype => {
+                        let value_key = keyword(NEWTYPE_FIELD);
+                        let map = map(vec![quote! { (#tag_key, #tag) }, quote! { (#value_key, ::jirsp::convert::IntoValue::into_value(value)) }]);
+                        quote! { Self::#variant(value) => #map }
+                    }
+                }
+            });
+            quote! {
+                match self {
+                    #(#arms,)*
+                }
+            }
+        }
+        Data::Union(data) => return Err(Error::new_spanned(data.union_token, "unions can't be converted to values")),
+    };
+    Ok(quote! {
+        impl #impl_generics ::jirsp::convert::IntoValue for #ident #type_generics #where_clause {
+            fn into_value(self) -> ::jirsp::value::Value {
+                #body
+            }
+        }
+    })
+}
+
+fn field_conversions(fields: &[(Ident, String)], map: &Ident) -> Vec<TokenStream2> {
+    fields.iter().map(|(ident, name)| quote! { #ident: ::jirsp::convert::field(#map, #name)? }).collect()
+}
+
+fn from_value(input: &DeriveInput) -> Result<TokenStream2, Error> {
+    let ident = &input.ident;
+    let generics = bounded(&input.generics, parse_quote!(::jirsp::convert::FromValue));
+    let (impl_generics, type_generics, where_clause) = generics.split_for_impl();
+    let map = format_ident!("map");
+    let body = match &input.data {
+        Data::Struct(data) => match shape(&data.fields, false)? {
+            Shape::Named(fields) => {
+                let conversions = field_conversions(&fields, &map);
+                quote! {
+                    let ::jirsp::value::Value::Map(#map) = value else {
+                        return ::std::result::Result::Err(::jirsp::convert::Mismatch::new("a map", value));
+                    };
+                    ::std::result::Result::Ok(Self { #(#conversions),* })
+                }
+            }
+            Shape::Newtype => quote! { ::jirsp::convert::FromValue::from_value(value).map(Self) },
+            Shape::Unit => quote! {
+                match value {
+                    ::jirsp::value::Value::Nil => ::std::result::Result::Ok(Self),
+                    other => ::std::result::Result::Err(::jirsp::convert::Mismatch::new("nil", other)),
+                }
+            },
+        },
+        Data::Enum(data) => {
+            let variants = variants(data)?;
+            let expected = variants.iter().map(|(_, name, shape)| match shape {
+                Shape::Unit => format!(":{name}"),
+                _ => format!("{{:{TAG} :{name}}}"),
+            }).collect::<Vec<_>>();
+            let expected = match expected.as_slice() {
+                [only] => only.clone(),
+                all => format!("one of {}", all.join(", ")),
+            };
+            let mut keyword_arms = vec![];
+            let mut map_arms = vec![];
+            for (variant, name, shape) in &variants {
+                let variant = &variant.ident;
+                match shape {
+                    Shape::Unit => keyword_arms.push(quote! { #name => ::std::result::Result::Ok(Self::#variant) }),
+                    Shape::Named(fields) => {
+                        let conversions = field_conversions(fields, &map);
+                        map_arms.push(quote! { #name => ::std::result::Result::Ok(Self::#variant { #(#conversions),* }) });
+                    }
+                    Shape::Newtype => map_arms.push(quote! {
+                        #name => ::std::result::Result::Ok(Self::#variant(::jirsp::convert::field(#map, #NEWTYPE_FIELD)?))
+                    }),
+                }
+            }
+            let tag_key = keyword(TAG);
+            quote! {
+                let mismatch = |actual: &::jirsp::value::Value| ::jirsp::convert::Mismatch::new(#expected, actual);
+                match value {
+                    ::jirsp::value::Value::Keyword(tag) => match tag.name() {
+                        #(#keyword_arms,)*
+                        _ => ::std::result::Result::Err(mismatch(value)),
+                    },
+                    ::jirsp::value::Value::Map(#map) => {
+                        let tag_key = #tag_key;
+                        let tag = match #map.get(&tag_key) {
+                            ::std::option::Option::Some(::jirsp::value::Value::Keyword(tag)) => *tag,
+                            ::std::option::Option::Some(other) => return ::std::result::Result::Err(mismatch(other).in_field(tag_key)),
+                            ::std::option::Option::None => return ::std::result::Result::Err(::jirsp::convert::Mismatch::missing(#expected, tag_key)),
+                        };
+                        match tag.name() {
+                            #(#map_arms,)*
+                            _ => ::std::result::Result::Err(mismatch(&::jirsp::value::Value::Keyword(tag)).in_field(tag_key)),
+                        }
+                    }
+                    other => ::std::result::Result::Err(mismatch(other)),
+                }
+            }
+        }
+        Data::Union(data) => return Err(Error::new_spanned(data.union_token, "unions can't be converted from values")),
+    };
+    Ok(quote! {
+        impl #impl_generics ::jirsp::convert::FromValue for #ident #type_generics #where_clause {
+            fn from_value(value: &::jirsp::value::Value) -> ::std::result::Result<Self, ::jirsp::convert::Mismatch> {
+                #body
+            }
+        }
+    })
+}
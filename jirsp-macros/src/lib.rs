@@ -0,0 +1,232 @@
+#![feature(proc_macro_span)]
+
+use std::ops::Range;
+use std::rc::Rc;
+
+use jirsp::tokenize::{tokenize_all_located, AstNode, Span};
+use proc_macro::{Delimiter, Literal, TokenStream, TokenTree};
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use syn::{parse_macro_input, Error, LitStr};
+
+// Reads one form from a string literal while compiling, with the same reader the interpreter uses,
+// so a typo in an embedded script fails cargo build instead of the first run
+//
+//   let node: AstNode = risp!("(map inc [1 2 3])");
+//
+// A parse error is reported at the form it is about inside the literal. The spans of lists, and so
+// backtraces through the form, point at where they are written in the Rust file
+#[proc_macro]
+pub fn risp(input: TokenStream) -> TokenStream {
+    let token = find_literal(input.clone());
+    let literal = parse_macro_input!(input as LitStr);
+    let Some(token) = token else {
+        return Error::new(literal.span(), "risp! expects a string literal").into_compile_error().into();
+    };
+    let source = LiteralSource::new(&literal, token.span().line(), token.span().column());
+    expand(&literal, &source).unwrap_or_else(|error| {
+        let span = error.range.and_then(|range| token.subspan(range)).map_or_else(|| literal.span(), Into::into);
+        Error::new(span, error.message).into_compile_error()
+    }).into()
+}
+
+// The literal can come wrapped in an invisible group when risp! is called from another macro
+fn find_literal(input: TokenStream) -> Option<Literal> {
+    match input.into_iter().next()? {
+        TokenTree::Literal(literal) => Some(literal),
+        TokenTree::Group(group) if group.delimiter() == Delimiter::None => find_literal(group.stream()),
+        _ => None,
+    }
+}
+
+struct ReadError {
+    message: String,
+    // Bytes of the literal as written, the whole literal when there is none
+    range: Option<Range<usize>>,
+}
+
+// The literal as written and where it starts in the Rust file, to find the byte of the source each
+// byte of its value was written as
+struct LiteralSource {
+    line: usize,
+    column: usize,
+    text: String,
+    value: String,
+    // One more than the value has bytes, the last is the closing quote
+    offsets: Vec<usize>,
+}
+
+impl LiteralSource {
+    fn new(literal: &LitStr, line: usize, column: usize) -> LiteralSource {
+        let (text, value) = (literal.token().to_string(), literal.value());
+        // Falls back to counting from the opening quote should the escapes read differently than rustc did
+        let offsets = value_offsets(&text).filter(|offsets| offsets.len() == value.len() + 1)
+            .unwrap_or_else(|| (1..=value.len() + 1).collect());
+        LiteralSource { line, column, text, value, offsets }
+    }
+
+    // The bytes of the char at the offset into the value
+    fn range(&self, offset: usize) -> Range<usize> {
+        let char_len = self.value[offset..].chars().next().map_or(0, char::len_utf8);
+        self.offsets[offset]..self.offsets[offset + char_len]
+    }
+
+    fn position(&self, offset: usize) -> (usize, usize) {
+        let before = &self.text[..self.offsets[offset]];
+        match before.rfind('\n') {
+            Some(newline) => (self.line + before.matches('\n').count(), before[newline + 1..].chars().count() + 1),
+            None => (self.line, self.column + before.chars().count()),
+        }
+    }
+
+    // Spans count lines and chars of the value from 1
+    fn value_offset(&self, span: &Span) -> usize {
+        let line_start = self.value.split_inclusive('\n').take(span.line - 1).map(str::len).sum::<usize>();
+        let line = &self.value[line_start..];
+        line_start + line.char_indices().nth(span.column - 1).map_or(line.len(), |(index, _)| index)
+    }
+}
+
+// For "(f \"x\")" the ( is written at byte 1 and the x at byte 5, raw strings are written as they are
+fn value_offsets(text: &str) -> Option<Vec<usize>> {
+    if let Some(raw) = text.strip_prefix('r') {
+        let start = raw.find('"')? + 2;
+        let end = text.rfind('"')?;
+        return Some((start..=end).collect());
+    }
+    let bytes = text.as_bytes();
+    let end = text.len().checked_sub(1)?;
+    let mut offsets = vec![];
+    let mut index = 1;
+    while index < end {
+        if bytes[index] != b'\\' {
+            offsets.push(index);
+            index += 1;
+            continue;
+        }
+        match bytes.get(index + 1)? {
+            b'x' => {
+                offsets.push(index);
+                index += 4;
+            }
+            b'u' => {
+                let close = index + text[index..].find('}')?;
+                let code = u32::from_str_radix(&text[index + 3..close], 16).ok()?;
+                offsets.extend(std::iter::repeat_n(index, char::from_u32(code)?.len_utf8()));
+                index = close + 1;
+            }
+            // A line continuation leaves out the line break and the indentation after it
+            b'\n' | b'\r' => {
+                index += 1;
+                while bytes.get(index).is_some_and(u8::is_ascii_whitespace) {
+                    index += 1;
+                }
+            }
+            _ => {
+                offsets.push(index);
+                index += 2;
+            }
+        }
+    }
+    offsets.push(end);
+    Some(offsets)
+}
+
+fn expand(literal: &LitStr, source: &LiteralSource) -> Result<TokenStream2, ReadError> {
+    let nodes = tokenize_all_located(literal.value().as_bytes(), Rc::from("risp!"))
+        .map_err(|(error, offset)| ReadError { message: format!("invalid risp: {error}"), range: Some(source.range(offset)) })?;
+    match nodes.as_slice() {
+        [node] => Ok(node_tokens(node, source)),
+        nodes => Err(ReadError { message: format!("risp! expects exactly one form, found {}", nodes.len()), range: None }),
+    }
+}
+
+fn span_tokens(span: &Span, source: &LiteralSource) -> TokenStream2 {
+    let (line, column) = source.position(source.value_offset(span));
+    quote! {
+        ::std::option::Option::Some(::jirsp::tokenize::Span {
+            source: ::std::option::Option::Some(::std::rc::Rc::from(::std::file!())),
+            line: #line,
+            column: #column,
+        })
+    }
+}
+
+fn nodes_tokens(nodes: &[AstNode], source: &LiteralSource) -> TokenStream2 {
+    let nodes = nodes.iter().map(|node| node_tokens(node, source));
+    quote! { ::std::boxed::Box::new([#(#nodes),*]) }
+}
+
+// Code that builds the node again: symbols can only be interned once the program runs
+fn node_tokens(node: &AstNode, source: &LiteralSource) -> TokenStream2 {
+    match node {
+        AstNode::List(nodes, span) => {
+            let nodes = nodes_tokens(nodes, source);
+            let span = span.as_ref().map_or_else(|| quote! { ::std::option::Option::None }, |span| span_tokens(span, source));
+            quote! { ::jirsp::tokenize::AstNode::List(#nodes, #span) }
+        }
+        AstNode::Num(number) => quote! { ::jirsp::tokenize::AstNode::Num(#number) },
+        AstNode::Sym(symbol) => {
+            let name = symbol.name();
+            quote! { ::jirsp::tokenize::AstNode::Sym(::jirsp::symbol::Symbol::intern(#name.as_bytes())) }
+        }
+        AstNode::Str(string) => {
+            let string = &**string;
+            quote! { ::jirsp::tokenize::AstNode::Str(::std::rc::Rc::from(#string)) }
+        }
+        AstNode::Keyword(keyword) => {
+            let name = keyword.name();
+            quote! { ::jirsp::tokenize::AstNode::Keyword(::jirsp::symbol::Symbol::intern(#name.as_bytes())) }
+        }
+        AstNode::Vector(nodes) => {
+            let nodes = nodes_tokens(nodes, source);
+            quote! { ::jirsp::tokenize::AstNode::Vector(#nodes) }
+        }
+        AstNode::Map(nodes) => {
+            let nodes = nodes_tokens(nodes, source);
+            quote! { ::jirsp::tokenize::AstNode::Map(#nodes) }
+        }
+        AstNode::Regex(pattern) => {
+            let source = pattern.as_str();
+            quote! {
+                ::jirsp::tokenize::AstNode::Regex(::std::rc::Rc::new(
+                    ::jirsp::pattern::Pattern::new(#source).expect("risp! checked the regex while compiling")
+                ))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use syn::parse_quote;
+
+    use super::*;
+
+    fn read_error(literal: LitStr) -> (String, Option<String>) {
+        let source = LiteralSource::new(&literal, 1, 1);
+        let Err(error) = expand(&literal, &source) else { panic!("Expected an error") };
+        (error.message, error.range.map(|range| source.text[range].to_string()))
+    }
+
+    #[test]
+    fn parse_errors_point_into_the_literal() {
+        assert_eq!(read_error(parse_quote!("(+ 1 2")), ("invalid risp: missing right parenthesis in S expression".into(), Some("(".into())));
+        assert_eq!(read_error(parse_quote!("(f [1 2x])")), ("invalid risp: the given atom is not a valid number (2x)".into(), Some("2".into())));
+        assert_eq!(read_error(parse_quote!("(f \"\u{e9}\" \u{31}x)")).1, Some("\\u{31}".into()));
+        assert_eq!(read_error(parse_quote!(r#"(f "a" #"(")"#)).1, Some("#".into()));
+        assert_eq!(read_error(parse_quote!("1 2")), ("risp! expects exactly one form, found 2".into(), None));
+        let literal: LitStr = parse_quote!("; just a comment\n{:a [1 \"b\" #\"c+\"]}");
+        assert!(expand(&literal, &LiteralSource::new(&literal, 1, 1)).is_ok());
+    }
+
+    #[test]
+    fn values_map_back_to_where_they_are_written() {
+        let literal: LitStr = parse_quote!("(a \"q\"\n  (b \
+                                            c))");
+        let source = LiteralSource::new(&literal, 10, 20);
+        assert_eq!(source.position(source.value_offset(&Span { source: None, line: 1, column: 1 })), (10, 21));
+        assert_eq!(source.position(source.value_offset(&Span { source: None, line: 2, column: 3 })), (10, 33));
+        assert_eq!(source.position(source.value_offset(&Span { source: None, line: 2, column: 6 })), (11, 45));
+    }
+}
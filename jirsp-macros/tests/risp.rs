@@ -0,0 +1,32 @@
+use jirsp::eval::{eval, eval_traced, GlobalNamespace};
+use jirsp::tokenize::{tokenize, AstNode, AstToken, Span};
+use jirsp::value::Value;
+use jirsp_macros::risp;
+
+fn read(source: &str) -> AstNode {
+    match tokenize(source.as_bytes()).unwrap() {
+        AstToken::Parsed(node) | AstToken::ParsedRest((node, _)) => node,
+    }
+}
+
+#[test]
+fn forms_read_while_compiling_match_the_reader() {
+    assert_eq!(risp!("(map inc [1 -2 3])"), read("(map inc [1 -2 3])"));
+    assert_eq!(risp!("{:name \"risp\" :tags [a b] :pattern #\"[a-z]+\"}"),
+               read("{:name \"risp\" :tags [a b] :pattern #\"[a-z]+\"}"));
+    assert_eq!(risp!("; a comment first\n(quoted \"line\\nbreak\")"), read("(quoted \"line\\nbreak\")"));
+    let mut namespace = GlobalNamespace::default();
+    assert_eq!(eval(&risp!("(reduce + (map (fn [x] (+ x 1)) [1 2 3]))"), &mut namespace).unwrap(), Value::Num(9));
+}
+
+#[test]
+fn spans_point_into_the_rust_file() {
+    let line = line!() + 1;
+    let AstNode::List(nodes, span) = risp!("(do
+        (f x))") else { panic!("Expected a list") };
+    assert_eq!(span, Some(Span { source: Some(file!().into()), line: line as usize, column: 45 }));
+    let AstNode::List(_, span) = &nodes[1] else { panic!("Expected a list") };
+    assert_eq!(span, &Some(Span { source: Some(file!().into()), line: line as usize + 1, column: 9 }));
+    let error = eval_traced(&risp!("(+ 1 :a)"), &mut GlobalNamespace::default()).unwrap_err();
+    assert_eq!(error.backtrace[0].span.as_ref().map(|span| span.line), Some(line!() as usize - 1));
+}
@@ -0,0 +1,102 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::eval::{apply, Arity, EvalError, GlobalNamespace};
+use crate::value::Value;
+
+// A mutable reference cell, the one place where state can change. Atoms are only equal to themselves
+pub struct Atom(RefCell<Value>);
+
+impl Atom {
+    pub fn new(value: Value) -> Atom {
+        Atom(RefCell::new(value))
+    }
+
+    pub fn get(&self) -> Value {
+        self.0.borrow().clone()
+    }
+
+    pub fn set(&self, value: Value) {
+        *self.0.borrow_mut() = value;
+    }
+}
+
+pub fn define_builtins(namespace: &mut GlobalNamespace) {
+    namespace.defn(b"atom", lisp_atom.into());
+    namespace.defn(b"deref", lisp_deref.into());
+    namespace.defn(b"reset!", lisp_reset.into());
+    namespace.defn(b"swap!", lisp_swap.into());
+}
+
+fn expect_atom<'a>(function_name: &str, value: &'a Value) -> Result<&'a Atom, EvalError> {
+    match value {
+        Value::Atom(atom) => Ok(atom),
+        other => Err(EvalError::wrong_type(function_name, "an atom", other)),
+    }
+}
+
+fn lisp_atom(arguments: &[Value]) -> Result<Value, EvalError> {
+    let [value] = arguments else {
+        return Err(EvalError::wrong_arity("atom", Arity::Exactly(1), arguments.len()));
+    };
+    Ok(Value::Atom(Rc::new(Atom::new(value.clone()))))
+}
+
+fn lisp_deref(arguments: &[Value]) -> Result<Value, EvalError> {
+    let [atom] = arguments else {
+        return Err(EvalError::wrong_arity("deref", Arity::Exactly(1), arguments.len()));
+    };
+    Ok(expect_atom("deref", atom)?.get())
+}
+
+fn lisp_reset(arguments: &[Value]) -> Result<Value, EvalError> {
+    let [atom, value] = arguments else {
+        return Err(EvalError::wrong_arity("reset!", Arity::Exactly(2), arguments.len()));
+    };
+    expect_atom("reset!", atom)?.set(value.clone());
+    Ok(value.clone())
+}
+
+// (swap! a f x y) sets a to (f @a x y) and returns the new value. The atom isn't borrowed while f
+// runs, so f can look at it too
+fn lisp_swap(arguments: &[Value]) -> Result<Value, EvalError> {
+    let [atom, function, extra_arguments @ ..] = arguments else {
+        return Err(EvalError::wrong_arity("swap!", Arity::AtLeast(2), arguments.len()));
+    };
+    let atom = expect_atom("swap!", atom)?;
+    let function_arguments: Vec<Value> = std::iter::once(atom.get()).chain(extra_arguments.iter().cloned()).collect();
+    let new_value = apply(function, &function_arguments)?;
+    atom.set(new_value.clone());
+    Ok(new_value)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::assert_matches;
+
+    use crate::test_support::eval_str;
+
+    use super::*;
+
+    #[test]
+    fn atoms_hold_state_across_calls() {
+        let namespace = &mut GlobalNamespace::default();
+        eval_str("(def counter (atom 0))", namespace).unwrap();
+        eval_str("(def tick (fn [] (swap! counter + 1)))", namespace).unwrap();
+        eval_str("(do (tick) (tick) (tick))", namespace).unwrap();
+        assert_eq!(eval_str("@counter", namespace).unwrap(), Value::Num(3));
+        assert_eq!(eval_str("(reset! counter 10)", namespace).unwrap(), Value::Num(10));
+        assert_eq!(eval_str("(deref counter)", namespace).unwrap(), Value::Num(10));
+        assert_eq!(eval_str("(swap! counter (fn [n] (+ n @counter)))", namespace).unwrap(), Value::Num(20));
+        assert_eq!(eval_str("(atom [1 \"a\"])", namespace).unwrap().to_string(), "#<atom [1 \"a\"]>");
+    }
+
+    #[test]
+    fn atoms_are_only_equal_to_themselves() {
+        let namespace = &mut GlobalNamespace::default();
+        eval_str("(def a (atom 1))", namespace).unwrap();
+        assert_eq!(eval_str("(= a a)", namespace).unwrap(), Value::Bool(true));
+        assert_eq!(eval_str("(= a (atom 1))", namespace).unwrap(), Value::Bool(false));
+        assert_matches!(eval_str("@1", namespace), Err(EvalError::WrongType { .. }));
+    }
+}
@@ -0,0 +1,88 @@
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+
+use crate::eval::EvalError;
+use crate::tokenize::Span;
+
+// A call an error went through on its way out, with where the call was written
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StackFrame {
+    pub function: String,
+    pub span: Option<Span>,
+}
+
+impl Display for StackFrame {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match &self.span {
+            Some(span) => write!(f, "at {} ({})", self.function, span),
+            None => write!(f, "at {}", self.function),
+        }
+    }
+}
+
+// An error that escaped a top level expression, with its backtrace from the innermost call outwards
+#[derive(Debug)]
+pub struct TracedError {
+    pub error: EvalError,
+    pub backtrace: Vec<StackFrame>,
+}
+
+impl Display for TracedError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.error)?;
+        self.backtrace.iter().try_for_each(|frame| write!(f, "\n    {}", frame))
+    }
+}
+
+impl Error for TracedError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        Some(&self.error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+
+    use crate::eval::{eval_traced, GlobalNamespace};
+    use crate::tokenize::AstToken::Parsed;
+    use crate::tokenize::tokenize_source;
+
+    use super::*;
+
+    fn eval_lines(source: &str, namespace: &mut GlobalNamespace) -> Result<(), TracedError> {
+        for (index, line) in source.lines().enumerate() {
+            let Parsed(node) = tokenize_source(line.as_bytes(), Rc::from("script.lsp"), index + 1).unwrap() else { panic!() };
+            eval_traced(&node, namespace)?;
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn errors_list_the_calls_they_went_through() {
+        let namespace = &mut GlobalNamespace::default();
+        let error = eval_lines("(def inner (fn [x] (+ x :a)))\n(def outer (fn [] (- (inner 1))))\n  (outer)", namespace).unwrap_err();
+        assert_eq!(error.to_string(), "\
+wrong type of argument: + expects a number, got keyword :a
+    at + (script.lsp:1:20)
+    at inner (script.lsp:2:22)
+    at outer (script.lsp:3:3)");
+    }
+
+    #[test]
+    fn caught_errors_leave_no_frames_behind() {
+        let namespace = &mut GlobalNamespace::default();
+        let error = eval_lines("(def f (fn [] (try* (throw 1) (catch* e (throw 2)))))\n(f)", namespace).unwrap_err();
+        let functions: Vec<&str> = error.backtrace.iter().map(|frame| frame.function.as_str()).collect();
+        assert_eq!(functions, ["throw", "f"]);
+        assert_eq!(error.backtrace[0].span.as_ref().map(|span| span.column), Some(41));
+    }
+
+    #[test]
+    fn errors_dropped_by_rust_code_leave_no_frames_behind() {
+        let namespace = &mut GlobalNamespace::default();
+        let error = eval_lines("(do (= (lazy-seq (undefined-fn)) (list)) (+ 1 :a))", namespace).unwrap_err();
+        let functions: Vec<&str> = error.backtrace.iter().map(|frame| frame.function.as_str()).collect();
+        assert_eq!(functions, ["+"]);
+    }
+}
@@ -0,0 +1,203 @@
+use crate::eval::{apply, Arity, EvalError, GlobalNamespace};
+use crate::eval::EvalError::InvalidArguments;
+use crate::persistent_map::PersistentMap;
+use crate::value::Value;
+
+pub fn define_builtins(namespace: &mut GlobalNamespace) {
+    namespace.defn(b"vector", lisp_vector.into());
+    namespace.defn(b"hash-map", lisp_hash_map.into());
+    namespace.defn(b"conj", lisp_conj.into());
+    namespace.defn(b"assoc", lisp_assoc.into());
+    namespace.defn(b"dissoc", lisp_dissoc.into());
+    namespace.defn(b"get", lisp_get.into());
+    namespace.defn(b"update", lisp_update.into());
+    namespace.defn(b"keys", lisp_keys.into());
+    namespace.defn(b"vals", lisp_vals.into());
+}
+
+fn lisp_vector(arguments: &[Value]) -> Result<Value, EvalError> {
+    Ok(Value::Vector(arguments.iter().cloned().collect()))
+}
+
+fn lisp_hash_map(arguments: &[Value]) -> Result<Value, EvalError> {
+    assoc_pairs("hash-map", PersistentMap::new(), arguments).map(Value::Map)
+}
+
+fn assoc_pairs(function_name: &str, map: PersistentMap<Value, Value>, pairs: &[Value]) -> Result<PersistentMap<Value, Value>, EvalError> {
+    if !pairs.len().is_multiple_of(2) {
+        return Err(EvalError::wrong_arity(function_name, Arity::Pairs { leading: 0 }, pairs.len()));
+    }
+    Ok(pairs.chunks_exact(2).fold(map, |map, pair| map.insert(pair[0].clone(), pair[1].clone())))
+}
+
+fn vector_index(function_name: &str, key: &Value) -> Result<usize, EvalError> {
+    key.num().and_then(|index| usize::try_from(index).ok())
+        .ok_or_else(|| EvalError::wrong_type(function_name, "a non-negative index", key))
+}
+
+fn lisp_conj(arguments: &[Value]) -> Result<Value, EvalError> {
+    let Some((collection, values)) = arguments.split_first() else {
+        return Err(EvalError::wrong_arity("conj", Arity::AtLeast(1), 0));
+    };
+    match collection {
+        // Lists grow at the front, so conj-ing several values reverses them
+        Value::Nil => Ok(values.iter().rev().cloned().collect::<Vec<Value>>().into()),
+        Value::List(list) => Ok(values.iter().rev().chain(list.iter()).cloned().collect::<Vec<Value>>().into()),
+        Value::Vector(vector) => Ok(Value::Vector(values.iter().cloned().fold(vector.clone(), |vector, value| vector.push_back(value)))),
+        Value::Map(map) => values.iter().try_fold(map.clone(), |map, entry| match pair_len(entry) {
+            Some(2) => Ok(map.insert(nth(entry, 0), nth(entry, 1))),
+            _ => Err(EvalError::wrong_type("conj", "[key value] pairs for maps", entry)),
+        }).map(Value::Map),
+        other => Err(EvalError::wrong_type("conj", "a collection", other)),
+    }
+}
+
+fn pair_len(value: &Value) -> Option<usize> {
+    match value {
+        Value::Vector(vector) => Some(vector.len()),
+        Value::List(list) => Some(list.len()),
+        _ => None,
+    }
+}
+
+fn nth(value: &Value, index: usize) -> Value {
+    match value {
+        Value::Vector(vector) => vector.get(index).cloned().unwrap_or(Value::Nil),
+        Value::List(list) => list.get(index).cloned().unwrap_or(Value::Nil),
+        _ => Value::Nil,
+    }
+}
+
+fn assoc(collection: &Value, key: &Value, value: &Value) -> Result<Value, EvalError> {
+    match collection {
+        Value::Nil => Ok(Value::Map(PersistentMap::new().insert(key.clone(), value.clone()))),
+        Value::Map(map) => Ok(Value::Map(map.insert(key.clone(), value.clone()))),
+        Value::Vector(vector) => {
+            let index = vector_index("assoc", key)?;
+            if index == vector.len() {
+                return Ok(Value::Vector(vector.push_back(value.clone())));
+            }
+            vector.set(index, value.clone()).map(Value::Vector)
+                .ok_or_else(|| InvalidArguments(format!("assoc index {index} is out of bounds for a vector of {}", vector.len())))
+        }
+        other => Err(EvalError::wrong_type("assoc", "a map or a vector", other)),
+    }
+}
+
+fn lisp_assoc(arguments: &[Value]) -> Result<Value, EvalError> {
+    let Some((collection, pairs)) = arguments.split_first().filter(|(_, pairs)| !pairs.is_empty() && pairs.len().is_multiple_of(2)) else {
+        return Err(EvalError::wrong_arity("assoc", Arity::Pairs { leading: 1 }, arguments.len()));
+    };
+    pairs.chunks_exact(2).try_fold(collection.clone(), |collection, pair| assoc(&collection, &pair[0], &pair[1]))
+}
+
+fn lisp_dissoc(arguments: &[Value]) -> Result<Value, EvalError> {
+    match arguments.split_first() {
+        Some((Value::Nil, _)) => Ok(Value::Nil),
+        Some((Value::Map(map), keys)) => Ok(Value::Map(keys.iter().fold(map.clone(), |map, key| map.remove(key)))),
+        Some((other, _)) => Err(EvalError::wrong_type("dissoc", "a map", other)),
+        None => Err(EvalError::wrong_arity("dissoc", Arity::AtLeast(1), 0)),
+    }
+}
+
+fn get(collection: &Value, key: &Value) -> Option<Value> {
+    match (collection, key) {
+        (Value::Map(map), key) => map.get(key).cloned(),
+        (Value::Vector(vector), Value::Num(index)) => usize::try_from(*index).ok().and_then(|index| vector.get(index)).cloned(),
+        _ => None,
+    }
+}
+
+fn lisp_get(arguments: &[Value]) -> Result<Value, EvalError> {
+    match arguments {
+        [collection, key] => Ok(get(collection, key).unwrap_or(Value::Nil)),
+        [collection, key, default] => Ok(get(collection, key).unwrap_or_else(|| default.clone())),
+        _ => Err(EvalError::wrong_arity("get", Arity::Between(2, 3), arguments.len())),
+    }
+}
+
+fn lisp_update(arguments: &[Value]) -> Result<Value, EvalError> {
+    let [collection, key, function, extra_arguments @ ..] = arguments else {
+        return Err(EvalError::wrong_arity("update", Arity::AtLeast(3), arguments.len()));
+    };
+    let old_value = get(collection, key).unwrap_or(Value::Nil);
+    let function_arguments: Vec<Value> = std::iter::once(old_value).chain(extra_arguments.iter().cloned()).collect();
+    let new_value = apply(function, &function_arguments)?;
+    assoc(collection, key, &new_value)
+}
+
+fn lisp_keys(arguments: &[Value]) -> Result<Value, EvalError> {
+    match arguments {
+        [Value::Nil] => Ok(Value::List([].into())),
+        [Value::Map(map)] => Ok(map.keys().cloned().collect::<Vec<Value>>().into()),
+        [other] => Err(EvalError::wrong_type("keys", "a map", other)),
+        _ => Err(EvalError::wrong_arity("keys", Arity::Exactly(1), arguments.len())),
+    }
+}
+
+fn lisp_vals(arguments: &[Value]) -> Result<Value, EvalError> {
+    match arguments {
+        [Value::Nil] => Ok(Value::List([].into())),
+        [Value::Map(map)] => Ok(map.values().cloned().collect::<Vec<Value>>().into()),
+        [other] => Err(EvalError::wrong_type("vals", "a map", other)),
+        _ => Err(EvalError::wrong_arity("vals", Arity::Exactly(1), arguments.len())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::assert_matches;
+
+    use crate::eval::EvalError::{InvalidArguments, WrongArity, WrongType};
+    use crate::test_support::{eval_str, eval_value};
+
+    use super::*;
+
+    #[test]
+    fn conj_adds_where_the_collection_grows() {
+        assert_eq!(eval_value("(conj (list 1 2) 3 4)").to_string(), "(4 3 1 2)");
+        assert_eq!(eval_value("(conj [1 2] 3 4)").to_string(), "[1 2 3 4]");
+        assert_eq!(eval_value("(conj nil 1 2)").to_string(), "(2 1)");
+        assert_eq!(eval_value("(get (conj {:a 1} [:b 2]) :b)"), Value::Num(2));
+    }
+
+    #[test]
+    fn assoc_and_dissoc_leave_the_original_alone() {
+        let namespace = &mut GlobalNamespace::default();
+        eval_str("(def m {:a 1})", namespace).unwrap();
+        assert_eq!(eval_str("(assoc m :b 2 :a 3)", namespace).unwrap(), eval_value("{:a 3 :b 2}"));
+        assert_eq!(eval_str("m", namespace).unwrap(), eval_value("{:a 1}"));
+        assert_eq!(eval_str("(dissoc m :missing)", namespace).unwrap(), eval_value("{:a 1}"));
+        assert_eq!(eval_str("(dissoc m :a)", namespace).unwrap(), eval_value("{}"));
+        assert_eq!(eval_value("(assoc [1 2] 0 :x 2 :y)").to_string(), "[:x 2 :y]");
+        assert_eq!(eval_value("(assoc nil :a 1)"), eval_value("{:a 1}"));
+    }
+
+    #[test]
+    fn get_update_keys_and_vals() {
+        assert_eq!(eval_value("(get {:a 1} :b :default)"), Value::Keyword("default".into()));
+        assert_eq!(eval_value("(get [1 2] 5 :default)"), Value::Keyword("default".into()));
+        assert_eq!(eval_value("(get {:a nil} :a :default)"), Value::Nil);
+        assert_eq!(eval_value("(get [1 2] -1)"), Value::Nil);
+        assert_eq!(eval_value("(update {:n 1} :n + 10)"), eval_value("{:n 11}"));
+        assert_eq!(eval_value("(update [1 2] 1 (fn [x] (* x 5)))").to_string(), "[1 10]");
+        assert_eq!(eval_value("(keys {:a 1})").to_string(), "(:a)");
+        assert_eq!(eval_value("(vals {:a 1})").to_string(), "(1)");
+        assert_eq!(eval_value("(keys nil)").to_string(), "()");
+    }
+
+    #[test]
+    fn wrong_collections_and_indexes_are_errors() {
+        let namespace = &mut GlobalNamespace::default();
+        assert_eq!(eval_str("(assoc [1 2] 5 :x)", namespace).unwrap_err().to_string(), "invalid arguments: assoc index 5 is out of bounds for a vector of 2");
+        assert_matches!(eval_str("(assoc [1 2] :a :x)", namespace), Err(WrongType { expected: "a non-negative index", .. }));
+        assert_matches!(eval_str("(conj 1 2)", namespace), Err(WrongType { expected: "a collection", .. }));
+        assert_matches!(eval_str("(conj {} 1)", namespace), Err(WrongType { expected: "[key value] pairs for maps", .. }));
+        assert_matches!(eval_str("(dissoc [1] 0)", namespace), Err(WrongType { expected: "a map", .. }));
+        assert_matches!(eval_str("(keys [1])", namespace), Err(WrongType { expected: "a map", .. }));
+        assert_matches!(eval_str("(get {})", namespace), Err(WrongArity { expected: Arity::Between(2, 3), actual: 1, .. }));
+        assert_matches!(eval_str("(update {} :a)", namespace), Err(WrongArity { .. }));
+        assert_matches!(eval_str("(update {:a 1} :a :not-a-function)", namespace), Err(EvalError::NotAFunction(_)));
+        assert_matches!(eval_str("(assoc [1 2] 5 :x)", namespace), Err(InvalidArguments(_)));
+    }
+}
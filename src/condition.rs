@@ -0,0 +1,108 @@
+use crate::eval::{apply, Arity, EvalError, GlobalNamespace, WeakNamespace};
+use crate::eval::EvalError::RestartInvoked;
+use crate::symbol::Symbol;
+use crate::value::Value;
+
+// Conditions are signalled without unwinding: handler-bind handlers run on top of the code that
+// signalled, and can recover by invoking one of the restarts a restart-case around that code offers.
+// Errors and thrown values are signalled too, as the values catch* would bind them to
+#[derive(Clone)]
+pub enum Handler {
+    // Bound by handler-bind, the cluster starts where the handlers bound by the same form begin on the stack
+    Bind { matcher: Value, function: Value, cluster_start: usize },
+    // A try* with a catch*
+    Catch,
+}
+
+pub fn define_builtins(namespace: &mut GlobalNamespace) {
+    let weak_namespace = namespace.downgrade();
+    namespace.defn(b"signal", (move |arguments: &[Value]| lisp_signal(&weak_namespace, arguments)).into());
+    let weak_namespace = namespace.downgrade();
+    namespace.defn(b"invoke-restart", (move |arguments: &[Value]| lisp_invoke_restart(&weak_namespace, arguments)).into());
+}
+
+// Keywords match the :type of a map condition or the condition itself, anything else is a predicate
+pub fn matches(matcher: &Value, condition: &Value) -> Result<bool, EvalError> {
+    match (matcher, condition) {
+        (Value::Keyword(_), Value::Map(map)) => Ok(map.get(&Value::Keyword(Symbol::intern(b"type"))) == Some(matcher)),
+        (Value::Keyword(_), condition) => Ok(matcher == condition),
+        (predicate, condition) => Ok(apply(predicate, std::slice::from_ref(condition))?.is_truthy()),
+    }
+}
+
+// Returns nil when no handler takes care of the condition
+fn lisp_signal(namespace: &WeakNamespace, arguments: &[Value]) -> Result<Value, EvalError> {
+    let [condition] = arguments else {
+        return Err(EvalError::wrong_arity("signal", Arity::Exactly(1), arguments.len()));
+    };
+    namespace.upgrade()?.signal(condition)?;
+    Ok(Value::Nil)
+}
+
+fn lisp_invoke_restart(namespace: &WeakNamespace, arguments: &[Value]) -> Result<Value, EvalError> {
+    let Some((name, restart_arguments)) = arguments.split_first() else {
+        return Err(EvalError::wrong_arity("invoke-restart", Arity::AtLeast(1), 0));
+    };
+    let Value::Keyword(name) = name else {
+        return Err(EvalError::wrong_type("invoke-restart", "a restart name as keyword", name));
+    };
+    let id = namespace.upgrade()?.find_restart(*name)
+        .ok_or_else(|| EvalError::InvalidArguments(format!("no restart named :{name} is active")))?;
+    Err(RestartInvoked { id, name: name.to_string(), arguments: restart_arguments.to_vec() })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::assert_matches;
+
+    use crate::test_support::eval_str;
+
+    use super::*;
+
+    const PARSE_RECORD: &str = "(def parse-record (fn [record] (restart-case (if (= record :bad) (throw {:type :bad-record :record record}) record) (:use-value [value] value) (:skip-record [] :skipped))))";
+
+    #[test]
+    fn handlers_pick_a_restart_without_unwinding_the_loop() {
+        let namespace = &mut GlobalNamespace::default();
+        eval_str(PARSE_RECORD, namespace).unwrap();
+        assert_eq!(eval_str("(handler-bind [:bad-record (fn [c] (invoke-restart :use-value 0))] (map parse-record [1 :bad 3]))", namespace).unwrap().to_string(), "(1 0 3)");
+        assert_eq!(eval_str("(handler-bind [:bad-record (fn [c] (invoke-restart :skip-record))] (map parse-record [:bad 2]))", namespace).unwrap().to_string(), "(:skipped 2)");
+        assert_eq!(eval_str("(handler-bind [:wrong-type (fn [c] (invoke-restart :use-value (get c :type)))] (restart-case (+ 1 :a) (:use-value [v] v)))", namespace).unwrap().to_string(), ":wrong-type");
+    }
+
+    #[test]
+    fn errors_outside_of_calls_are_signalled_once() {
+        let namespace = &mut GlobalNamespace::default();
+        assert_eq!(eval_str("(handler-bind [:unbound-symbol (fn [c] (invoke-restart :use-value 41))] (restart-case (+ undefined-sym 1) (:use-value [v] v)))", namespace).unwrap(), Value::Num(41));
+        assert_eq!(eval_str("(handler-bind [:invalid-arguments (fn [c] (invoke-restart :use-value :bad-let))] (restart-case (do (let [x] x)) (:use-value [v] v)))", namespace).unwrap().to_string(), ":bad-let");
+        eval_str("(def counter (atom 0))", namespace).unwrap();
+        assert_matches!(eval_str("(handler-bind [(fn [c] true) (fn [c] (swap! counter (fn [n] (+ n 1))))] (list (list (list undefined-sym))))", namespace), Err(EvalError::UnboundSymbol { .. }));
+        assert_eq!(eval_str("@counter", namespace).unwrap(), Value::Num(1));
+    }
+
+    #[test]
+    fn errors_dropped_by_rust_code_dont_hide_later_ones() {
+        let namespace = &mut GlobalNamespace::default();
+        let source = "(handler-bind [:bad (fn [c] (invoke-restart :use-value 7))] (restart-case (do (= (lazy-seq (undefined-fn)) (list)) (throw {:type :bad})) (:use-value [v] v)))";
+        assert_eq!(eval_str(source, namespace).unwrap(), Value::Num(7));
+    }
+
+    #[test]
+    fn declining_handlers_let_the_error_through() {
+        let namespace = &mut GlobalNamespace::default();
+        assert_eq!(eval_str("(try* (handler-bind [:boom (fn [c] (def seen (get c :type)))] (throw {:type :boom})) (catch* e :caught))", namespace).unwrap().to_string(), ":caught");
+        assert_eq!(eval_str("seen", namespace).unwrap().to_string(), ":boom");
+        // The inner catch* wins over the outer handler
+        assert_eq!(eval_str("(handler-bind [(fn [c] true) (fn [c] (invoke-restart :nowhere))] (try* (throw 1) (catch* e :inner)))", namespace).unwrap().to_string(), ":inner");
+        assert_eq!(eval_str("(handler-bind [:ping (fn [c] (def pinged true))] (signal :ping))", namespace).unwrap(), Value::Nil);
+        assert_eq!(eval_str("pinged", namespace).unwrap(), Value::Bool(true));
+    }
+
+    #[test]
+    fn restarts_only_exist_inside_their_restart_case() {
+        let namespace = &mut GlobalNamespace::default();
+        assert_matches!(eval_str("(invoke-restart :use-value 1)", namespace), Err(EvalError::InvalidArguments(_)));
+        assert_matches!(eval_str("(restart-case 1 (:use-value [v] v))", namespace), Ok(Value::Num(1)));
+        assert_matches!(eval_str("(restart-case (try* (invoke-restart :retry 2) (catch* e 0)) (:retry [n] (* n 10)))", namespace), Ok(Value::Num(20)));
+    }
+}
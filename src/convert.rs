@@ -0,0 +1,347 @@
+use std::rc::Rc;
+
+use crate::eval::{Arity, EvalError, LispFn};
+use crate::eval::EvalError::{MissingField, WrongFieldType};
+use crate::persistent_map::PersistentMap;
+use crate::symbol::Symbol;
+use crate::value::Value;
+
+pub use jirsp_derive::{FromValue, IntoValue};
+
+// Why an argument couldn't be converted, becomes a wrong type error naming the function
+#[derive(Debug)]
+pub struct Mismatch {
+    pub expected: &'static str,
+    // None when a map was missing the key
+    pub actual: Option<Value>,
+    // The keys leading to the value inside of the argument, outermost first, like [:server :port]
+    pub path: Vec<Value>,
+}
+
+impl Mismatch {
+    pub fn new(expected: &'static str, actual: &Value) -> Mismatch {
+        Mismatch { expected, actual: Some(actual.clone()), path: vec![] }
+    }
+
+    pub fn missing(expected: &'static str, key: Value) -> Mismatch {
+        Mismatch { expected, actual: None, path: vec![key] }
+    }
+
+    // For conversions of maps, says under which key the value that didn't convert was
+    pub fn in_field(mut self, key: Value) -> Mismatch {
+        self.path.insert(0, key);
+        self
+    }
+
+    pub fn into_error(self, function: &str) -> EvalError {
+        match self {
+            Mismatch { expected, actual: Some(actual), path } if path.is_empty() => EvalError::wrong_type(function, expected, &actual),
+            Mismatch { expected, actual: Some(actual), path } => WrongFieldType { function: function.into(), path: path.into(), expected, actual },
+            Mismatch { expected, actual: None, path } => MissingField { function: function.into(), path: path.into(), expected },
+        }
+    }
+}
+
+// Rust types a registered function can take as arguments
+pub trait FromValue: Sized {
+    fn from_value(value: &Value) -> Result<Self, Mismatch>;
+}
+
+// Rust types a registered function can return
+pub trait IntoValue {
+    fn into_value(self) -> Value;
+}
+
+impl FromValue for Value {
+    fn from_value(value: &Value) -> Result<Self, Mismatch> {
+        Ok(value.clone())
+    }
+}
+
+impl FromValue for bool {
+    fn from_value(value: &Value) -> Result<Self, Mismatch> {
+        match value {
+            Value::Bool(bool) => Ok(*bool),
+            other => Err(Mismatch::new("a boolean", other)),
+        }
+    }
+}
+
+impl FromValue for isize {
+    fn from_value(value: &Value) -> Result<Self, Mismatch> {
+        value.num().ok_or_else(|| Mismatch::new("a number", value))
+    }
+}
+
+// Numbers are pointer sized, integer types only take the numbers that fit. The other way numbers
+// that don't fit saturate, so usize::MAX is the largest number rather than wrapping around to -1
+macro_rules! integer_conversions {
+    ($($integer:ty => $expected:literal),*) => {$(
+        impl FromValue for $integer {
+            fn from_value(value: &Value) -> Result<Self, Mismatch> {
+                value.num().and_then(|num| <$integer>::try_from(num).ok()).ok_or_else(|| Mismatch::new($expected, value))
+            }
+        }
+
+        impl IntoValue for $integer {
+            fn into_value(self) -> Value {
+                let saturated = if self > <$integer>::default() { isize::MAX } else { isize::MIN };
+                Value::Num(isize::try_from(self).unwrap_or(saturated))
+            }
+        }
+    )*};
+}
+
+integer_conversions!(i64 => "a number", i32 => "a 32 bit number", usize => "a non-negative number", u32 => "a non-negative 32 bit number");
+
+impl FromValue for Rc<str> {
+    fn from_value(value: &Value) -> Result<Self, Mismatch> {
+        match value {
+            Value::Str(string) => Ok(string.clone()),
+            other => Err(Mismatch::new("a string", other)),
+        }
+    }
+}
+
+impl FromValue for String {
+    fn from_value(value: &Value) -> Result<Self, Mismatch> {
+        Rc::<str>::from_value(value).map(|string| string.to_string())
+    }
+}
+
+// Lists and vectors both convert, and the first element that doesn't is what the error is about
+impl<T: FromValue> FromValue for Vec<T> {
+    fn from_value(value: &Value) -> Result<Self, Mismatch> {
+        match value {
+            Value::List(values) => values.iter().map(T::from_value).collect(),
+            Value::Vector(values) => values.iter().map(T::from_value).collect(),
+            other => Err(Mismatch::new("a list or vector", other)),
+        }
+    }
+}
+
+// nil is None
+impl<T: FromValue> FromValue for Option<T> {
+    fn from_value(value: &Value) -> Result<Self, Mismatch> {
+        match value {
+            Value::Nil => Ok(None),
+            value => T::from_value(value).map(Some),
+        }
+    }
+}
+
+impl IntoValue for Value {
+    fn into_value(self) -> Value {
+        self
+    }
+}
+
+impl IntoValue for () {
+    fn into_value(self) -> Value {
+        Value::Nil
+    }
+}
+
+impl IntoValue for bool {
+    fn into_value(self) -> Value {
+        Value::Bool(self)
+    }
+}
+
+impl IntoValue for isize {
+    fn into_value(self) -> Value {
+        Value::Num(self)
+    }
+}
+
+impl IntoValue for Rc<str> {
+    fn into_value(self) -> Value {
+        Value::Str(self)
+    }
+}
+
+impl IntoValue for String {
+    fn into_value(self) -> Value {
+        Value::Str(self.into())
+    }
+}
+
+impl IntoValue for &str {
+    fn into_value(self) -> Value {
+        Value::Str(self.into())
+    }
+}
+
+impl<T: IntoValue> IntoValue for Vec<T> {
+    fn into_value(self) -> Value {
+        Value::Vector(self.into_iter().map(IntoValue::into_value).collect())
+    }
+}
+
+impl<T: IntoValue> IntoValue for Option<T> {
+    fn into_value(self) -> Value {
+        self.map_or(Value::Nil, IntoValue::into_value)
+    }
+}
+
+// Converts the value under the keyword, what derived FromValue implementations use for each field.
+// A missing key converts like nil, so Option fields can be left out
+pub fn field<T: FromValue>(map: &PersistentMap<Value, Value>, name: &str) -> Result<T, Mismatch> {
+    let key = Value::Keyword(Symbol::intern(name.as_bytes()));
+    match map.get(&key) {
+        Some(value) => T::from_value(value).map_err(|mismatch| mismatch.in_field(key)),
+        None => T::from_value(&Value::Nil).map_err(|mismatch| Mismatch::missing(mismatch.expected, key)),
+    }
+}
+
+// What a registered function returns: a value, or a Result whose error converts to an EvalError,
+// like a String message or an EvalError itself
+pub trait IntoResult {
+    fn into_result(self) -> Result<Value, EvalError>;
+}
+
+impl<T: IntoValue> IntoResult for T {
+    fn into_result(self) -> Result<Value, EvalError> {
+        Ok(self.into_value())
+    }
+}
+
+impl<T: IntoValue, E: Into<EvalError>> IntoResult for Result<T, E> {
+    fn into_result(self) -> Result<Value, EvalError> {
+        self.map(IntoValue::into_value).map_err(Into::into)
+    }
+}
+
+// Rust functions and closures that can be registered with GlobalNamespace::register, the name is
+// used in the errors for wrong arguments. Args is only there to tell the implementations apart
+pub trait NativeFunction<Args> {
+    fn into_lisp_fn(self, name: &str) -> LispFn;
+}
+
+macro_rules! native_functions {
+    ($($argument:ident),*) => {
+        impl<Function, Return, $($argument),*> NativeFunction<($($argument,)*)> for Function
+            where Function: Fn($($argument),*) -> Return + 'static, Return: IntoResult, $($argument: FromValue),* {
+            #[allow(non_snake_case)]
+            fn into_lisp_fn(self, name: &str) -> LispFn {
+                let name = name.to_string();
+                LispFn::from(move |arguments: &[Value]| {
+                    let [$($argument),*] = arguments else {
+                        return Err(EvalError::wrong_arity(&name, Arity::Exactly(<[&str]>::len(&[$(stringify!($argument)),*])), arguments.len()));
+                    };
+                    $(let $argument = $argument::from_value($argument)
+                        .map_err(|mismatch| mismatch.into_error(&name))?;)*
+                    self($($argument),*).into_result()
+                })
+            }
+        }
+    };
+}
+
+native_functions!();
+native_functions!(A);
+native_functions!(A, B);
+native_functions!(A, B, C);
+native_functions!(A, B, C, D);
+native_functions!(A, B, C, D, E);
+native_functions!(A, B, C, D, E, F);
+
+#[cfg(test)]
+mod tests {
+    use std::assert_matches;
+
+    use crate::eval::GlobalNamespace;
+    use crate::eval::EvalError::{Host, WrongArity, WrongType};
+    use crate::test_support::eval_str;
+
+    use super::*;
+
+    fn repeat(times: i64, word: String) -> Result<Vec<String>, String> {
+        if times < 0 {
+            return Err(format!("cannot repeat {times} times"));
+        }
+        Ok((0..times).map(|_| word.clone()).collect())
+    }
+
+    #[test]
+    fn registered_functions_convert_their_arguments_and_results() {
+        let namespace = &mut GlobalNamespace::default();
+        namespace.register("repeat-word", repeat);
+        namespace.register("sum", |numbers: Vec<i64>| numbers.iter().sum::<i64>());
+        namespace.register("greet", |name: Option<String>| format!("hello {}", name.as_deref().unwrap_or("stranger")));
+        namespace.register("answer", || 42isize);
+        assert_eq!(eval_str("(repeat-word 2 \"hi\")", namespace).unwrap(), eval_str("[\"hi\" \"hi\"]", namespace).unwrap());
+        assert_eq!(eval_str("(sum (list 1 2 3))", namespace).unwrap(), Value::Num(6));
+        assert_eq!(eval_str("(greet nil)", namespace).unwrap(), Value::Str("hello stranger".into()));
+        assert_eq!(eval_str("(answer)", namespace).unwrap(), Value::Num(42));
+    }
+
+    #[test]
+    fn wrong_arguments_and_errors_are_reported_by_name() {
+        let namespace = &mut GlobalNamespace::default();
+        namespace.register("repeat-word", repeat);
+        namespace.register("sum", |numbers: Vec<i64>| numbers.iter().sum::<i64>());
+        assert_matches!(eval_str("(repeat-word 2)", namespace), Err(WrongArity { expected: Arity::Exactly(2), actual: 1, .. }));
+        assert_eq!(eval_str("(sum [1 :a])", namespace).unwrap_err().to_string(),
+                   "wrong type of argument: sum expects a number, got keyword :a");
+        assert_matches!(eval_str("(repeat-word \"2\" \"hi\")", namespace), Err(WrongType { expected: "a number", .. }));
+        assert_matches!(eval_str("(repeat-word -1 \"hi\")", namespace), Err(Host(message)) if message == "cannot repeat -1 times");
+        assert_eq!(eval_str("(try* (repeat-word -1 \"hi\") (catch* e (get e :type)))", namespace).unwrap(),
+                   Value::Keyword("host-error".into()));
+    }
+
+    #[test]
+    fn integers_that_dont_fit_saturate() {
+        assert_eq!(usize::MAX.into_value(), Value::Num(isize::MAX));
+        assert_eq!(i64::MIN.into_value(), Value::Num(isize::MIN));
+        assert_eq!(u32::MAX.into_value(), Value::Num(u32::MAX as isize));
+        assert_eq!(i32::from_value(&i64::MAX.into_value()).unwrap_err().into_error("f").to_string(),
+                   format!("wrong type of argument: f expects a 32 bit number, got number {}", isize::MAX));
+        assert_eq!(usize::from_value(&Value::Num(-1)).ok(), None);
+    }
+
+    #[derive(Debug, PartialEq, IntoValue, FromValue)]
+    enum Color {
+        Red,
+        DarkGreen,
+        Rgb { red: i64, green: i64, blue: i64 },
+        Named(String),
+    }
+
+    #[derive(Debug, PartialEq, IntoValue, FromValue)]
+    struct Theme {
+        name: String,
+        accent_color: Color,
+        font_size: Option<i64>,
+        tags: Vec<String>,
+    }
+
+    #[test]
+    fn derived_conversions_use_maps_and_tagged_keywords() {
+        let namespace = &mut GlobalNamespace::default();
+        let theme = Theme { name: "dusk".into(), accent_color: Color::Rgb { red: 1, green: 2, blue: 3 }, font_size: None, tags: vec!["dark".into()] };
+        namespace.def(b"theme", theme.into_value());
+        assert_eq!(eval_str("(get (get theme :accent-color) :type)", namespace).unwrap(), Value::Keyword("rgb".into()));
+        assert_eq!(eval_str("(get theme :font-size)", namespace).unwrap(), Value::Nil);
+        let read_back = |source: &str, namespace: &mut GlobalNamespace| Theme::from_value(&eval_str(source, namespace).unwrap());
+        assert_eq!(read_back("(assoc theme :accent-color :dark-green)", namespace).unwrap().accent_color, Color::DarkGreen);
+        assert_eq!(read_back("(dissoc theme :font-size)", namespace).unwrap().font_size, None);
+        assert_eq!(Color::from_value(&Color::Named("teal".into()).into_value()).unwrap(), Color::Named("teal".into()));
+        assert_eq!(Color::Red.into_value(), Value::Keyword("red".into()));
+    }
+
+    #[test]
+    fn derived_conversions_say_which_field_is_wrong() {
+        let namespace = &mut GlobalNamespace::default();
+        namespace.register("theme-name", |theme: Theme| theme.name);
+        assert_eq!(eval_str("(theme-name {:name \"a\" :accent-color :red :tags [\"x\" 1]})", namespace).unwrap_err().to_string(),
+                   "wrong type of argument: theme-name expects a string at :tags, got number 1");
+        assert_eq!(eval_str("(theme-name {:accent-color :red :tags []})", namespace).unwrap_err().to_string(),
+                   "missing field: theme-name expects a string at :name");
+        assert_eq!(eval_str("(theme-name {:name \"a\" :accent-color {:type :rgb :red 1 :green :g :blue 3} :tags []})", namespace).unwrap_err().to_string(),
+                   "wrong type of argument: theme-name expects a number at :accent-color :green, got keyword :g");
+        assert_eq!(eval_str("(theme-name {:name \"a\" :accent-color :blue :tags []})", namespace).unwrap_err().to_string(),
+                   "wrong type of argument: theme-name expects one of :red, :dark-green, {:type :rgb}, {:type :named} at :accent-color, got keyword :blue");
+        assert_eq!(eval_str("(theme-name {:name \"a\" :accent-color :red :tags []})", namespace).unwrap(), Value::Str("a".into()));
+    }
+}
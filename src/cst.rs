@@ -0,0 +1,168 @@
+use std::str::from_utf8;
+
+use crate::parse_error::ParseError;
+use crate::parse_error::ParseError::{CannotParseEmpty, InvalidUtf8, MismatchedDelimiter, MissingClosingDelimiter, MissingLeftParenthesis, MissingRightParenthesis, OddNumberOfMapForms, UnexpectedClosingDelimiter};
+use crate::tokenize::{is_whitespace, read_literal};
+
+// A concrete syntax tree: unlike AstNode it keeps comments, blank lines and literals exactly as they
+// were written, so the formatter can lay the source out again without losing anything
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CstNode {
+    // Numbers, symbols, keywords, strings and regexes, with their original spelling
+    Atom(String),
+    // The whole comment including the semicolon. A trailing comment followed code on the same line
+    Comment { text: String, trailing: bool },
+    // One or more empty lines between two forms
+    BlankLine,
+    Sequence { open: u8, close: u8, children: Vec<CstNode> },
+    // A reader shorthand like @form, directly followed by its form
+    Prefixed { prefix: u8, form: Box<CstNode> },
+}
+
+impl CstNode {
+    pub fn is_form(&self) -> bool {
+        matches!(self, CstNode::Atom(_) | CstNode::Sequence { .. } | CstNode::Prefixed { .. })
+    }
+}
+
+struct CstReader<'a> {
+    source: &'a [u8],
+    position: usize,
+}
+
+fn closing_delimiter_for(opening_delimiter: u8) -> u8 {
+    match opening_delimiter {
+        b'(' => b')',
+        b'[' => b']',
+        _ => b'}',
+    }
+}
+
+impl CstReader<'_> {
+    // Skips whitespace and returns how many line breaks it went over
+    fn skip_whitespace(&mut self) -> usize {
+        let mut newlines = 0;
+        while let Some(c) = self.source.get(self.position).filter(|c| is_whitespace(c)) {
+            newlines += usize::from(*c == b'\n');
+            self.position += 1;
+        }
+        newlines
+    }
+
+    fn read_comment(&mut self) -> String {
+        let rest = &self.source[self.position..];
+        let len = rest.iter().position(|c| *c == b'\n').unwrap_or(rest.len());
+        self.position += len;
+        String::from_utf8_lossy(&rest[..len]).trim_end().to_string()
+    }
+
+    fn read_atom(&mut self) -> Result<String, ParseError> {
+        let rest = &self.source[self.position..];
+        let (_, unread) = read_literal(rest)?;
+        let len = rest.len() - unread.len();
+        self.position += len;
+        Ok(String::from_utf8_lossy(&rest[..len]).to_string())
+    }
+
+    fn read_sequence(&mut self, open: u8) -> Result<CstNode, ParseError> {
+        self.position += 1;
+        let close = closing_delimiter_for(open);
+        let children = self.read_children(Some(close))?;
+        if open == b'{' && !children.iter().filter(|child| child.is_form()).count().is_multiple_of(2) {
+            return Err(OddNumberOfMapForms);
+        }
+        Ok(CstNode::Sequence { open, close, children })
+    }
+
+    fn read_prefixed(&mut self, prefix: u8) -> Result<CstNode, ParseError> {
+        self.position += 1;
+        let form = match self.source.get(self.position) {
+            Some(&open @ (b'(' | b'[' | b'{')) => self.read_sequence(open)?,
+            Some(&b'@') => self.read_prefixed(b'@')?,
+            Some(c) if !is_whitespace(c) && !b")]};".contains(c) => CstNode::Atom(self.read_atom()?),
+            _ => return Err(CannotParseEmpty),
+        };
+        Ok(CstNode::Prefixed { prefix, form: Box::new(form) })
+    }
+
+    // Reads up to the closing delimiter, or the end of the source for the top level
+    fn read_children(&mut self, closing_delimiter: Option<u8>) -> Result<Vec<CstNode>, ParseError> {
+        let mut children = vec![];
+        loop {
+            let newlines = self.skip_whitespace();
+            let Some(&next) = self.source.get(self.position) else {
+                return match closing_delimiter {
+                    None => Ok(children),
+                    Some(b')') => Err(MissingRightParenthesis),
+                    Some(closing_delimiter) => Err(MissingClosingDelimiter(closing_delimiter.into())),
+                };
+            };
+            if Some(next) == closing_delimiter {
+                self.position += 1;
+                return Ok(children);
+            }
+            if newlines >= 2 && !children.is_empty() {
+                children.push(CstNode::BlankLine);
+            }
+            let child = match next {
+                b')' | b']' | b'}' => return Err(match closing_delimiter {
+                    Some(closing_delimiter) => MismatchedDelimiter(closing_delimiter.into(), next.into()),
+                    None if next == b')' => MissingLeftParenthesis,
+                    None => UnexpectedClosingDelimiter(next.into()),
+                }),
+                b';' => {
+                    let trailing = newlines == 0 && children.last().is_some_and(CstNode::is_form);
+                    CstNode::Comment { text: self.read_comment(), trailing }
+                }
+                b'(' | b'[' | b'{' => self.read_sequence(next)?,
+                b'@' => self.read_prefixed(next)?,
+                _ => CstNode::Atom(self.read_atom()?),
+            };
+            children.push(child);
+        }
+    }
+}
+
+// Reads every top level form, comment and blank line of a source file
+pub fn parse_cst(source: &[u8]) -> Result<Vec<CstNode>, ParseError> {
+    // Checked up front so nothing gets replaced when the text is laid out again
+    from_utf8(source).map_err(|_| InvalidUtf8)?;
+    CstReader { source, position: 0 }.read_children(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::assert_matches;
+
+    use super::*;
+
+    fn atom(text: &str) -> CstNode {
+        CstNode::Atom(text.into())
+    }
+
+    #[test]
+    fn comments_and_blank_lines_are_kept() {
+        let nodes = parse_cst(b"; header\n(def x 1) ; one\n\n\n[\"a b\" #\"\\d\"]").unwrap();
+        assert_eq!(nodes, vec![
+            CstNode::Comment { text: "; header".into(), trailing: false },
+            CstNode::Sequence { open: b'(', close: b')', children: vec![atom("def"), atom("x"), atom("1")] },
+            CstNode::Comment { text: "; one".into(), trailing: true },
+            CstNode::BlankLine,
+            CstNode::Sequence { open: b'[', close: b']', children: vec![atom("\"a b\""), atom("#\"\\d\"")] },
+        ]);
+        assert_eq!(parse_cst(b"@(f @x)").unwrap(), vec![CstNode::Prefixed {
+            prefix: b'@',
+            form: Box::new(CstNode::Sequence { open: b'(', close: b')', children: vec![atom("f"), CstNode::Prefixed { prefix: b'@', form: Box::new(atom("x")) }] }),
+        }]);
+    }
+
+    #[test]
+    fn literals_follow_the_reader_rules() {
+        assert_eq!(parse_cst(b"(f 0x1F :k)").unwrap()[0], CstNode::Sequence { open: b'(', close: b')', children: vec![atom("f"), atom("0x1F"), atom(":k")] });
+        assert_matches!(parse_cst(b"(f x)y)"), Err(ParseError::ForbiddenCharInSymbol(')')));
+        assert_matches!(parse_cst(b"(f [x)"), Err(MismatchedDelimiter(']', ')')));
+        assert_matches!(parse_cst(b"{:a}"), Err(OddNumberOfMapForms));
+        assert_matches!(parse_cst(b"; caf\xe9\n(f x)"), Err(InvalidUtf8));
+        assert_matches!(parse_cst(b"(f \"x"), Err(ParseError::MissingDoubleQuote));
+    }
+}
@@ -1,78 +1,343 @@
-use std::cell::RefCell;
+use std::any::TypeId;
+use std::cell::{Cell, RefCell};
 use std::collections::HashMap;
-use std::hash::Hash;
-use std::str::from_utf8;
+use std::cmp::Ordering;
+use std::fmt::{Display, Formatter};
+use std::io;
+use std::io::Write;
+use std::rc::{Rc, Weak};
 
 use thiserror::Error;
 
-use crate::eval::EvalError::{InvalidArguments, UnableToEvalFunction};
-use crate::tokenize::{AstNode, Value};
-use crate::tokenize::AstNode::{List, Num, Str, Sym};
+use crate::{atom, collections, condition, exception, format, host, lazy_seq, loader, pattern, pretty, sequences, strings, suggestions};
+use crate::backtrace::{StackFrame, TracedError};
+use crate::condition::Handler;
+use crate::convert::NativeFunction;
+use crate::lazy_seq::{first_and_rest, LazySeq};
+use crate::namespace::{Namespaces, CORE};
+use crate::eval::EvalError::{InvalidArguments, NotAFunction, RestartInvoked, UnableToEvalFunction, UnboundSymbol, WrongArity, WrongType};
+use crate::symbol::Symbol;
+use crate::tokenize::{AstNode, format_radix};
+use crate::tokenize::AstNode::{Keyword, List, Map, Num, Regex, Str, Sym, Vector};
+use crate::value::Value;
 
-struct LispFn(Box<dyn Fn(&[Value]) -> Result<Value, EvalError>>);
+type BuiltinFn = dyn Fn(&[Value]) -> Result<Value, EvalError>;
+
+pub struct LispFn {
+    function: Box<BuiltinFn>,
+    // Known for closures, so a call by name can report a wrong number of arguments under that name
+    arity: Option<Arity>,
+}
 
 impl LispFn {
-    fn call(&self, arguments: &[Value]) -> Result<Value, EvalError> {
-        self.0(arguments)
+    pub fn call(&self, arguments: &[Value]) -> Result<Value, EvalError> {
+        (self.function)(arguments)
+    }
+
+    fn with_arity(self, arity: Arity) -> LispFn {
+        LispFn { arity: Some(arity), ..self }
+    }
+
+    // Called as (name arguments...)
+    fn call_as(&self, name: Symbol, arguments: &[Value]) -> Result<Value, EvalError> {
+        match self.arity {
+            Some(arity) if !arity.accepts(arguments.len()) => Err(EvalError::wrong_arity(name.name(), arity, arguments.len())),
+            _ => self.call(arguments),
+        }
     }
 }
 
 impl<F> From<F> for LispFn
     where F: Fn(&[Value]) -> Result<Value, EvalError> + 'static {
     fn from(value: F) -> Self {
-        Self {
-            0: Box::new(value)
+        LispFn { function: Box::new(value), arity: None }
+    }
+}
+
+// How many arguments a function takes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Arity {
+    Exactly(usize),
+    AtLeast(usize),
+    Between(usize, usize),
+    // Some leading arguments followed by keys and values, like assoc and hash-map
+    Pairs { leading: usize },
+}
+
+impl Arity {
+    pub fn accepts(self, count: usize) -> bool {
+        match self {
+            Arity::Exactly(expected) => count == expected,
+            Arity::AtLeast(low) => count >= low,
+            Arity::Between(low, high) => (low..=high).contains(&count),
+            Arity::Pairs { leading } => count >= leading && (count - leading).is_multiple_of(2),
         }
     }
 }
 
-#[derive(Error, Debug)]
+impl Display for Arity {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let last = match *self {
+            Arity::Pairs { leading: 0 } => return write!(f, "an even number of arguments"),
+            Arity::Pairs { leading } => {
+                return write!(f, "{leading} argument{} followed by keys and values", if leading == 1 { "" } else { "s" });
+            }
+            Arity::Exactly(count) => { write!(f, "{count}")?; count }
+            Arity::AtLeast(count) => { write!(f, "at least {count}")?; count }
+            Arity::Between(low, high) if low + 1 == high => { write!(f, "{low} or {high}")?; high }
+            Arity::Between(low, high) => { write!(f, "{low} to {high}")?; high }
+        };
+        write!(f, " argument{}", if last == 1 { "" } else { "s" })
+    }
+}
+
+fn joined_path(path: &[Value]) -> String {
+    path.iter().map(Value::to_string).collect::<Vec<_>>().join(" ")
+}
+
+fn did_you_mean(suggestions: &[String]) -> String {
+    if suggestions.is_empty() {
+        return String::new();
+    }
+    format!(", did you mean {}?", suggestions.join(", "))
+}
+
+#[derive(Error, Debug, Clone)]
 pub enum EvalError {
-    #[error("there are no available functions with name: {0}")]
-    UnableToEvalFunction(String),
+    #[error("there are no available functions with name: {name}{}", did_you_mean(.suggestions))]
+    UnableToEvalFunction { name: String, suggestions: Vec<String> },
     #[error("cannot evaluate empty list")]
     CannotEvaluateEmptyList,
     #[error("cannot evaluate non-symbol")]
     CannotEvaluateNonSymbol,
     #[error("invalid arguments: {0}")]
     InvalidArguments(String),
+    #[error("wrong number of arguments: {function} expects {expected}, got {actual}")]
+    WrongArity { function: String, expected: Arity, actual: usize },
+    #[error("wrong type of argument: {function} expects {expected}, got {} {actual}", .actual.type_name())]
+    WrongType { function: String, expected: &'static str, actual: Value },
+    #[error("integer overflow: the result of {function} doesn't fit in a number")]
+    Overflow { function: String },
+    // Raised when an argument is converted to a Rust type, the path is the keys to the wrong value
+    #[error("wrong type of argument: {function} expects {expected} at {}, got {} {actual}", joined_path(.path), .actual.type_name())]
+    WrongFieldType { function: String, path: Box<[Value]>, expected: &'static str, actual: Value },
+    #[error("missing field: {function} expects {expected} at {}", joined_path(.path))]
+    MissingField { function: String, path: Box<[Value]>, expected: &'static str },
+    #[error("unable to resolve symbol: {name}{}", did_you_mean(.suggestions))]
+    UnboundSymbol { name: String, suggestions: Vec<String> },
+    #[error("{0} is not a function")]
+    NotAFunction(String),
+    #[error("the namespace this function was defined in no longer exists")]
+    NamespaceDropped,
+    // Raised by throw, catch* gets the value back as it was thrown
+    #[error("uncaught exception: {0}")]
+    Thrown(Value),
+    // Unwinds from invoke-restart to the restart-case that established the restart, nothing catches it on the way
+    #[error("the restart :{name} was invoked outside of its restart-case")]
+    RestartInvoked { id: usize, name: String, arguments: Vec<Value> },
+    #[error("cannot load {path}: {reason}")]
+    CannotLoad { path: String, reason: String },
+    #[error("cannot find module {name}, looked for it at: {}", .searched.join(", "))]
+    ModuleNotFound { name: String, searched: Vec<String> },
+    #[error("circular require: {}", .cycle.join(" -> "))]
+    CircularRequire { cycle: Vec<String> },
+    #[error("no namespace named {0}")]
+    UnknownNamespace(String),
+    #[error("{name} is private to namespace {namespace}")]
+    PrivateSymbol { name: String, namespace: String },
+    #[error("{type_name} has no method {method}")]
+    NoSuchMethod { type_name: String, method: String },
+    // Returned by a function registered from Rust
+    #[error("{0}")]
+    Host(String),
+}
+
+impl From<String> for EvalError {
+    fn from(message: String) -> Self {
+        EvalError::Host(message)
+    }
+}
+
+impl From<&str> for EvalError {
+    fn from(message: &str) -> Self {
+        EvalError::Host(message.into())
+    }
+}
+
+impl EvalError {
+    pub fn wrong_arity(function: &str, expected: Arity, actual: usize) -> EvalError {
+        WrongArity { function: function.into(), expected, actual }
+    }
+
+    pub fn wrong_type(function: &str, expected: &'static str, actual: &Value) -> EvalError {
+        WrongType { function: function.into(), expected, actual: actual.clone() }
+    }
+}
+
+// Calls a function value with already evaluated arguments
+pub fn apply(function: &Value, arguments: &[Value]) -> Result<Value, EvalError> {
+    match function {
+        Value::Fn(function) => function.call(arguments),
+        other => Err(NotAFunction(other.to_string())),
+    }
+}
+
+// Like apply, for calls that name the function they call
+fn apply_named(name: Symbol, function: &Value, arguments: &[Value]) -> Result<Value, EvalError> {
+    match function {
+        Value::Fn(function) => function.call_as(name, arguments),
+        other => Err(NotAFunction(other.to_string())),
+    }
+}
+
+fn ensure_all_nums(function_name: &str, arguments: &[Value]) -> Result<Box<[isize]>, EvalError> {
+    arguments.iter().map(|argument| argument.num().ok_or_else(|| EvalError::wrong_type(function_name, "a number", argument)))
+        .collect()
 }
 
-fn ensure_all_nums(arguments: &[Value]) -> Result<Box<[isize]>, EvalError> {
-    arguments.iter().map(Value::num).collect::<Option<Box<[isize]>>>()
-        .ok_or_else(|| EvalError::InvalidArguments("Unexpected non number".into()))
+// Folds the numbers with checked arithmetic, a result that doesn't fit is an error rather than a panic
+fn fold_nums(function_name: &str, initial: isize, nums: &[isize], operation: fn(isize, isize) -> Option<isize>) -> Result<Value, EvalError> {
+    nums.iter().try_fold(initial, |accumulator, num| operation(accumulator, *num))
+        .map(Value::Num)
+        .ok_or_else(|| EvalError::Overflow { function: function_name.into() })
 }
 
 fn lisp_plus(arguments: &[Value]) -> Result<Value, EvalError> {
-    let nums = ensure_all_nums(arguments)?;
-    Ok(Value::Num(nums.into_iter().sum::<isize>()))
+    let nums = ensure_all_nums("+", arguments)?;
+    fold_nums("+", 0, &nums, isize::checked_add)
 }
 
 fn lisp_mul(arguments: &[Value]) -> Result<Value, EvalError> {
-    let nums = ensure_all_nums(arguments)?;
-    Ok(Value::Num(nums.into_iter().product()))
+    let nums = ensure_all_nums("*", arguments)?;
+    fold_nums("*", 1, &nums, isize::checked_mul)
 }
 
 fn lisp_sub(arguments: &[Value]) -> Result<Value, EvalError> {
-    let nums = ensure_all_nums(arguments)?;
-    let mut nums_iter = nums.into_iter();
-    let Some(first) = nums_iter.next() else {
+    let nums = ensure_all_nums("-", arguments)?;
+    let Some((first, rest)) = nums.split_first() else {
         return Ok(Value::Num(0));
     };
-    Ok(Value::Num(first - nums_iter.sum::<isize>()))
+    fold_nums("-", *first, rest, isize::checked_sub)
+}
+
+fn lisp_equals(arguments: &[Value]) -> Result<Value, EvalError> {
+    Ok(Value::Bool(arguments.windows(2).all(|pair| pair[0] == pair[1])))
+}
+
+fn compare(left: &Value, right: &Value) -> Result<Ordering, EvalError> {
+    left.partial_cmp(right)
+        .ok_or_else(|| InvalidArguments(format!("cannot compare {} with {}", left.type_name(), right.type_name())))
+}
+
+fn lisp_ordered(name: &str, arguments: &[Value], holds: fn(Ordering) -> bool) -> Result<Value, EvalError> {
+    if arguments.is_empty() {
+        return Err(EvalError::wrong_arity(name, Arity::AtLeast(1), 0));
+    }
+    for pair in arguments.windows(2) {
+        if !holds(compare(&pair[0], &pair[1])?) {
+            return Ok(Value::Bool(false));
+        }
+    }
+    Ok(Value::Bool(true))
+}
+
+fn lisp_compare(arguments: &[Value]) -> Result<Value, EvalError> {
+    let [left, right] = arguments else {
+        return Err(EvalError::wrong_arity("compare", Arity::Exactly(2), arguments.len()));
+    };
+    Ok(Value::Num(compare(left, right)? as isize))
+}
+
+fn lisp_not(arguments: &[Value]) -> Result<Value, EvalError> {
+    let [value] = arguments else {
+        return Err(EvalError::wrong_arity("not", Arity::Exactly(1), arguments.len()));
+    };
+    Ok(Value::Bool(!value.is_truthy()))
+}
+
+fn lisp_format_radix(arguments: &[Value]) -> Result<Value, EvalError> {
+    let [number, radix] = *ensure_all_nums("format-radix", arguments)? else {
+        return Err(EvalError::wrong_arity("format-radix", Arity::Exactly(2), arguments.len()));
+    };
+    let radix = u32::try_from(radix).ok().and_then(|radix| format_radix(number, radix))
+        .ok_or_else(|| InvalidArguments(format!("unsupported radix {radix}, expected one of 2, 8, 10 or 16")))?;
+    Ok(Value::Str(radix.into()))
+}
+
+fn lisp_list(arguments: &[Value]) -> Result<Value, EvalError> {
+    Ok(Value::List(arguments.into()))
 }
 
+struct NamespaceState {
+    namespaces: RefCell<Namespaces>,
+    // The calls the error being raised has gone through so far, innermost first
+    backtrace: RefCell<Vec<StackFrame>>,
+    // Whether an error is on its way out. It is signalled once where it is raised, and is let go of
+    // as soon as an evaluation ends well, since whatever raised it inside was caught or dropped
+    raising: Cell<bool>,
+    // The handlers of the handler-bind and try* forms being evaluated, innermost last
+    handlers: RefCell<Vec<Handler>>,
+    // The restarts of the restart-case forms being evaluated as (id, name), innermost last
+    restarts: RefCell<Vec<(usize, Symbol)>>,
+    next_restart_id: Cell<usize>,
+    // Where print, println and friends write to, the process's stdout and stderr unless an embedder
+    // redirects them
+    stdout: RefCell<Box<dyn Write>>,
+    stderr: RefCell<Box<dyn Write>>,
+    // Methods of host objects by the Rust type they are for and their name
+    methods: RefCell<HashMap<(TypeId, Symbol), Value>>,
+}
+
+// A cheap handle to the global bindings, closures keep a weak one so they can call back into the
+// namespace they were defined in without keeping it alive forever
+#[derive(Clone)]
 pub struct GlobalNamespace {
-    functions: RefCell<HashMap<Box<[u8]>, LispFn>>,
+    state: Rc<NamespaceState>,
+}
+
+#[derive(Clone)]
+pub struct WeakNamespace {
+    state: Weak<NamespaceState>,
+}
+
+impl WeakNamespace {
+    pub fn upgrade(&self) -> Result<GlobalNamespace, EvalError> {
+        self.state.upgrade()
+            .map(|state| GlobalNamespace { state })
+            .ok_or(EvalError::NamespaceDropped)
+    }
 }
 
 impl Default for GlobalNamespace {
     fn default() -> Self {
-        // Create a global namespace with all the primitives
+        // Create a global namespace with all the primitives in core, then start out in user
         let mut namespace = GlobalNamespace::empty();
+        let user = namespace.current_namespace();
+        namespace.in_namespace(Symbol::intern(CORE));
         namespace.defn(b"+", lisp_plus.into());
         namespace.defn(b"-", lisp_sub.into());
         namespace.defn(b"*", lisp_mul.into());
+        namespace.defn(b"=", lisp_equals.into());
+        namespace.defn(b"<", (|arguments: &[Value]| lisp_ordered("<", arguments, Ordering::is_lt)).into());
+        namespace.defn(b">", (|arguments: &[Value]| lisp_ordered(">", arguments, Ordering::is_gt)).into());
+        namespace.defn(b"<=", (|arguments: &[Value]| lisp_ordered("<=", arguments, Ordering::is_le)).into());
+        namespace.defn(b">=", (|arguments: &[Value]| lisp_ordered(">=", arguments, Ordering::is_ge)).into());
+        namespace.defn(b"compare", lisp_compare.into());
+        namespace.defn(b"not", lisp_not.into());
+        namespace.defn(b"format-radix", lisp_format_radix.into());
+        namespace.defn(b"list", lisp_list.into());
+        collections::define_builtins(&mut namespace);
+        sequences::define_builtins(&mut namespace);
+        lazy_seq::define_builtins(&mut namespace);
+        strings::define_builtins(&mut namespace);
+        pattern::define_builtins(&mut namespace);
+        format::define_builtins(&mut namespace);
+        pretty::define_builtins(&mut namespace);
+        exception::define_builtins(&mut namespace);
+        condition::define_builtins(&mut namespace);
+        atom::define_builtins(&mut namespace);
+        loader::define_builtins(&mut namespace);
+        namespace.in_namespace(user);
         namespace
     }
 }
@@ -80,41 +345,615 @@ impl Default for GlobalNamespace {
 impl GlobalNamespace {
     pub fn empty() -> GlobalNamespace {
         GlobalNamespace {
-            functions: RefCell::new(HashMap::new())
+            state: Rc::new(NamespaceState {
+                namespaces: RefCell::new(Namespaces::default()),
+                backtrace: RefCell::new(vec![]),
+                raising: Cell::new(false),
+                handlers: RefCell::new(vec![]),
+                restarts: RefCell::new(vec![]),
+                next_restart_id: Cell::new(0),
+                stdout: RefCell::new(Box::new(io::stdout())),
+                stderr: RefCell::new(Box::new(io::stderr())),
+                methods: RefCell::new(HashMap::new()),
+            })
         }
     }
     pub fn new() -> GlobalNamespace {
         Self::default()
     }
 
+    pub fn downgrade(&self) -> WeakNamespace {
+        WeakNamespace { state: Rc::downgrade(&self.state) }
+    }
+
     pub fn defn(&mut self, key: &[u8], function: LispFn) {
-        let new_key = key.iter().cloned().collect();
-        self.functions.borrow_mut().insert(new_key, function);
+        self.def(key, Value::Fn(function.into()));
+    }
+
+    // Registers a plain Rust function or closure, its arguments and result are converted with
+    // FromValue and IntoValue
+    pub fn register<Args>(&mut self, name: &str, function: impl NativeFunction<Args>) {
+        self.defn(name.as_bytes(), function.into_lisp_fn(name));
+    }
+
+    // Makes (.name object arguments...) call the function for host objects holding a T, the object
+    // is passed as the first argument
+    pub fn register_method<T: 'static, Args>(&mut self, name: &str, method: impl NativeFunction<Args>) {
+        let function = Value::Fn(Rc::new(method.into_lisp_fn(&format!(".{name}"))));
+        self.state.methods.borrow_mut().insert((TypeId::of::<T>(), Symbol::intern(name.as_bytes())), function);
+    }
+
+    pub(crate) fn method(&self, object_type: TypeId, name: Symbol) -> Option<Value> {
+        self.state.methods.borrow().get(&(object_type, name)).cloned()
+    }
+
+    pub fn def(&mut self, key: &[u8], value: Value) {
+        self.bind(Symbol::intern(key), value);
+    }
+
+    // Definitions go into the current namespace
+    pub fn bind(&mut self, key: Symbol, value: Value) {
+        self.state.namespaces.borrow_mut().define(key, value, false);
+    }
+
+    pub fn bind_private(&mut self, key: Symbol, value: Value) {
+        self.state.namespaces.borrow_mut().define(key, value, true);
+    }
+
+    pub fn get(&self, key: Symbol) -> Option<Value> {
+        self.resolve(key).ok().flatten()
+    }
+
+    // Like get, but says why a qualified symbol can't be used from the current namespace
+    pub fn resolve(&self, key: Symbol) -> Result<Option<Value>, EvalError> {
+        self.state.namespaces.borrow().resolve(key)
+    }
+
+    pub fn current_namespace(&self) -> Symbol {
+        self.state.namespaces.borrow().current
+    }
+
+    // Switches to the namespace, creating it if there is none with that name yet
+    pub fn in_namespace(&mut self, name: Symbol) {
+        self.state.namespaces.borrow_mut().switch(name);
+    }
+
+    pub fn set_exports(&mut self, exports: impl IntoIterator<Item=Symbol>) {
+        self.state.namespaces.borrow_mut().set_exports(exports);
+    }
+
+    // Makes alias/x refer to x in the target namespace, from the current namespace
+    pub fn add_alias(&mut self, alias: Symbol, target: Symbol) {
+        self.state.namespaces.borrow_mut().add_alias(alias, target);
+    }
+
+    // Runs f with the namespace current, and switches back to the previous one afterwards
+    pub fn within<R>(&mut self, name: Symbol, f: impl FnOnce(&mut GlobalNamespace) -> R) -> R {
+        let previous = self.current_namespace();
+        self.in_namespace(name);
+        let result = f(self);
+        self.in_namespace(previous);
+        result
+    }
+
+    fn push_frame(&self, frame: StackFrame) {
+        self.state.backtrace.borrow_mut().push(frame);
+    }
+
+    pub fn take_backtrace(&self) -> Vec<StackFrame> {
+        self.state.raising.set(false);
+        self.state.backtrace.take()
+    }
+
+    // An error is signalled where it is raised, before anything unwinds, and then passes through the
+    // forms around it as it is. A handler invoking a restart replaces it
+    fn raise(&self, error: EvalError) -> EvalError {
+        if self.state.raising.get() {
+            return error;
+        }
+        let result = match error {
+            RestartInvoked { .. } => Ok(()),
+            _ => self.signal(&exception::error_value(&error)),
+        };
+        self.state.raising.set(true);
+        result.err().unwrap_or(error)
+    }
+
+    // Rust code like = realising a lazy sequence can drop an error, whatever it raised and the calls
+    // it went through are done with once the form around it has a value
+    fn settle(&self) {
+        if self.state.raising.get() {
+            self.take_backtrace();
+        }
+    }
+
+    // Returns how many handlers there were before, to truncate back to once the form is done
+    fn push_handlers(&self, handlers: impl IntoIterator<Item=Handler>) -> usize {
+        let mut stack = self.state.handlers.borrow_mut();
+        let previous_len = stack.len();
+        stack.extend(handlers);
+        previous_len
+    }
+
+    fn truncate_handlers(&self, len: usize) {
+        self.state.handlers.borrow_mut().truncate(len);
+    }
+
+    // Calls the matching handlers, innermost first, while whatever signalled is still on the stack. A
+    // handler that returns declines, to recover it has to leave through invoke-restart or throw. A
+    // try* with a catch* ends the search, since it is going to unwind to its catch* anyway
+    pub(crate) fn signal(&self, condition: &Value) -> Result<(), EvalError> {
+        let mut index = self.state.handlers.borrow().len();
+        while index > 0 {
+            index -= 1;
+            let handler = self.state.handlers.borrow()[index].clone();
+            let Handler::Bind { matcher, function, cluster_start } = handler else {
+                return Ok(());
+            };
+            if !condition::matches(&matcher, condition)? {
+                continue;
+            }
+            // While a handler runs, its own handler-bind and everything inside it is switched off, so
+            // it can signal again without ending up in itself
+            let hidden = self.state.handlers.borrow_mut().split_off(cluster_start);
+            let result = apply(&function, std::slice::from_ref(condition));
+            self.state.handlers.borrow_mut().extend(hidden);
+            result?;
+        }
+        Ok(())
+    }
+
+    // Returns the id of the first restart, the others follow in order
+    fn push_restarts(&self, names: impl IntoIterator<Item=Symbol>) -> usize {
+        let first_id = self.state.next_restart_id.get();
+        let mut restarts = self.state.restarts.borrow_mut();
+        let len = restarts.len();
+        restarts.extend(names.into_iter().enumerate().map(|(index, name)| (first_id + index, name)));
+        self.state.next_restart_id.set(first_id + restarts.len() - len);
+        first_id
+    }
+
+    fn pop_restarts(&self, count: usize) {
+        let mut restarts = self.state.restarts.borrow_mut();
+        let len = restarts.len();
+        restarts.truncate(len - count);
+    }
+
+    pub(crate) fn find_restart(&self, name: Symbol) -> Option<usize> {
+        self.state.restarts.borrow().iter().rev().find(|(_, restart)| *restart == name).map(|(id, _)| *id)
+    }
+
+    pub fn set_stdout(&mut self, sink: impl Write + 'static) {
+        self.state.stdout.replace(Box::new(sink));
+    }
+
+    pub fn set_stderr(&mut self, sink: impl Write + 'static) {
+        self.state.stderr.replace(Box::new(sink));
+    }
+
+    pub(crate) fn write_stdout(&self, text: &str) -> Result<(), EvalError> {
+        let mut stdout = self.state.stdout.borrow_mut();
+        stdout.write_all(text.as_bytes()).and_then(|_| stdout.flush()).map_err(|error| EvalError::Host(format!("cannot write to stdout: {error}")))
+    }
+
+    pub(crate) fn write_stderr(&self, text: &str) -> Result<(), EvalError> {
+        let mut stderr = self.state.stderr.borrow_mut();
+        stderr.write_all(text.as_bytes()).and_then(|_| stderr.flush()).map_err(|error| EvalError::Host(format!("cannot write to stderr: {error}")))
+    }
+
+    // The names usable without qualifying them: those of the current namespace and of core
+    pub fn names(&self) -> Vec<Symbol> {
+        self.state.namespaces.borrow().visible_names()
+    }
+
+    pub fn eval(&mut self, key: Symbol, arguments: Vec<Value>) -> Result<Value, EvalError> {
+        if let Some(function) = self.resolve(key)? {
+            return apply_named(key, &function, &arguments);
+        }
+        if let Some(method) = key.name().strip_prefix('.').filter(|method| !method.is_empty()) {
+            return host::call_method(self, method, &arguments);
+        }
+        Err(UnableToEvalFunction {
+            name: key.to_string(),
+            suggestions: suggestions::similar_names(key.name(), self.names()),
+        })
+    }
+}
+
+struct Frame {
+    symbol: Symbol,
+    value: Value,
+    parent: Scope,
+}
+
+// Local bindings introduced by fn parameters and let, as a persistent linked list of frames so
+// closures can capture the scope they were created in for free
+#[derive(Clone, Default)]
+pub struct Scope(Option<Rc<Frame>>);
+
+impl Scope {
+    pub fn bind(&self, symbol: Symbol, value: Value) -> Scope {
+        Scope(Some(Rc::new(Frame { symbol, value, parent: self.clone() })))
+    }
+
+    pub fn lookup(&self, symbol: Symbol) -> Option<&Value> {
+        let mut scope = self;
+        while let Some(frame) = &scope.0 {
+            if frame.symbol == symbol {
+                return Some(&frame.value);
+            }
+            scope = &frame.parent;
+        }
+        None
+    }
+
+    fn symbols(&self) -> impl Iterator<Item=Symbol> + '_ {
+        std::iter::successors(self.0.as_deref(), |frame| frame.parent.0.as_deref()).map(|frame| frame.symbol)
+    }
+}
+
+fn expect_symbol(form_name: &str, node: &AstNode) -> Result<Symbol, EvalError> {
+    match node {
+        Sym(symbol) => Ok(*symbol),
+        other => Err(InvalidArguments(format!("{form_name} expects a symbol, got {other}"))),
+    }
+}
+
+struct Parameters {
+    required: Vec<Symbol>,
+    rest: Option<Symbol>,
+}
+
+impl Parameters {
+    // Parses [a b & more] parameter vectors
+    fn parse(node: &AstNode) -> Result<Parameters, EvalError> {
+        let Vector(nodes) = node else {
+            return Err(InvalidArguments(format!("fn expects a parameter vector, got {node}")));
+        };
+        let symbols = nodes.iter().map(|node| expect_symbol("fn", node)).collect::<Result<Vec<Symbol>, EvalError>>()?;
+        match symbols.iter().position(|symbol| *symbol == Symbol::AMPERSAND) {
+            None => Ok(Parameters { required: symbols, rest: None }),
+            Some(position) if position + 2 == symbols.len() => Ok(Parameters {
+                required: symbols[..position].to_vec(),
+                rest: Some(symbols[position + 1]),
+            }),
+            Some(_) => Err(InvalidArguments("fn expects exactly one parameter after &".into())),
+        }
+    }
+
+    fn arity(&self) -> Arity {
+        match self.rest {
+            Some(_) => Arity::AtLeast(self.required.len()),
+            None => Arity::Exactly(self.required.len()),
+        }
+    }
+
+    fn bind(&self, scope: &Scope, arguments: &[Value]) -> Result<Scope, EvalError> {
+        if !self.arity().accepts(arguments.len()) {
+            // Only anonymous calls get here, calls by name are checked before
+            return Err(EvalError::wrong_arity("fn", self.arity(), arguments.len()));
+        }
+        let (required_arguments, rest_arguments) = arguments.split_at(self.required.len());
+        let scope = self.required.iter().zip(required_arguments)
+            .fold(scope.clone(), |scope, (symbol, value)| scope.bind(*symbol, value.clone()));
+        Ok(match self.rest {
+            Some(rest) => scope.bind(rest, Value::List(rest_arguments.into())),
+            None => scope,
+        })
+    }
+}
+
+fn eval_body(body: &[AstNode], scope: &Scope, global_namespace: &mut GlobalNamespace) -> Result<Value, EvalError> {
+    body.iter().try_fold(Value::Nil, |_, node| eval_in(node, scope, global_namespace))
+}
+
+// def- is def for names only the namespace itself can use
+fn eval_def(arguments: &[AstNode], scope: &Scope, global_namespace: &mut GlobalNamespace, private: bool) -> Result<Value, EvalError> {
+    let form_name = if private { "def-" } else { "def" };
+    let [name, value] = arguments else {
+        return Err(InvalidArguments(format!("{form_name} expects a name and a value")));
+    };
+    let name = expect_symbol(form_name, name)?;
+    let value = eval_in(value, scope, global_namespace)?;
+    if private {
+        global_namespace.bind_private(name, value.clone());
+    } else {
+        global_namespace.bind(name, value.clone());
+    }
+    Ok(value)
+}
+
+// (ns name (:export a b)) switches to the namespace, and with :export only a and b are public
+fn eval_ns(arguments: &[AstNode], global_namespace: &mut GlobalNamespace) -> Result<Value, EvalError> {
+    let Some((name, clauses)) = arguments.split_first() else {
+        return Err(InvalidArguments("ns expects a name".into()));
+    };
+    let name = expect_symbol("ns", name)?;
+    let mut exports = None;
+    for clause in clauses {
+        match clause {
+            List(clause, _) if clause.first() == Some(&Keyword(Symbol::intern(b"export"))) => {
+                let names = clause[1..].iter().map(|name| expect_symbol("ns :export", name)).collect::<Result<Vec<_>, _>>()?;
+                exports.get_or_insert_with(Vec::new).extend(names);
+            }
+            other => return Err(InvalidArguments(format!("ns expects (:export names...) clauses, got {other}"))),
+        }
     }
+    global_namespace.in_namespace(name);
+    if let Some(exports) = exports {
+        global_namespace.set_exports(exports);
+    }
+    Ok(Value::Nil)
+}
+
+fn eval_in_ns(arguments: &[AstNode], global_namespace: &mut GlobalNamespace) -> Result<Value, EvalError> {
+    let [name] = arguments else {
+        return Err(InvalidArguments("in-ns expects a namespace name".into()));
+    };
+    global_namespace.in_namespace(expect_symbol("in-ns", name)?);
+    Ok(Value::Nil)
+}
+
+fn eval_fn(arguments: &[AstNode], scope: &Scope, global_namespace: &mut GlobalNamespace) -> Result<Value, EvalError> {
+    let Some((parameters, body)) = arguments.split_first() else {
+        return Err(InvalidArguments("fn expects a parameter vector and a body".into()));
+    };
+    let parameters = Parameters::parse(parameters)?;
+    let arity = parameters.arity();
+    let body: Rc<[AstNode]> = body.into();
+    let captured_scope = scope.clone();
+    let namespace = global_namespace.downgrade();
+    // Unqualified names in the body keep referring to the namespace the function was defined in
+    let defined_in = global_namespace.current_namespace();
+    let closure = move |arguments: &[Value]| {
+        let mut global_namespace = namespace.upgrade()?;
+        let scope = parameters.bind(&captured_scope, arguments)?;
+        global_namespace.within(defined_in, |global_namespace| eval_body(&body, &scope, global_namespace))
+    };
+    Ok(Value::Fn(Rc::new(LispFn::from(closure).with_arity(arity))))
+}
+
+fn eval_let(arguments: &[AstNode], scope: &Scope, global_namespace: &mut GlobalNamespace) -> Result<Value, EvalError> {
+    let Some((Vector(bindings), body)) = arguments.split_first() else {
+        return Err(InvalidArguments("let expects a binding vector and a body".into()));
+    };
+    if !bindings.len().is_multiple_of(2) {
+        return Err(InvalidArguments("let expects an even number of forms in its binding vector".into()));
+    }
+    // Every binding can already see the ones before it
+    let scope = bindings.chunks_exact(2).try_fold(scope.clone(), |scope, binding| {
+        let symbol = expect_symbol("let", &binding[0])?;
+        let value = eval_in(&binding[1], &scope, global_namespace)?;
+        Ok::<Scope, EvalError>(scope.bind(symbol, value))
+    })?;
+    eval_body(body, &scope, global_namespace)
+}
+
+fn eval_if(arguments: &[AstNode], scope: &Scope, global_namespace: &mut GlobalNamespace) -> Result<Value, EvalError> {
+    let (condition, then_branch, else_branch) = match arguments {
+        [condition, then_branch] => (condition, then_branch, None),
+        [condition, then_branch, else_branch] => (condition, then_branch, Some(else_branch)),
+        _ => return Err(InvalidArguments("if expects a condition, a then branch and an optional else branch".into())),
+    };
+    if eval_in(condition, scope, global_namespace)?.is_truthy() {
+        eval_in(then_branch, scope, global_namespace)
+    } else if let Some(else_branch) = else_branch {
+        eval_in(else_branch, scope, global_namespace)
+    } else {
+        Ok(Value::Nil)
+    }
+}
+
+// Finds a trailing (catch* e handler...) or (finally cleanup...) clause of try*
+fn try_clause(node: &AstNode, head: Symbol) -> Option<&[AstNode]> {
+    match node {
+        List(nodes, _) => match nodes.split_first() {
+            Some((Sym(symbol), clause)) if *symbol == head => Some(clause),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+// (try* body... (catch* e handler...) (finally cleanup...)) where both clauses are optional. The
+// cleanup always runs and its value is dropped, but an error raised by it replaces the result
+fn eval_try(arguments: &[AstNode], scope: &Scope, global_namespace: &mut GlobalNamespace) -> Result<Value, EvalError> {
+    let (mut body, mut catch, mut finally) = (arguments, None, None);
+    if let Some((last, rest)) = body.split_last() {
+        if let Some(clause) = try_clause(last, Symbol::FINALLY) {
+            (body, finally) = (rest, Some(clause));
+        }
+    }
+    if let Some((last, rest)) = body.split_last() {
+        if let Some(clause) = try_clause(last, Symbol::CATCH) {
+            (body, catch) = (rest, Some(clause));
+        }
+    }
+    if body.iter().any(|node| try_clause(node, Symbol::CATCH).or(try_clause(node, Symbol::FINALLY)).is_some()) {
+        return Err(InvalidArguments("try* expects at most one catch* and one finally, after its body and in that order".into()));
+    }
+    let catch = catch.map(|clause| match clause.split_first() {
+        Some((name, handler)) => Ok((expect_symbol("catch*", name)?, handler)),
+        None => Err(InvalidArguments("catch* expects a symbol to bind the error to and a body".into())),
+    }).transpose()?;
+
+    let handlers_len = global_namespace.push_handlers(catch.map(|_| Handler::Catch));
+    let result = eval_body(body, scope, global_namespace);
+    global_namespace.truncate_handlers(handlers_len);
+    let result = match (result, catch) {
+        // Restarts go straight through to their restart-case
+        (Err(error @ RestartInvoked { .. }), _) => Err(error),
+        (Err(error), Some((name, handler))) => {
+            // The error was handled, so the calls it went through are no longer of interest
+            global_namespace.take_backtrace();
+            eval_body(handler, &scope.bind(name, exception::error_value(&error)), global_namespace)
+        }
+        (result, _) => result,
+    };
+    if let Some(cleanup) = finally {
+        let raising = global_namespace.state.raising.get();
+        let backtrace = global_namespace.take_backtrace();
+        eval_body(cleanup, scope, global_namespace)?;
+        *global_namespace.state.backtrace.borrow_mut() = backtrace;
+        global_namespace.state.raising.set(raising);
+    }
+    result
+}
+
+// (handler-bind [matcher handler ...] body...) where a matcher is a keyword for the :type of the
+// condition or a predicate, see signal for how the handlers get called
+fn eval_handler_bind(arguments: &[AstNode], scope: &Scope, global_namespace: &mut GlobalNamespace) -> Result<Value, EvalError> {
+    let Some((Vector(bindings), body)) = arguments.split_first() else {
+        return Err(InvalidArguments("handler-bind expects a vector of matchers and handlers and a body".into()));
+    };
+    if !bindings.len().is_multiple_of(2) {
+        return Err(InvalidArguments("handler-bind expects an even number of forms in its binding vector".into()));
+    }
+    let cluster_start = global_namespace.state.handlers.borrow().len();
+    let mut handlers = vec![];
+    for binding in bindings.chunks_exact(2) {
+        let matcher = eval_in(&binding[0], scope, global_namespace)?;
+        let function = eval_in(&binding[1], scope, global_namespace)?;
+        handlers.push(Handler::Bind { matcher, function, cluster_start });
+    }
+    // The first binding gets asked first, so it goes on top
+    let handlers_len = global_namespace.push_handlers(handlers.into_iter().rev());
+    let result = eval_body(body, scope, global_namespace);
+    global_namespace.truncate_handlers(handlers_len);
+    result
+}
 
-    pub fn eval(&mut self, key: &[u8], arguments: Vec<Value>) -> Result<Value, EvalError> {
-        let map_ref = self.functions.borrow();
-        let function = map_ref.get(key)
-            .ok_or_else(|| UnableToEvalFunction(from_utf8(key).unwrap().to_string()))?;
-        function.call(&arguments)
+// (restart-case expression (:name [parameters] body...) ...) evaluates the expression with the restarts
+// available. Invoking one unwinds back here and evaluates its body with the arguments given instead
+fn eval_restart_case(arguments: &[AstNode], scope: &Scope, global_namespace: &mut GlobalNamespace) -> Result<Value, EvalError> {
+    let Some((expression, clauses)) = arguments.split_first() else {
+        return Err(InvalidArguments("restart-case expects an expression and restart clauses".into()));
+    };
+    let clauses = clauses.iter().map(|clause| match clause {
+        List(nodes, _) if matches!(nodes.first(), Some(Keyword(_))) && nodes.len() >= 2 => {
+            let Keyword(name) = nodes[0] else { unreachable!("Checked above") };
+            Ok((name, Parameters::parse(&nodes[1])?, &nodes[2..]))
+        }
+        other => Err(InvalidArguments(format!("restart-case expects clauses like (:name [parameters] body...), got {other}"))),
+    }).collect::<Result<Vec<_>, EvalError>>()?;
+    let first_id = global_namespace.push_restarts(clauses.iter().map(|(name, _, _)| *name));
+    let result = eval_in(expression, scope, global_namespace);
+    global_namespace.pop_restarts(clauses.len());
+    match result {
+        Err(RestartInvoked { id, arguments, .. }) if (first_id..first_id + clauses.len()).contains(&id) => {
+            global_namespace.take_backtrace();
+            let (_, parameters, body) = &clauses[id - first_id];
+            eval_body(body, &parameters.bind(scope, &arguments)?, global_namespace)
+        }
+        result => result,
     }
 }
 
+// The body only runs once the sequence is first realised, and its result is remembered
+fn eval_lazy_seq(arguments: &[AstNode], scope: &Scope, global_namespace: &mut GlobalNamespace) -> Value {
+    let body: Rc<[AstNode]> = arguments.into();
+    let captured_scope = scope.clone();
+    let namespace = global_namespace.downgrade();
+    let defined_in = global_namespace.current_namespace();
+    LazySeq::from_thunk(move || {
+        let mut global_namespace = namespace.upgrade()?;
+        let value = global_namespace.within(defined_in, |global_namespace| eval_body(&body, &captured_scope, global_namespace))?;
+        first_and_rest(&value)
+    })
+}
 
 pub fn eval(node: &AstNode, global_namespace: &mut GlobalNamespace) -> Result<Value, EvalError> {
-    // We can only eval lists
-    let the_list = match node {
-        List(the_list) => { the_list }
+    global_namespace.take_backtrace();
+    eval_in(node, &Scope::default(), global_namespace)
+}
+
+// Like eval, but an error comes with the calls it went through
+pub fn eval_traced(node: &AstNode, global_namespace: &mut GlobalNamespace) -> Result<Value, TracedError> {
+    eval(node, global_namespace).map_err(|error| TracedError { error, backtrace: global_namespace.take_backtrace() })
+}
+
+pub fn eval_in(node: &AstNode, scope: &Scope, global_namespace: &mut GlobalNamespace) -> Result<Value, EvalError> {
+    match eval_form(node, scope, global_namespace) {
+        Ok(value) => {
+            global_namespace.settle();
+            Ok(value)
+        }
+        Err(error) => Err(global_namespace.raise(error)),
+    }
+}
+
+fn eval_form(node: &AstNode, scope: &Scope, global_namespace: &mut GlobalNamespace) -> Result<Value, EvalError> {
+    // Everything but lists evaluates to itself, or to what the symbol is bound to
+    let (the_list, span) = match node {
+        List(the_list, span) => { (the_list, span) }
         Num(the_num) => { return Ok(Value::Num(*the_num)); }
-        Sym(the_sym) => { return Err(InvalidArguments("Cannot eval a plain symbol since vars are not supported yet".into())); }
+        Sym(Symbol::NIL) => { return Ok(Value::Nil); }
+        Sym(Symbol::TRUE) => { return Ok(Value::Bool(true)); }
+        Sym(Symbol::FALSE) => { return Ok(Value::Bool(false)); }
+        Sym(the_sym) => {
+            if let Some(value) = scope.lookup(*the_sym) {
+                return Ok(value.clone());
+            }
+            return global_namespace.resolve(*the_sym)?
+                .ok_or_else(|| UnboundSymbol {
+                    name: the_sym.to_string(),
+                    suggestions: suggestions::similar_names(the_sym.name(), scope.symbols().chain(global_namespace.names())),
+                });
+        }
         Str(the_str) => { return Ok(Value::Str(the_str.clone())); }
+        Keyword(the_keyword) => { return Ok(Value::Keyword(*the_keyword)); }
+        Regex(the_pattern) => { return Ok(Value::Regex(the_pattern.clone())); }
+        Vector(nodes) => {
+            return nodes.iter().map(|node| eval_in(node, scope, global_namespace))
+                .collect::<Result<_, EvalError>>().map(Value::Vector);
+        }
+        Map(nodes) => {
+            return nodes.chunks_exact(2)
+                .map(|entry| Ok((eval_in(&entry[0], scope, global_namespace)?, eval_in(&entry[1], scope, global_namespace)?)))
+                .collect::<Result<_, EvalError>>().map(Value::Map);
+        }
     };
-    let mut list_iter = the_list.into_iter();
-    let Some(Sym(symbol_name)) = list_iter.next() else {
-        return Err(InvalidArguments("The evaluated value must exist and be a symbol".into()));
+    let Some((head, arguments)) = the_list.split_first() else {
+        return Err(EvalError::CannotEvaluateEmptyList);
     };
+    match head {
+        Sym(Symbol::DEF) => return eval_def(arguments, scope, global_namespace, false),
+        Sym(Symbol::DEF_PRIVATE) => return eval_def(arguments, scope, global_namespace, true),
+        Sym(Symbol::NS) => return eval_ns(arguments, global_namespace),
+        Sym(Symbol::IN_NS) => return eval_in_ns(arguments, global_namespace),
+        Sym(Symbol::FN) => return eval_fn(arguments, scope, global_namespace),
+        Sym(Symbol::LET) => return eval_let(arguments, scope, global_namespace),
+        Sym(Symbol::IF) => return eval_if(arguments, scope, global_namespace),
+        Sym(Symbol::DO) => return eval_body(arguments, scope, global_namespace),
+        Sym(Symbol::LAZY_SEQ) => return Ok(eval_lazy_seq(arguments, scope, global_namespace)),
+        Sym(Symbol::TRY) => return eval_try(arguments, scope, global_namespace),
+        Sym(Symbol::HANDLER_BIND) => return eval_handler_bind(arguments, scope, global_namespace),
+        Sym(Symbol::RESTART_CASE) => return eval_restart_case(arguments, scope, global_namespace),
+        _ => {}
+    }
 
-    let evaluated_arguments: Vec<Value> = list_iter
-        .map(|node| eval(node, global_namespace)).collect::<Result<Vec<Value>, EvalError>>()?;
-    return global_namespace.eval(symbol_name, evaluated_arguments);
+    let evaluated_arguments: Vec<Value> = arguments.iter()
+        .map(|node| eval_in(node, scope, global_namespace)).collect::<Result<Vec<Value>, EvalError>>()?;
+    let result = match head {
+        Sym(symbol_name) if scope.lookup(*symbol_name).is_none() => global_namespace.eval(*symbol_name, evaluated_arguments),
+        Sym(symbol_name) => {
+            let function = eval_in(head, scope, global_namespace)?;
+            apply_named(*symbol_name, &function, &evaluated_arguments)
+        }
+        head => {
+            let function = eval_in(head, scope, global_namespace)?;
+            apply(&function, &evaluated_arguments)
+        }
+    };
+    // Signalled before the call gets its frame, so handlers run on top of the call that failed
+    let result = result.map_err(|error| global_namespace.raise(error));
+    if result.is_err() {
+        // Only the call itself gets a frame, errors in its arguments already got theirs
+        let function = match head {
+            Sym(symbol) => symbol.to_string(),
+            _ => "fn".into(),
+        };
+        global_namespace.push_frame(StackFrame { function, span: span.clone() });
+    }
+    result
 }
@@ -0,0 +1,116 @@
+use crate::eval::{Arity, EvalError, GlobalNamespace};
+use crate::eval::EvalError::{CannotEvaluateEmptyList, CannotLoad, CircularRequire, ModuleNotFound, PrivateSymbol, UnknownNamespace, CannotEvaluateNonSymbol, Host, InvalidArguments, NamespaceDropped, NoSuchMethod, NotAFunction, RestartInvoked, Thrown, UnableToEvalFunction, UnboundSymbol, WrongArity, WrongType, WrongFieldType, MissingField, Overflow};
+use crate::symbol::Symbol;
+use crate::value::Value;
+
+pub fn define_builtins(namespace: &mut GlobalNamespace) {
+    namespace.defn(b"throw", lisp_throw.into());
+}
+
+fn keyword(name: &str) -> Value {
+    Value::Keyword(Symbol::intern(name.as_bytes()))
+}
+
+// What catch* binds the error to: thrown values stay as they were thrown, errors raised by the
+// interpreter itself become a map like {:type :unbound-symbol :message "unable to resolve symbol: x"}
+pub fn error_value(error: &EvalError) -> Value {
+    let error_type = match error {
+        Thrown(value) => return value.clone(),
+        UnableToEvalFunction { .. } => "unable-to-eval-function",
+        CannotEvaluateEmptyList => "cannot-evaluate-empty-list",
+        CannotEvaluateNonSymbol => "cannot-evaluate-non-symbol",
+        InvalidArguments(_) => "invalid-arguments",
+        WrongArity { .. } => "wrong-arity",
+        WrongType { .. } => "wrong-type",
+        Overflow { .. } => "overflow",
+        WrongFieldType { .. } => "wrong-field-type",
+        MissingField { .. } => "missing-field",
+        UnboundSymbol { .. } => "unbound-symbol",
+        NotAFunction(_) => "not-a-function",
+        NamespaceDropped => "namespace-dropped",
+        RestartInvoked { .. } => "restart-invoked",
+        CannotLoad { .. } => "cannot-load",
+        ModuleNotFound { .. } => "module-not-found",
+        CircularRequire { .. } => "circular-require",
+        UnknownNamespace(_) => "unknown-namespace",
+        PrivateSymbol { .. } => "private-symbol",
+        NoSuchMethod { .. } => "no-such-method",
+        Host(_) => "host-error",
+    };
+    Value::Map([
+        (keyword("type"), keyword(error_type)),
+        (keyword("message"), Value::Str(error.to_string().into())),
+    ].into_iter().collect())
+}
+
+fn lisp_throw(arguments: &[Value]) -> Result<Value, EvalError> {
+    let [value] = arguments else {
+        return Err(EvalError::wrong_arity("throw", Arity::Exactly(1), arguments.len()));
+    };
+    Err(Thrown(value.clone()))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::assert_matches;
+
+    use crate::test_support::eval_str;
+
+    use super::*;
+
+    #[test]
+    fn thrown_values_are_caught_as_they_were_thrown() {
+        let namespace = &mut GlobalNamespace::default();
+        assert_eq!(eval_str("(try* (throw {:code 42}) (catch* e (get e :code)))", namespace).unwrap(), Value::Num(42));
+        assert_eq!(eval_str("(try* 1 2 (catch* e 3))", namespace).unwrap(), Value::Num(2));
+        assert_eq!(eval_str("(try* (throw :inner) (catch* e (throw [e :outer])))", namespace).unwrap_err().to_string(),
+                   "uncaught exception: [:inner :outer]");
+        assert_matches!(eval_str("(throw \"boom\")", namespace), Err(Thrown(Value::Str(message))) if &*message == "boom");
+    }
+
+    #[test]
+    fn interpreter_errors_become_maps() {
+        let namespace = &mut GlobalNamespace::default();
+        assert_eq!(eval_str("(try* (undefined-thing) (catch* e (get e :message)))", namespace).unwrap(),
+                   Value::Str("there are no available functions with name: undefined-thing".into()));
+        assert_eq!(eval_str("(try* (+ 1 :a) (catch* e (get e :type)))", namespace).unwrap(), keyword("wrong-type"));
+        assert_eq!(eval_str("(try* (take 1) (catch* e (get e :message)))", namespace).unwrap(),
+                   Value::Str("wrong number of arguments: take expects 2 arguments, got 1".into()));
+        assert_matches!(eval_str("(throw 1 2)", namespace), Err(WrongArity { expected: Arity::Exactly(1), actual: 2, .. }));
+        assert_matches!(eval_str("(+ 1 :a)", namespace), Err(WrongType { expected: "a number", actual: Value::Keyword(_), .. }));
+    }
+
+    #[test]
+    fn arithmetic_overflow_is_an_error() {
+        let namespace = &mut GlobalNamespace::default();
+        assert_eq!(eval_str("(+ 9223372036854775807 1)", namespace).unwrap_err().to_string(), "integer overflow: the result of + doesn't fit in a number");
+        assert_matches!(eval_str("(- -9223372036854775807 1 1)", namespace), Err(Overflow { function }) if function == "-");
+        assert_matches!(eval_str("(* 0x7fffffffffffffff 2)", namespace), Err(Overflow { function }) if function == "*");
+        assert_eq!(eval_str("(try* (* 4294967296 4294967296) (catch* e (get e :type)))", namespace).unwrap(), keyword("overflow"));
+        assert_eq!(eval_str("(- 9223372036854775807 -1 2)", namespace).unwrap_err().to_string(), "integer overflow: the result of - doesn't fit in a number");
+    }
+
+    #[test]
+    fn arity_errors_name_the_function_called() {
+        let namespace = &mut GlobalNamespace::default();
+        eval_str("(def f (fn [x] x))", namespace).unwrap();
+        assert_eq!(eval_str("(f 1 2)", namespace).unwrap_err().to_string(), "wrong number of arguments: f expects 1 argument, got 2");
+        assert_eq!(eval_str("(let [g (fn [x & more] x)] (g))", namespace).unwrap_err().to_string(), "wrong number of arguments: g expects at least 1 argument, got 0");
+        assert_matches!(eval_str("((fn [] 1) 2)", namespace), Err(WrongArity { function, .. }) if function == "fn");
+        assert_eq!(eval_str("(try* (assoc {} :a) (catch* e (get e :type)))", namespace).unwrap(), keyword("wrong-arity"));
+        assert_eq!(eval_str("(assoc [1] 0)", namespace).unwrap_err().to_string(), "wrong number of arguments: assoc expects 1 argument followed by keys and values, got 2");
+        assert_matches!(eval_str("(hash-map :a 1 :b)", namespace), Err(WrongArity { expected: Arity::Pairs { leading: 0 }, actual: 3, .. }));
+    }
+
+    #[test]
+    fn finally_always_runs_but_keeps_the_result() {
+        let namespace = &mut GlobalNamespace::default();
+        assert_eq!(eval_str("(try* 1 (finally (def cleaned 1) 2))", namespace).unwrap(), Value::Num(1));
+        assert_matches!(eval_str("(try* (throw 1) (finally (def cleaned 2)))", namespace), Err(Thrown(Value::Num(1))));
+        assert_eq!(eval_str("cleaned", namespace).unwrap(), Value::Num(2));
+        assert_eq!(eval_str("(try* (throw 1) (catch* e (+ e 1)) (finally (def cleaned 3)))", namespace).unwrap(), Value::Num(2));
+        assert_eq!(eval_str("cleaned", namespace).unwrap(), Value::Num(3));
+        assert_matches!(eval_str("(try* 1 (finally (throw 2)))", namespace), Err(Thrown(Value::Num(2))));
+        assert_matches!(eval_str("(try* (finally 1) 2)", namespace), Err(InvalidArguments(_)));
+    }
+}
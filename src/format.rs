@@ -0,0 +1,341 @@
+use crate::eval::{Arity, EvalError, GlobalNamespace, WeakNamespace};
+use crate::eval::EvalError::InvalidArguments;
+use crate::strings::expect_str;
+use crate::value::{PrintMode, Value};
+
+// Placeholders look like Rust's: {} takes the next argument, {1} a positional one and {name} looks
+// the name up as keyword (or string) key in a map passed as the last argument. After a colon comes
+// [[fill]align][+][#][0][width][.precision][type] with align one of < ^ >, and type one of
+// x X o b (radix, # adds the reader prefix), f (fixed point), e (exponent) or ? (readable form).
+// Numbers are all whole, so there is no float formatting as such: f only writes the precision's
+// worth of zero decimals, and e is the one that rounds, like 1550 as {:.1e} is 1.6e3. Width and
+// precision go up to MAX_WIDTH, a template can't make format allocate whatever it asks for
+pub fn define_builtins(namespace: &mut GlobalNamespace) {
+    namespace.defn(b"format", lisp_format.into());
+    let printers: [(&[u8], Printer); 6] = [
+        (b"printf", lisp_printf),
+        (b"print", lisp_print),
+        (b"println", lisp_println),
+        (b"prn", lisp_prn),
+        (b"eprint", lisp_eprint),
+        (b"eprintln", lisp_eprintln),
+    ];
+    for (name, printer) in printers {
+        let weak_namespace = namespace.downgrade();
+        namespace.defn(name, (move |arguments: &[Value]| printer(&weak_namespace, arguments)).into());
+    }
+    namespace.defn(b"pr-str", lisp_pr_str.into());
+}
+
+// The printing builtins write to the output of the namespace they were defined in
+type Printer = fn(&WeakNamespace, &[Value]) -> Result<Value, EvalError>;
+
+#[derive(Clone, Copy)]
+enum Align {
+    Left,
+    Center,
+    Right,
+}
+
+#[derive(Clone, Copy)]
+enum Kind {
+    Display,
+    Readable,
+    Radix(u32, bool),
+    Fixed,
+    Exponent,
+}
+
+struct Spec {
+    fill: char,
+    align: Option<Align>,
+    plus_sign: bool,
+    alternate: bool,
+    zero_pad: bool,
+    width: usize,
+    precision: Option<usize>,
+    kind: Kind,
+}
+
+fn align_for(c: char) -> Option<Align> {
+    match c {
+        '<' => Some(Align::Left),
+        '^' => Some(Align::Center),
+        '>' => Some(Align::Right),
+        _ => None,
+    }
+}
+
+const MAX_WIDTH: usize = 1024;
+
+// Numbers too big for a usize are still numbers, just ones over MAX_WIDTH
+fn take_number(spec: &str) -> (Option<usize>, &str) {
+    let digits_end = spec.find(|c: char| !c.is_ascii_digit()).unwrap_or(spec.len());
+    let number = (digits_end > 0).then(|| spec[..digits_end].parse().unwrap_or(usize::MAX));
+    (number, &spec[digits_end..])
+}
+
+impl Spec {
+    fn parse(spec: &str) -> Option<Spec> {
+        let mut chars = spec.chars();
+        let (fill, align, spec) = match (chars.next(), chars.next().and_then(align_for)) {
+            (Some(fill), Some(align)) => (fill, Some(align), chars.as_str()),
+            (Some(first), _) if align_for(first).is_some() => (' ', align_for(first), &spec[first.len_utf8()..]),
+            _ => (' ', None, spec),
+        };
+        let (plus_sign, spec) = spec.strip_prefix('+').map_or((false, spec), |rest| (true, rest));
+        let (alternate, spec) = spec.strip_prefix('#').map_or((false, spec), |rest| (true, rest));
+        let (zero_pad, spec) = spec.strip_prefix('0').map_or((false, spec), |rest| (true, rest));
+        let (width, spec) = take_number(spec);
+        let (precision, spec) = match spec.strip_prefix('.') {
+            Some(rest) => match take_number(rest) {
+                (None, _) => return None,
+                (precision, rest) => (precision, rest),
+            },
+            None => (None, spec),
+        };
+        let kind = match spec {
+            "" => Kind::Display,
+            "?" => Kind::Readable,
+            "x" => Kind::Radix(16, false),
+            "X" => Kind::Radix(16, true),
+            "o" => Kind::Radix(8, false),
+            "b" => Kind::Radix(2, false),
+            "f" => Kind::Fixed,
+            "e" => Kind::Exponent,
+            _ => return None,
+        };
+        Some(Spec { fill, align, plus_sign, alternate, zero_pad, width: width.unwrap_or(0), precision, kind })
+    }
+
+    // Numbers are split into a sign (and radix prefix) and digits, so zero padding can go in between
+    fn render_number(&self, number: isize) -> (String, String) {
+        let mut prefix = match number {
+            ..0 => "-".to_string(),
+            _ if self.plus_sign => "+".to_string(),
+            _ => String::new(),
+        };
+        let magnitude = number.unsigned_abs();
+        // There are only whole numbers, so fixed point just adds the requested zero decimals
+        let fixed = || match self.precision {
+            Some(precision) if precision > 0 => format!("{magnitude}.{}", "0".repeat(precision)),
+            _ => magnitude.to_string(),
+        };
+        let digits = match self.kind {
+            Kind::Display | Kind::Readable | Kind::Fixed => fixed(),
+            Kind::Exponent => match self.precision {
+                Some(precision) => format!("{magnitude:.precision$e}"),
+                None => format!("{magnitude:e}"),
+            },
+            Kind::Radix(radix, upper_case) => {
+                if self.alternate {
+                    prefix.push_str(match radix {
+                        16 => "0x",
+                        8 => "0o",
+                        _ => "0b",
+                    });
+                }
+                match (radix, upper_case) {
+                    (16, true) => format!("{magnitude:X}"),
+                    (16, false) => format!("{magnitude:x}"),
+                    (8, _) => format!("{magnitude:o}"),
+                    _ => format!("{magnitude:b}"),
+                }
+            }
+        };
+        (prefix, digits)
+    }
+
+    fn render(&self, function_name: &str, value: &Value) -> Result<String, EvalError> {
+        let (prefix, body) = match (self.kind, value) {
+            (_, Value::Num(number)) => self.render_number(*number),
+            (Kind::Display | Kind::Readable, other) => {
+                let mode = if let Kind::Readable = self.kind { PrintMode::Readable } else { PrintMode::Display };
+                let text = other.printed(mode).to_string();
+                // For anything but numbers the precision is the maximum number of chars shown
+                match self.precision {
+                    Some(precision) => (String::new(), text.chars().take(precision).collect()),
+                    None => (String::new(), text),
+                }
+            }
+            (_, other) => return Err(InvalidArguments(format!("{function_name} can only format numbers with radix, fixed point or exponent, got {} {other}", other.type_name()))),
+        };
+        let is_number = matches!(value, Value::Num(_));
+        let len = prefix.chars().count() + body.chars().count();
+        let Some(padding) = self.width.checked_sub(len).filter(|padding| *padding > 0) else {
+            return Ok(prefix + &body);
+        };
+        if self.zero_pad && is_number {
+            return Ok(prefix + &"0".repeat(padding) + &body);
+        }
+        let fill = |count: usize| self.fill.to_string().repeat(count);
+        let default_align = if is_number { Align::Right } else { Align::Left };
+        Ok(match self.align.unwrap_or(default_align) {
+            Align::Left => prefix + &body + &fill(padding),
+            Align::Center => fill(padding / 2) + &prefix + &body + &fill(padding - padding / 2),
+            Align::Right => fill(padding) + &prefix + &body,
+        })
+    }
+}
+
+fn select_argument<'a>(function_name: &str, selector: &str, arguments: &'a [Value], next_positional: &mut usize) -> Result<&'a Value, EvalError> {
+    if selector.is_empty() {
+        let index = *next_positional;
+        *next_positional += 1;
+        return arguments.get(index)
+            .ok_or_else(|| InvalidArguments(format!("{function_name} has more placeholders than the {} arguments given", arguments.len())));
+    }
+    if let Ok(index) = selector.parse::<usize>() {
+        return arguments.get(index)
+            .ok_or_else(|| InvalidArguments(format!("{function_name} has no argument {index}, only {} were given", arguments.len())));
+    }
+    let Some(Value::Map(names)) = arguments.last() else {
+        return Err(InvalidArguments(format!("{function_name} needs a map as last argument to fill in {{{selector}}}")));
+    };
+    names.iter()
+        .find(|(key, _)| match key {
+            Value::Keyword(keyword) => keyword.name() == selector,
+            Value::Str(string) => string.as_ref() == selector,
+            _ => false,
+        })
+        .map(|(_, value)| value)
+        .ok_or_else(|| InvalidArguments(format!("{function_name} got no value for {{{selector}}}")))
+}
+
+pub fn format_values(function_name: &str, template: &str, arguments: &[Value]) -> Result<String, EvalError> {
+    let mut output = String::new();
+    let mut next_positional = 0;
+    let mut rest = template;
+    while let Some(index) = rest.find(['{', '}']) {
+        output.push_str(&rest[..index]);
+        let (brace, after) = rest[index..].split_at(1);
+        // Doubled braces are literal ones
+        if after.starts_with(brace) {
+            output.push_str(brace);
+            rest = &after[1..];
+            continue;
+        }
+        if brace == "}" {
+            return Err(InvalidArguments(format!("{function_name} found an unmatched }} in {template:?}")));
+        }
+        let Some((placeholder, after)) = after.split_once('}') else {
+            return Err(InvalidArguments(format!("{function_name} found an unclosed {{ in {template:?}")));
+        };
+        let (selector, spec) = placeholder.split_once(':').unwrap_or((placeholder, ""));
+        let spec = Spec::parse(spec)
+            .ok_or_else(|| InvalidArguments(format!("{function_name} cannot understand the placeholder {{{placeholder}}}")))?;
+        if spec.width.max(spec.precision.unwrap_or(0)) > MAX_WIDTH {
+            return Err(InvalidArguments(format!("{function_name} allows a width and precision of at most {MAX_WIDTH}, got {{{placeholder}}}")));
+        }
+        let value = select_argument(function_name, selector, arguments, &mut next_positional)?;
+        output.push_str(&spec.render(function_name, value)?);
+        rest = after;
+    }
+    output.push_str(rest);
+    Ok(output)
+}
+
+fn format_arguments(function_name: &str, arguments: &[Value]) -> Result<String, EvalError> {
+    let [template, arguments @ ..] = arguments else {
+        return Err(EvalError::wrong_arity(function_name, Arity::AtLeast(1), 0));
+    };
+    format_values(function_name, expect_str(function_name, template)?, arguments)
+}
+
+fn lisp_format(arguments: &[Value]) -> Result<Value, EvalError> {
+    Ok(Value::Str(format_arguments("format", arguments)?.into()))
+}
+
+fn lisp_printf(namespace: &WeakNamespace, arguments: &[Value]) -> Result<Value, EvalError> {
+    namespace.upgrade()?.write_stdout(&format_arguments("printf", arguments)?)?;
+    Ok(Value::Nil)
+}
+
+// print and println are for people, so strings come out without quotes, while prn and pr-str
+// print values the way they are written in source
+fn joined(arguments: &[Value], mode: PrintMode) -> String {
+    let printed: Vec<String> = arguments.iter().map(|argument| argument.printed(mode).to_string()).collect();
+    printed.join(" ")
+}
+
+fn lisp_print(namespace: &WeakNamespace, arguments: &[Value]) -> Result<Value, EvalError> {
+    namespace.upgrade()?.write_stdout(&joined(arguments, PrintMode::Display))?;
+    Ok(Value::Nil)
+}
+
+fn lisp_println(namespace: &WeakNamespace, arguments: &[Value]) -> Result<Value, EvalError> {
+    namespace.upgrade()?.write_stdout(&format!("{}\n", joined(arguments, PrintMode::Display)))?;
+    Ok(Value::Nil)
+}
+
+fn lisp_prn(namespace: &WeakNamespace, arguments: &[Value]) -> Result<Value, EvalError> {
+    namespace.upgrade()?.write_stdout(&format!("{}\n", joined(arguments, PrintMode::Readable)))?;
+    Ok(Value::Nil)
+}
+
+// Like print and println, but for diagnostics
+fn lisp_eprint(namespace: &WeakNamespace, arguments: &[Value]) -> Result<Value, EvalError> {
+    namespace.upgrade()?.write_stderr(&joined(arguments, PrintMode::Display))?;
+    Ok(Value::Nil)
+}
+
+fn lisp_eprintln(namespace: &WeakNamespace, arguments: &[Value]) -> Result<Value, EvalError> {
+    namespace.upgrade()?.write_stderr(&format!("{}\n", joined(arguments, PrintMode::Display)))?;
+    Ok(Value::Nil)
+}
+
+fn lisp_pr_str(arguments: &[Value]) -> Result<Value, EvalError> {
+    Ok(Value::Str(joined(arguments, PrintMode::Readable).into()))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_support::{eval_str, eval_value};
+
+    use super::*;
+
+    fn format(source: &str) -> String {
+        let Value::Str(formatted) = eval_value(source) else { panic!("Expected a string") };
+        formatted.to_string()
+    }
+
+    #[test]
+    fn positional_and_named_placeholders() {
+        assert_eq!(format(r#"(format "{} + {} = {2}, {0}" 1 2 3)"#), "1 + 2 = 3, 1");
+        assert_eq!(format(r#"(format "{name} is {age}" {:name "Ada" :age 36})"#), "Ada is 36");
+        assert_eq!(format(r#"(format "{{{}}}" "x")"#), "{x}");
+        assert_eq!(format(r#"(format "{} vs {:?}" "text" "text")"#), "text vs \"text\"");
+    }
+
+    #[test]
+    fn width_precision_and_padding() {
+        assert_eq!(format(r#"(format "[{:5}|{:<5}|{:^5}|{:*>5}]" 42 42 "ab" "ab")"#), "[   42|42   | ab  |***ab]");
+        assert_eq!(format(r#"(format "{:05}|{:+05}|{:05}" 42 42 -42)"#), "00042|+0042|-0042");
+        assert_eq!(format(r#"(format "{:.3}|{:8.2f}" "héllo" 7)"#), "hél|    7.00");
+    }
+
+    #[test]
+    fn radix_and_exponent_types() {
+        assert_eq!(format(r#"(format "{:x} {:X} {:#x} {:#010b} {:o}" 255 255 -255 5 8)"#), "ff FF -0xff 0b00000101 10");
+        assert_eq!(format(r#"(format "{:e} {:.1e}" 1500 1550)"#), "1.5e3 1.6e3");
+        assert_eq!(format(r#"(format "{:.2f}|{:+.1f}|{:.0e}|{:.3e}" 5 -3 1500 -123456)"#), "5.00|-3.0|2e3|-1.235e5");
+    }
+
+    #[test]
+    fn width_and_precision_are_bounded() {
+        assert_eq!(format(r#"(format "{:1024}" 1)"#).len(), 1024);
+        let namespace = &mut GlobalNamespace::default();
+        for template in ["{:999999999}", "{:.1025}", "{:99999999999999999999999}", "{:.99999999999999999999999e}"] {
+            let error = eval_str(&format!(r#"(format "{template}" 1)"#), namespace).unwrap_err();
+            assert_eq!(error.to_string(), format!("invalid arguments: format allows a width and precision of at most 1024, got {template}"));
+        }
+    }
+
+    #[test]
+    fn bad_templates_are_errors() {
+        for template in ["{", "}", "{} {}", "{:q}", "{missing}", "{:x}"] {
+            let source = format!(r#"(format "{template}" "a")"#);
+            assert!(eval_str(&source, &mut GlobalNamespace::default()).is_err(), "{template}");
+        }
+    }
+}
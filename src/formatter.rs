@@ -0,0 +1,212 @@
+use crate::cst::{parse_cst, CstNode};
+use crate::parse_error::ParseError;
+
+const WIDTH: usize = 80;
+const BODY_INDENT: usize = 2;
+
+// How many arguments of a special form stay on the line of its name, everything after them is the
+// body and gets indented by two, like (let [x 1]\n  body)
+fn special_form_arguments(head: &str) -> Option<usize> {
+    match head {
+        "do" | "lazy-seq" | "try*" | "finally" => Some(0),
+        "def" | "fn" | "let" | "if" | "catch*" | "handler-bind" | "restart-case"
+        | "def-" | "ns" | "in-ns" => Some(1),
+        _ => None,
+    }
+}
+
+// Sequences that contain a comment or blank line can never be put on one line
+fn flat(node: &CstNode) -> Option<String> {
+    match node {
+        CstNode::Atom(text) => Some(text.clone()),
+        CstNode::Comment { .. } | CstNode::BlankLine => None,
+        CstNode::Sequence { open, close, children } => {
+            let children = children.iter().map(flat).collect::<Option<Vec<String>>>()?;
+            Some(format!("{}{}{}", char::from(*open), children.join(" "), char::from(*close)))
+        }
+        CstNode::Prefixed { prefix, form } => Some(format!("{}{}", char::from(*prefix), flat(form)?)),
+    }
+}
+
+// Where the children of a sequence that doesn't fit on one line go
+struct Layout {
+    // How many forms share the line of the opening delimiter
+    first_line_forms: usize,
+    indent: usize,
+    // Maps and let bindings keep every key on one line with its value
+    paired: bool,
+}
+
+fn layout(open: u8, children: &[CstNode], column: usize, parent_head: Option<&str>) -> Layout {
+    let paired = open == b'{' || (open == b'[' && parent_head == Some("let"));
+    if open != b'(' {
+        return Layout { first_line_forms: 1, indent: column + 1, paired };
+    }
+    match children.first() {
+        Some(CstNode::Atom(head)) => match special_form_arguments(head) {
+            Some(arguments) => Layout { first_line_forms: 1 + arguments, indent: column + BODY_INDENT, paired },
+            // Function calls line their arguments up below the first one
+            None if children.len() > 1 && children[1].is_form() =>
+                Layout { first_line_forms: 2, indent: column + 1 + head.chars().count() + 1, paired },
+            None => Layout { first_line_forms: 1, indent: column + 1, paired },
+        },
+        _ => Layout { first_line_forms: 1, indent: column + 1, paired },
+    }
+}
+
+struct Formatter {
+    output: String,
+    column: usize,
+}
+
+impl Formatter {
+    fn write(&mut self, text: &str) {
+        self.output.push_str(text);
+        self.column = match text.rfind('\n') {
+            Some(newline) => text[newline + 1..].chars().count(),
+            None => self.column + text.chars().count(),
+        };
+    }
+
+    fn newline(&mut self, indent: usize, blank_line: bool) {
+        if blank_line {
+            self.output.push('\n');
+        }
+        self.output.push('\n');
+        self.output.push_str(&" ".repeat(indent));
+        self.column = indent;
+    }
+
+    fn sequence(&mut self, node: &CstNode, parent_head: Option<&str>) {
+        let CstNode::Sequence { open, close, children } = node else {
+            unreachable!("Only sequences have children to lay out");
+        };
+        if let Some(flat) = flat(node).filter(|flat| self.column + flat.chars().count() <= WIDTH) {
+            return self.write(&flat);
+        }
+        let layout = layout(*open, children, self.column, parent_head);
+        let head = match children.first() {
+            Some(CstNode::Atom(head)) if *open == b'(' => Some(head.as_str()),
+            _ => None,
+        };
+        self.write(&char::from(*open).to_string());
+        self.children(children, &layout, head);
+        self.write(&char::from(*close).to_string());
+    }
+
+    fn form(&mut self, form: &CstNode, parent_head: Option<&str>) {
+        match form {
+            CstNode::Atom(text) => self.write(text),
+            CstNode::Prefixed { prefix, form } => {
+                self.write(&char::from(*prefix).to_string());
+                self.form(form, parent_head);
+            }
+            sequence => self.sequence(sequence, parent_head),
+        }
+    }
+
+    fn children(&mut self, children: &[CstNode], layout: &Layout, head: Option<&str>) {
+        let mut forms = 0;
+        let mut blank_line = false;
+        // After a comment only a new line can follow, or the comment would swallow the code
+        let mut after_comment = false;
+        for (index, child) in children.iter().enumerate() {
+            match child {
+                CstNode::BlankLine => blank_line = true,
+                CstNode::Comment { text, trailing } => {
+                    if index == 0 {
+                        self.write(text);
+                    } else if *trailing && !after_comment {
+                        self.write(" ");
+                        self.write(text);
+                    } else {
+                        self.newline(layout.indent, blank_line);
+                        self.write(text);
+                    }
+                    blank_line = false;
+                    after_comment = true;
+                }
+                form => {
+                    let same_line = !after_comment && !blank_line
+                        && (forms < layout.first_line_forms || (layout.paired && forms % 2 == 1));
+                    if index == 0 {
+                        // Right after the opening delimiter
+                    } else if same_line {
+                        self.write(" ");
+                    } else {
+                        self.newline(layout.indent, blank_line);
+                    }
+                    self.form(form, head);
+                    forms += 1;
+                    blank_line = false;
+                    after_comment = false;
+                }
+            }
+        }
+        if after_comment {
+            // The closing delimiter can't go after a comment either
+            self.newline(layout.indent, false);
+        }
+    }
+}
+
+// Lays a source file out again: every top level form starts on its own line, sequences stay on one
+// line when they fit in 80 columns and are broken up otherwise, comments and blank lines are kept
+pub fn format_source(source: &[u8]) -> Result<String, ParseError> {
+    let nodes = parse_cst(source)?;
+    let mut formatter = Formatter { output: String::new(), column: 0 };
+    formatter.children(&nodes, &Layout { first_line_forms: 0, indent: 0, paired: false }, None);
+    let mut output = formatter.output.trim_end().to_string();
+    if !output.is_empty() {
+        output.push('\n');
+    }
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::tokenize::AstToken::Parsed;
+    use crate::tokenize::tokenize;
+
+    use super::*;
+
+    const MESSY: &str = "; squares things\n(def   square (fn [x]\n (* x x)))  ; short enough\n\n\n\n(def numbers (map (fn [n] {:n n :square (square n) :label (str \"number \" n) :even (= 0 (- n (* 2 (reduce + [n]))))}) (range 10)))\n(let [a 1 b [1 2 3 4 5 6 7 8 9 10 11 12 13 14 15 16 17 18 19 20]] ; bindings\n  ; the body\n  (+ a b))";
+
+    #[test]
+    fn formats_with_special_form_indentation() {
+        assert_eq!(format_source(MESSY.as_bytes()).unwrap(), "\
+; squares things
+(def square (fn [x] (* x x))) ; short enough
+
+(def numbers
+  (map (fn [n]
+         {:n n
+          :square (square n)
+          :label (str \"number \" n)
+          :even (= 0 (- n (* 2 (reduce + [n]))))})
+       (range 10)))
+(let [a 1 b [1 2 3 4 5 6 7 8 9 10 11 12 13 14 15 16 17 18 19 20]] ; bindings
+  ; the body
+  (+ a b))
+");
+    }
+
+    #[test]
+    fn formatting_is_idempotent_and_keeps_the_meaning() {
+        let formatted = format_source(MESSY.as_bytes()).unwrap();
+        assert_eq!(format_source(formatted.as_bytes()).unwrap(), formatted);
+        let last_form = |source: &str| {
+            let form = &source[source.rfind("(let").unwrap()..];
+            let Parsed(node) = tokenize(form.as_bytes()).unwrap() else { panic!() };
+            node
+        };
+        assert_eq!(last_form(&formatted), last_form(MESSY));
+    }
+
+    #[test]
+    fn comments_never_swallow_code() {
+        assert_eq!(format_source(b"(f ; first\n x)").unwrap(), "(f ; first\n x)\n");
+        assert_eq!(format_source(b"[1 2 ; last\n]").unwrap(), "[1\n 2 ; last\n ]\n");
+        assert_eq!(format_source(b"(swap!   counter +   @step)").unwrap(), "(swap! counter + @step)\n");
+    }
+}
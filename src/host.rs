@@ -0,0 +1,189 @@
+use std::any::{type_name, Any, TypeId};
+use std::fmt;
+use std::fmt::{Display, Formatter};
+use std::marker::PhantomData;
+use std::ops::Deref;
+use std::rc::Rc;
+
+use crate::convert::{FromValue, IntoValue, Mismatch};
+use crate::eval::{apply, Arity, EvalError, GlobalNamespace};
+use crate::eval::EvalError::NoSuchMethod;
+use crate::symbol::Symbol;
+use crate::value::Value;
+
+// A value of the embedding application's own, like a connection or a config, that scripts can pass
+// around and hand back to Rust but can't look inside of except through registered methods
+pub struct HostObject {
+    object: Box<dyn Any>,
+    type_name: &'static str,
+    display: Option<fn(&dyn Any, &mut Formatter<'_>) -> fmt::Result>,
+}
+
+// Config rather than app::settings::Config, generic arguments are left out
+fn short_type_name<T>() -> &'static str {
+    let name = type_name::<T>();
+    let without_generics = &name[..name.find('<').unwrap_or(name.len())];
+    without_generics.rsplit("::").next().unwrap_or(without_generics)
+}
+
+impl HostObject {
+    pub fn new<T: 'static>(object: T) -> HostObject {
+        HostObject { object: Box::new(object), type_name: short_type_name::<T>(), display: None }
+    }
+
+    // Printed with the type's Display instead of as #<host Type>
+    pub fn with_display<T: Display + 'static>(object: T) -> HostObject {
+        let display = |object: &dyn Any, f: &mut Formatter<'_>| match object.downcast_ref::<T>() {
+            Some(object) => object.fmt(f),
+            None => unreachable!("The display function is only ever given the object it was made for"),
+        };
+        HostObject { display: Some(display), ..HostObject::new(object) }
+    }
+
+    pub fn downcast_ref<T: 'static>(&self) -> Option<&T> {
+        self.object.downcast_ref()
+    }
+
+    pub fn is<T: 'static>(&self) -> bool {
+        self.object.is::<T>()
+    }
+
+    pub fn object_type(&self) -> TypeId {
+        (*self.object).type_id()
+    }
+
+    pub fn type_name(&self) -> &'static str {
+        self.type_name
+    }
+}
+
+impl Display for HostObject {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self.display {
+            Some(display) => display(&*self.object, f),
+            None => write!(f, "#<host {}>", self.type_name),
+        }
+    }
+}
+
+// A host object known to hold a T, what registered functions and methods take and return
+pub struct Host<T> {
+    object: Rc<HostObject>,
+    marker: PhantomData<T>,
+}
+
+impl<T: 'static> Host<T> {
+    pub fn new(object: T) -> Host<T> {
+        Host { object: Rc::new(HostObject::new(object)), marker: PhantomData }
+    }
+}
+
+impl<T: Display + 'static> Host<T> {
+    pub fn with_display(object: T) -> Host<T> {
+        Host { object: Rc::new(HostObject::with_display(object)), marker: PhantomData }
+    }
+}
+
+impl<T> Clone for Host<T> {
+    fn clone(&self) -> Self {
+        Host { object: self.object.clone(), marker: PhantomData }
+    }
+}
+
+impl<T: 'static> Deref for Host<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        self.object.downcast_ref().expect("A Host<T> is only made for objects holding a T")
+    }
+}
+
+impl<T: 'static> FromValue for Host<T> {
+    fn from_value(value: &Value) -> Result<Self, Mismatch> {
+        match value {
+            Value::Host(object) if object.is::<T>() => Ok(Host { object: object.clone(), marker: PhantomData }),
+            other => Err(Mismatch::new(short_type_name::<T>(), other)),
+        }
+    }
+}
+
+impl<T: 'static> IntoValue for Host<T> {
+    fn into_value(self) -> Value {
+        Value::Host(self.object)
+    }
+}
+
+// (.port config) calls the method port registered for the type of config, with config as the first
+// argument
+pub(crate) fn call_method(namespace: &GlobalNamespace, method: &str, arguments: &[Value]) -> Result<Value, EvalError> {
+    let function_name = format!(".{method}");
+    let object = match arguments.first() {
+        Some(Value::Host(object)) => object,
+        Some(other) => return Err(EvalError::wrong_type(&function_name, "a host object", other)),
+        None => return Err(EvalError::wrong_arity(&function_name, Arity::AtLeast(1), 0)),
+    };
+    let function = namespace.method(object.object_type(), Symbol::intern(method.as_bytes()))
+        .ok_or_else(|| NoSuchMethod { type_name: object.type_name().into(), method: method.into() })?;
+    apply(&function, arguments)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::assert_matches;
+    use std::cell::Cell;
+
+    use crate::eval::EvalError::WrongType;
+    use crate::interpreter::{Interpreter, InterpreterError};
+
+    use super::*;
+
+    struct Connection {
+        url: String,
+        queries: Cell<usize>,
+    }
+
+    struct Config {
+        port: i64,
+    }
+
+    impl Display for Config {
+        fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+            write!(f, "#<config :{}>", self.port)
+        }
+    }
+
+    #[test]
+    fn host_objects_go_through_scripts_and_back() {
+        let mut interpreter = Interpreter::new();
+        interpreter.set_global("db", Host::new(Connection { url: "db://local".into(), queries: Cell::new(0) }));
+        interpreter.set_global("config", Host::with_display(Config { port: 8080 }));
+        interpreter.register("query", |connection: Host<Connection>, sql: String| {
+            connection.queries.set(connection.queries.get() + 1);
+            format!("{} on {}", sql, connection.url)
+        });
+        assert_eq!(interpreter.eval_str("(query db \"select 1\")").unwrap(), Value::Str("select 1 on db://local".into()));
+        let Some(Value::Host(object)) = interpreter.get_global("db") else { panic!("Expected a host object") };
+        assert_eq!(object.downcast_ref::<Connection>().unwrap().queries.get(), 1);
+        assert!(object.downcast_ref::<Config>().is_none());
+        assert_eq!(interpreter.eval_str("(str db \" \" config)").unwrap(), Value::Str("#<host Connection> #<config :8080>".into()));
+        assert_eq!(interpreter.eval_str("(= db db)").unwrap(), Value::Bool(true));
+        let Err(InterpreterError::Eval(traced)) = interpreter.eval_str("(query config \"x\")") else { panic!("Expected an error") };
+        assert_matches!(traced.error, WrongType { expected: "Connection", .. });
+    }
+
+    #[test]
+    fn methods_dispatch_on_the_type_of_the_object() {
+        let mut interpreter = Interpreter::new();
+        interpreter.set_global("config", Host::with_display(Config { port: 8080 }));
+        interpreter.set_global("db", Host::new(Connection { url: "db://local".into(), queries: Cell::new(0) }));
+        interpreter.register_method::<Config, _>("port", |config: Host<Config>| config.port);
+        interpreter.register_method::<Config, _>("with-port", |_: Host<Config>, port: i64| Host::with_display(Config { port }));
+        interpreter.register_method::<Connection, _>("url", |connection: Host<Connection>| connection.url.clone());
+        assert_eq!(interpreter.eval_str("(.port config)").unwrap(), Value::Num(8080));
+        assert_eq!(interpreter.eval_str("(.port (.with-port config 9000))").unwrap(), Value::Num(9000));
+        assert_eq!(interpreter.eval_str("(.url db)").unwrap(), Value::Str("db://local".into()));
+        assert_eq!(interpreter.eval_str("(.port db)").unwrap_err().to_string(), "Connection has no method port\n    at .port (<string>:1:1)");
+        assert_eq!(interpreter.eval_str("(.port 1)").unwrap_err().to_string(),
+                   "wrong type of argument: .port expects a host object, got number 1\n    at .port (<string>:1:1)");
+    }
+}
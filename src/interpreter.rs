@@ -0,0 +1,173 @@
+use std::cell::RefCell;
+use std::fs;
+use std::io;
+use std::io::Write;
+use std::path::Path;
+use std::rc::Rc;
+
+use thiserror::Error;
+
+use crate::backtrace::TracedError;
+use crate::convert::{IntoValue, NativeFunction};
+use crate::eval::{eval_traced, GlobalNamespace};
+use crate::loader::add_search_path;
+use crate::parse_error::ParseError;
+use crate::symbol::Symbol;
+use crate::tokenize::tokenize_all;
+use crate::value::Value;
+
+#[derive(Error, Debug)]
+pub enum InterpreterError {
+    #[error("cannot read {path}: {error}")]
+    CannotRead { path: String, error: io::Error },
+    #[error("cannot parse {source_name}: {error}")]
+    Parse { source_name: String, error: ParseError },
+    #[error(transparent)]
+    Eval(#[from] TracedError),
+}
+
+// Everything an application needs to run scripts: a namespace with all the builtins, and ways to
+// get values in and out of it
+//
+//   let mut interpreter = Interpreter::new();
+//   interpreter.register("double", |x: i64| x * 2);
+//   interpreter.set_global("limit", 10isize);
+//   let result = interpreter.eval_str("(double limit)")?;
+#[derive(Default)]
+pub struct Interpreter {
+    namespace: GlobalNamespace,
+}
+
+impl Interpreter {
+    pub fn new() -> Interpreter {
+        Self::default()
+    }
+
+    // For anything the facade doesn't cover
+    pub fn namespace(&mut self) -> &mut GlobalNamespace {
+        &mut self.namespace
+    }
+
+    // Evaluates every form and returns the value of the last one, nil if there are none
+    pub fn eval_str(&mut self, source: &str) -> Result<Value, InterpreterError> {
+        self.eval_source(source.as_bytes(), "<string>")
+    }
+
+    pub fn eval_file(&mut self, path: impl AsRef<Path>) -> Result<Value, InterpreterError> {
+        let path = path.as_ref();
+        let source = fs::read(path).map_err(|error| InterpreterError::CannotRead { path: path.display().to_string(), error })?;
+        self.eval_source(&source, &path.display().to_string())
+    }
+
+    fn eval_source(&mut self, source: &[u8], source_name: &str) -> Result<Value, InterpreterError> {
+        let nodes = tokenize_all(source, source_name.into())
+            .map_err(|error| InterpreterError::Parse { source_name: source_name.into(), error })?;
+        let mut result = Value::Nil;
+        for node in &nodes {
+            result = eval_traced(node, &mut self.namespace)?;
+        }
+        Ok(result)
+    }
+
+    // Calls the function bound to the name, which can be qualified like text/shout
+    pub fn call(&mut self, name: &str, arguments: &[Value]) -> Result<Value, InterpreterError> {
+        self.namespace.take_backtrace();
+        self.namespace.eval(Symbol::intern(name.as_bytes()), arguments.to_vec())
+            .map_err(|error| InterpreterError::Eval(TracedError { error, backtrace: self.namespace.take_backtrace() }))
+    }
+
+    // Defines the name in the current namespace
+    pub fn set_global(&mut self, name: &str, value: impl IntoValue) {
+        self.namespace.def(name.as_bytes(), value.into_value());
+    }
+
+    pub fn get_global(&self, name: &str) -> Option<Value> {
+        self.namespace.get(Symbol::intern(name.as_bytes()))
+    }
+
+    pub fn register<Args>(&mut self, name: &str, function: impl NativeFunction<Args>) {
+        self.namespace.register(name, function);
+    }
+
+    pub fn register_method<T: 'static, Args>(&mut self, name: &str, method: impl NativeFunction<Args>) {
+        self.namespace.register_method::<T, Args>(name, method);
+    }
+
+    pub fn add_search_path(&mut self, directory: impl AsRef<Path>) {
+        add_search_path(&mut self.namespace, directory.as_ref());
+    }
+
+    pub fn set_stdout(&mut self, sink: impl Write + 'static) {
+        self.namespace.set_stdout(sink);
+    }
+
+    pub fn set_stderr(&mut self, sink: impl Write + 'static) {
+        self.namespace.set_stderr(sink);
+    }
+}
+
+// A sink that keeps what is written to it, to capture the output of scripts. Clones share the
+// buffer, so one can be handed to the interpreter and the other read from
+#[derive(Clone, Default)]
+pub struct OutputBuffer(Rc<RefCell<Vec<u8>>>);
+
+impl OutputBuffer {
+    pub fn new() -> OutputBuffer {
+        Self::default()
+    }
+
+    // Everything written so far, emptying the buffer
+    pub fn take_string(&self) -> String {
+        String::from_utf8_lossy(&self.0.take()).into_owned()
+    }
+}
+
+impl Write for OutputBuffer {
+    fn write(&mut self, buffer: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(buffer);
+        Ok(buffer.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::assert_matches;
+
+    use crate::eval::EvalError;
+
+    use super::*;
+
+    #[test]
+    fn scripts_run_with_host_values_and_output() {
+        let mut interpreter = Interpreter::new();
+        let (stdout, stderr) = (OutputBuffer::new(), OutputBuffer::new());
+        interpreter.set_stdout(stdout.clone());
+        interpreter.set_stderr(stderr.clone());
+        interpreter.register("double", |x: i64| x * 2);
+        interpreter.set_global("limit", 10isize);
+        let result = interpreter.eval_str("(println \"limit is\" limit)\n(eprintln \"careful\")\n(def doubled (double limit))");
+        assert_eq!(result.unwrap(), Value::Num(20));
+        assert_eq!(interpreter.get_global("doubled"), Some(Value::Num(20)));
+        assert_eq!(stdout.take_string(), "limit is 10\n");
+        assert_eq!(stderr.take_string(), "careful\n");
+        interpreter.eval_str("(prn {:a \"b\"}) (pprint [1 2])").unwrap();
+        assert_eq!(stdout.take_string(), "{:a \"b\"}\n[1 2]\n");
+    }
+
+    #[test]
+    fn functions_can_be_called_by_name() {
+        let mut interpreter = Interpreter::new();
+        interpreter.eval_str("(ns text) (def shout (fn [s] (str (upper-case s) \"!\"))) (in-ns user)").unwrap();
+        assert_eq!(interpreter.call("text/shout", &[Value::Str("hi".into())]).unwrap(), Value::Str("HI!".into()));
+        assert_eq!(interpreter.call("+", &[Value::Num(1), Value::Num(2)]).unwrap(), Value::Num(3));
+        assert_matches!(interpreter.call("nothing", &[]), Err(InterpreterError::Eval(TracedError { error: EvalError::UnableToEvalFunction { .. }, .. })));
+        assert_matches!(interpreter.eval_str("(+ 1"), Err(InterpreterError::Parse { .. }));
+        let error = interpreter.eval_str("(def f (fn [] (+ 1 :a)))\n(f)").unwrap_err();
+        assert_eq!(error.to_string(), "wrong type of argument: + expects a number, got keyword :a\n    at + (<string>:1:15)\n    at f (<string>:2:1)");
+        assert_matches!(interpreter.eval_file("/nonexistent/script.lsp"), Err(InterpreterError::CannotRead { .. }));
+    }
+}
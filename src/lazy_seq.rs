@@ -0,0 +1,367 @@
+use std::cell::RefCell;
+use std::fmt::Formatter;
+use std::rc::Rc;
+
+use crate::eval::{apply, Arity, EvalError, GlobalNamespace};
+use crate::eval::EvalError::InvalidArguments;
+use crate::value::{PrintMode, Value};
+
+// How many elements of a lazy sequence get realised when printing it
+pub const PRINT_LIMIT: usize = 100;
+
+// A realised step of a sequence: nothing left, or the first element and the sequence of the rest
+pub type Step = Option<(Value, Value)>;
+
+type Thunk = Box<dyn FnOnce() -> Result<Step, EvalError>>;
+
+enum LazyState {
+    Pending(Thunk),
+    Realizing,
+    Realized(Step),
+    // Every later force gets the error back as it was, so it can still be matched and caught
+    Failed(EvalError),
+}
+
+// A sequence whose next step is only computed when somebody asks for it, and then remembered
+pub struct LazySeq {
+    state: RefCell<LazyState>,
+}
+
+impl LazySeq {
+    pub fn from_thunk(thunk: impl FnOnce() -> Result<Step, EvalError> + 'static) -> Value {
+        Value::LazySeq(Rc::new(LazySeq { state: RefCell::new(LazyState::Pending(Box::new(thunk))) }))
+    }
+
+    pub fn cons(first: Value, rest: Value) -> Value {
+        Value::LazySeq(Rc::new(LazySeq { state: RefCell::new(LazyState::Realized(Some((first, rest)))) }))
+    }
+
+    pub fn realize(&self) -> Result<Step, EvalError> {
+        let state = std::mem::replace(&mut *self.state.borrow_mut(), LazyState::Realizing);
+        let (new_state, result) = match state {
+            LazyState::Pending(thunk) => match thunk() {
+                Ok(step) => (LazyState::Realized(step.clone()), Ok(step)),
+                Err(error) => (LazyState::Failed(error.clone()), Err(error)),
+            },
+            LazyState::Realized(step) => (LazyState::Realized(step.clone()), Ok(step)),
+            LazyState::Realizing => (LazyState::Realizing, Err(InvalidArguments("lazy sequence depends on its own value".into()))),
+            LazyState::Failed(error) => (LazyState::Failed(error.clone()), Err(error)),
+        };
+        *self.state.borrow_mut() = new_state;
+        result
+    }
+}
+
+impl Drop for LazySeq {
+    fn drop(&mut self) {
+        // Long realised chains would otherwise be dropped recursively and blow the stack
+        let LazyState::Realized(Some((_, mut rest))) = std::mem::replace(self.state.get_mut(), LazyState::Realizing) else {
+            return;
+        };
+        while let Value::LazySeq(next) = rest {
+            let Ok(mut next) = Rc::try_unwrap(next) else {
+                return;
+            };
+            let LazyState::Realized(Some((_, next_rest))) = std::mem::replace(next.state.get_mut(), LazyState::Realizing) else {
+                return;
+            };
+            rest = next_rest;
+        }
+    }
+}
+
+fn slice_step(items: Rc<[Value]>, index: usize) -> Step {
+    let first = items.get(index)?.clone();
+    let rest = if index + 1 < items.len() {
+        LazySeq::from_thunk(move || Ok(slice_step(items, index + 1)))
+    } else {
+        Value::Nil
+    };
+    Some((first, rest))
+}
+
+// Splits anything sequential into its first element and the rest, realising lazy sequences on the way
+pub fn first_and_rest(value: &Value) -> Result<Step, EvalError> {
+    match value {
+        Value::Nil => Ok(None),
+        Value::LazySeq(lazy_seq) => lazy_seq.realize(),
+        Value::List(list) => Ok(slice_step(list.clone(), 0)),
+        Value::Vector(_) | Value::Map(_) => Ok(slice_step(crate::sequences::items("seq", value)?.into(), 0)),
+        other => Err(InvalidArguments(format!("expected a sequence, got {} {other}", other.type_name()))),
+    }
+}
+
+// Walks a sequence one step at a time, only realising as much as gets consumed
+pub struct SeqIter {
+    current: Value,
+}
+
+impl SeqIter {
+    pub fn new(value: Value) -> SeqIter {
+        SeqIter { current: value }
+    }
+}
+
+impl Iterator for SeqIter {
+    type Item = Result<Value, EvalError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match first_and_rest(&self.current) {
+            Ok(Some((first, rest))) => {
+                self.current = rest;
+                Some(Ok(first))
+            }
+            Ok(None) => None,
+            Err(error) => {
+                self.current = Value::Nil;
+                Some(Err(error))
+            }
+        }
+    }
+}
+
+// Printing realises the sequence, but stops after PRINT_LIMIT elements so infinite ones can be shown
+impl LazySeq {
+    pub fn fmt_with(&self, f: &mut Formatter<'_>, mode: PrintMode) -> std::fmt::Result {
+        write!(f, "(")?;
+        let mut step = self.realize();
+        for index in 0..=PRINT_LIMIT {
+            if index > 0 && !matches!(step, Ok(None)) {
+                write!(f, " ")?;
+            }
+            match step {
+                Ok(Some(_)) if index == PRINT_LIMIT => write!(f, "...")?,
+                Ok(Some((first, rest))) => {
+                    write!(f, "{}", first.printed(mode))?;
+                    step = first_and_rest(&rest);
+                    continue;
+                }
+                Ok(None) => {}
+                // Display can't report evaluation errors, so just show where the sequence broke off
+                Err(_) => write!(f, "...")?,
+            }
+            break;
+        }
+        write!(f, ")")?;
+        Ok(())
+    }
+}
+
+pub fn define_builtins(namespace: &mut GlobalNamespace) {
+    namespace.defn(b"range", lisp_range.into());
+    namespace.defn(b"iterate", lisp_iterate.into());
+    namespace.defn(b"repeat", lisp_repeat.into());
+    namespace.defn(b"take", lisp_take.into());
+    namespace.defn(b"drop", lisp_drop.into());
+    namespace.defn(b"take-while", lisp_take_while.into());
+    namespace.defn(b"cons", lisp_cons.into());
+    namespace.defn(b"first", lisp_first.into());
+    namespace.defn(b"rest", lisp_rest.into());
+}
+
+fn expect_num(function_name: &str, value: &Value) -> Result<isize, EvalError> {
+    value.num().ok_or_else(|| EvalError::wrong_type(function_name, "a number", value))
+}
+
+fn range(start: isize, end: Option<isize>, step: isize) -> Value {
+    LazySeq::from_thunk(move || {
+        let finished = match end {
+            Some(end) if step > 0 => start >= end,
+            Some(end) => start <= end,
+            None => false,
+        };
+        if finished {
+            return Ok(None);
+        }
+        // The sequence ends where the next number would no longer fit
+        let rest = start.checked_add(step).map_or(Value::Nil, |next| range(next, end, step));
+        Ok(Some((Value::Num(start), rest)))
+    })
+}
+
+fn lisp_range(arguments: &[Value]) -> Result<Value, EvalError> {
+    let numbers = arguments.iter().map(|argument| expect_num("range", argument)).collect::<Result<Vec<isize>, EvalError>>()?;
+    let (start, end, step) = match numbers[..] {
+        [] => (0, None, 1),
+        [end] => (0, Some(end), 1),
+        [start, end] => (start, Some(end), 1),
+        [start, end, step] => (start, Some(end), step),
+        _ => return Err(EvalError::wrong_arity("range", Arity::Between(0, 3), arguments.len())),
+    };
+    if step == 0 {
+        return Err(InvalidArguments("range expects a non-zero step".into()));
+    }
+    Ok(range(start, end, step))
+}
+
+fn iterate(function: Value, value: Value) -> Value {
+    LazySeq::cons(value.clone(), LazySeq::from_thunk(move || {
+        let next_value = apply(&function, &[value])?;
+        first_and_rest(&iterate(function, next_value))
+    }))
+}
+
+fn lisp_iterate(arguments: &[Value]) -> Result<Value, EvalError> {
+    let [function, value] = arguments else {
+        return Err(EvalError::wrong_arity("iterate", Arity::Exactly(2), arguments.len()));
+    };
+    Ok(iterate(function.clone(), value.clone()))
+}
+
+fn repeat(value: Value, times: Option<usize>) -> Value {
+    LazySeq::from_thunk(move || match times {
+        Some(0) => Ok(None),
+        _ => Ok(Some((value.clone(), repeat(value, times.map(|times| times - 1))))),
+    })
+}
+
+fn lisp_repeat(arguments: &[Value]) -> Result<Value, EvalError> {
+    match arguments {
+        [value] => Ok(repeat(value.clone(), None)),
+        [times, value] => Ok(repeat(value.clone(), Some(expect_num("repeat", times)?.max(0) as usize))),
+        _ => Err(EvalError::wrong_arity("repeat", Arity::Between(1, 2), arguments.len())),
+    }
+}
+
+fn take(count: usize, collection: Value) -> Value {
+    LazySeq::from_thunk(move || {
+        if count == 0 {
+            return Ok(None);
+        }
+        Ok(first_and_rest(&collection)?.map(|(first, rest)| (first, take(count - 1, rest))))
+    })
+}
+
+fn lisp_take(arguments: &[Value]) -> Result<Value, EvalError> {
+    let [count, collection] = arguments else {
+        return Err(EvalError::wrong_arity("take", Arity::Exactly(2), arguments.len()));
+    };
+    Ok(take(expect_num("take", count)?.max(0) as usize, collection.clone()))
+}
+
+fn lisp_drop(arguments: &[Value]) -> Result<Value, EvalError> {
+    let [count, collection] = arguments else {
+        return Err(EvalError::wrong_arity("drop", Arity::Exactly(2), arguments.len()));
+    };
+    let count = expect_num("drop", count)?.max(0) as usize;
+    let collection = collection.clone();
+    Ok(LazySeq::from_thunk(move || {
+        let mut current = collection;
+        for _ in 0..count {
+            match first_and_rest(&current)? {
+                Some((_, rest)) => current = rest,
+                None => return Ok(None),
+            }
+        }
+        first_and_rest(&current)
+    }))
+}
+
+fn take_while(predicate: Value, collection: Value) -> Value {
+    LazySeq::from_thunk(move || match first_and_rest(&collection)? {
+        Some((first, rest)) if apply(&predicate, std::slice::from_ref(&first))?.is_truthy() => {
+            Ok(Some((first, take_while(predicate, rest))))
+        }
+        _ => Ok(None),
+    })
+}
+
+fn lisp_take_while(arguments: &[Value]) -> Result<Value, EvalError> {
+    let [predicate, collection] = arguments else {
+        return Err(EvalError::wrong_arity("take-while", Arity::Exactly(2), arguments.len()));
+    };
+    Ok(take_while(predicate.clone(), collection.clone()))
+}
+
+fn lisp_cons(arguments: &[Value]) -> Result<Value, EvalError> {
+    let [first, rest] = arguments else {
+        return Err(EvalError::wrong_arity("cons", Arity::Exactly(2), arguments.len()));
+    };
+    Ok(LazySeq::cons(first.clone(), rest.clone()))
+}
+
+fn lisp_first(arguments: &[Value]) -> Result<Value, EvalError> {
+    let [collection] = arguments else {
+        return Err(EvalError::wrong_arity("first", Arity::Exactly(1), arguments.len()));
+    };
+    Ok(first_and_rest(collection)?.map(|(first, _)| first).unwrap_or(Value::Nil))
+}
+
+fn lisp_rest(arguments: &[Value]) -> Result<Value, EvalError> {
+    let [collection] = arguments else {
+        return Err(EvalError::wrong_arity("rest", Arity::Exactly(1), arguments.len()));
+    };
+    Ok(first_and_rest(collection)?.map(|(_, rest)| rest).unwrap_or_else(|| Value::List([].into())))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::assert_matches;
+
+    use crate::test_support::eval_str;
+
+    use super::*;
+
+    #[test]
+    fn infinite_sequences_can_be_taken_from() {
+        let namespace = &mut GlobalNamespace::default();
+        assert_eq!(eval_str("(take 5 (range))", namespace).unwrap().to_string(), "(0 1 2 3 4)");
+        assert_eq!(eval_str("(take 4 (iterate (fn [x] (* x 2)) 1))", namespace).unwrap().to_string(), "(1 2 4 8)");
+        assert_eq!(eval_str("(take 2 (repeat :a))", namespace).unwrap().to_string(), "(:a :a)");
+        assert_eq!(eval_str("(take-while (fn [x] (< x 3)) (drop 1 (range)))", namespace).unwrap().to_string(), "(1 2)");
+    }
+
+    #[test]
+    fn lazy_seq_bodies_are_only_evaluated_when_realised() {
+        let namespace = &mut GlobalNamespace::default();
+        assert_eq!(eval_str("(do (lazy-seq (undefined-function)) 1)", namespace).unwrap(), Value::Num(1));
+        assert_eq!(eval_str("(first (cons 1 (lazy-seq (undefined-function))))", namespace).unwrap(), Value::Num(1));
+        eval_str("(def ones (fn [] (lazy-seq (cons 1 (ones)))))", namespace).unwrap();
+        assert_eq!(eval_str("(take 3 (ones))", namespace).unwrap().to_string(), "(1 1 1)");
+        assert_eq!(eval_str("(take 3 (map (fn [x] (* x x)) (range)))", namespace).unwrap().to_string(), "(0 1 4)");
+        assert_eq!(eval_str("(take 2 (filter (fn [x] (> x 10)) (range)))", namespace).unwrap().to_string(), "(11 12)");
+    }
+
+    #[test]
+    fn failed_sequences_give_back_the_original_error() {
+        let namespace = &mut GlobalNamespace::default();
+        eval_str("(def broken (lazy-seq (+ 1 :a)))", namespace).unwrap();
+        for _ in 0..2 {
+            assert_matches!(eval_str("(first broken)", namespace), Err(EvalError::WrongType { function, .. }) if function == "+");
+        }
+        eval_str("(def thrown (lazy-seq (throw {:type :boom})))", namespace).unwrap();
+        for _ in 0..2 {
+            assert_eq!(eval_str("(try* (first thrown) (catch* e (get e :type)))", namespace).unwrap().to_string(), ":boom");
+        }
+    }
+
+    #[test]
+    fn ranges_end_before_overflowing() {
+        let namespace = &mut GlobalNamespace::default();
+        assert_eq!(eval_str("(take 3 (range 9223372036854775806 9223372036854775807 5))", namespace).unwrap().to_string(), "(9223372036854775806)");
+        assert_eq!(eval_str("(take 3 (drop 1 (range 9223372036854775805 9223372036854775807 1)))", namespace).unwrap().to_string(), "(9223372036854775806)");
+        // An unbounded range can only start from 0, so it takes the function to get near the end quickly
+        assert_eq!(SeqIter::new(range(isize::MAX - 1, None, 1)).count(), 2);
+    }
+
+    #[test]
+    fn printing_stops_at_the_limit() {
+        let namespace = &mut GlobalNamespace::default();
+        let printed = eval_str("(range)", namespace).unwrap().to_string();
+        assert!(printed.starts_with("(0 1 2"));
+        assert!(printed.ends_with(" 99 ...)"));
+    }
+
+    #[test]
+    fn lazy_and_eager_sequences_compare_equal() {
+        let namespace = &mut GlobalNamespace::default();
+        assert_eq!(eval_str("(= (take 3 (range)) (list 0 1 2))", namespace).unwrap(), Value::Bool(true));
+        assert_eq!(eval_str("(= (range 3) (list 0 1))", namespace).unwrap(), Value::Bool(false));
+    }
+
+    #[test]
+    fn long_realised_chains_drop_without_overflowing() {
+        let namespace = &mut GlobalNamespace::default();
+        assert_eq!(eval_str("(reduce + (take 200000 (range)))", namespace).unwrap(), Value::Num(19999900000));
+    }
+}
@@ -1,8 +1,34 @@
-#![feature(slice_split_once)]
-#![feature(byte_slice_trim_ascii)]
-#![feature(assert_matches)]
-#![feature(ascii_char)]
+#![cfg_attr(test, feature(ascii_char))]
+
+// The derive macros refer to ::jirsp, which has to work inside this crate too
+extern crate self as jirsp;
 
 pub mod tokenize;
 pub mod parse_error;
-pub mod eval;
\ No newline at end of file
+pub mod eval;
+pub mod convert;
+pub mod symbol;
+pub mod value;
+pub mod persistent_vector;
+pub mod persistent_map;
+mod collections;
+mod sequences;
+pub mod lazy_seq;
+mod strings;
+pub mod pattern;
+mod format;
+pub mod pretty;
+pub mod cst;
+pub mod formatter;
+pub mod exception;
+pub mod backtrace;
+mod condition;
+pub mod atom;
+pub mod host;
+pub mod loader;
+pub mod manifest;
+pub mod interpreter;
+pub mod namespace;
+mod suggestions;
+#[cfg(test)]
+mod test_support;
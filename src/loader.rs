@@ -0,0 +1,208 @@
+use std::cell::RefCell;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use crate::atom::Atom;
+use crate::eval::{eval_in, Arity, EvalError, GlobalNamespace, Scope, WeakNamespace};
+use crate::eval::EvalError::{CannotLoad, CircularRequire, InvalidArguments, ModuleNotFound};
+use crate::symbol::Symbol;
+use crate::tokenize::tokenize_all;
+use crate::value::Value;
+
+// The directories require looks for modules in, an atom holding a vector of strings so scripts can
+// change it with (swap! *load-path* conj "lib")
+pub const LOAD_PATH: &[u8] = b"*load-path*";
+const EXTENSION: &str = "lsp";
+
+#[derive(Default)]
+struct Modules {
+    // Logical names of the modules required so far, each one is only loaded once
+    loaded: Vec<String>,
+    // The files being loaded right now as (what to call them in errors, canonical path), innermost last
+    loading: Vec<(String, PathBuf)>,
+}
+
+pub fn define_builtins(namespace: &mut GlobalNamespace) {
+    namespace.def(LOAD_PATH, Value::Atom(Rc::new(Atom::new(Value::Vector(Default::default())))));
+    let modules = Rc::new(RefCell::new(Modules::default()));
+    let (weak_namespace, load_modules) = (namespace.downgrade(), modules.clone());
+    namespace.defn(b"load-file", (move |arguments: &[Value]| lisp_load_file(&weak_namespace, &load_modules, arguments)).into());
+    let weak_namespace = namespace.downgrade();
+    namespace.defn(b"require", (move |arguments: &[Value]| lisp_require(&weak_namespace, &modules, arguments)).into());
+}
+
+// Appends a directory to the load path, for whoever runs the interpreter
+pub fn add_search_path(namespace: &mut GlobalNamespace, directory: &Path) {
+    let Some(Value::Atom(load_path)) = namespace.get(Symbol::intern(LOAD_PATH)) else {
+        return;
+    };
+    let Value::Vector(directories) = load_path.get() else {
+        return;
+    };
+    load_path.set(Value::Vector(directories.push_back(Value::Str(directory.to_string_lossy().into()))));
+}
+
+fn cannot_load(path: &Path, reason: impl ToString) -> EvalError {
+    CannotLoad { path: path.display().to_string(), reason: reason.to_string() }
+}
+
+// Evaluates every form of the file into the namespace and returns the value of the last one
+fn load(namespace: &mut GlobalNamespace, modules: &RefCell<Modules>, label: String, path: &Path) -> Result<Value, EvalError> {
+    let canonical_path = path.canonicalize().map_err(|error| cannot_load(path, error))?;
+    let loading_index = modules.borrow().loading.iter().position(|(_, loading)| *loading == canonical_path);
+    if let Some(index) = loading_index {
+        let cycle = modules.borrow().loading[index..].iter().map(|(label, _)| label.clone()).chain([label]).collect();
+        return Err(CircularRequire { cycle });
+    }
+    let source = fs::read(path).map_err(|error| cannot_load(path, error))?;
+    let nodes = tokenize_all(&source, path.display().to_string().into()).map_err(|error| cannot_load(path, error))?;
+    modules.borrow_mut().loading.push((label, canonical_path));
+    // The modules borrow is released while evaluating, since the file can load more files. An ns form
+    // in the file only changes the current namespace until the file is done
+    let result = namespace.within(namespace.current_namespace(), |namespace| {
+        nodes.iter().try_fold(Value::Nil, |_, node| eval_in(node, &Scope::default(), namespace))
+    });
+    modules.borrow_mut().loading.pop();
+    result
+}
+
+// Relative paths are relative to the working directory
+fn lisp_load_file(namespace: &WeakNamespace, modules: &RefCell<Modules>, arguments: &[Value]) -> Result<Value, EvalError> {
+    let [Value::Str(path)] = arguments else {
+        return Err(match arguments {
+            [other] => EvalError::wrong_type("load-file", "a path as string", other),
+            _ => EvalError::wrong_arity("load-file", Arity::Exactly(1), arguments.len()),
+        });
+    };
+    load(&mut namespace.upgrade()?, modules, path.to_string(), Path::new(&**path))
+}
+
+fn module_name(value: &Value) -> Result<String, EvalError> {
+    match value {
+        Value::Str(name) => Ok(name.to_string()),
+        Value::Keyword(name) => Ok(name.to_string()),
+        other => Err(EvalError::wrong_type("require", "a module name as string or keyword", other)),
+    }
+}
+
+// A module named "utils.strings" is the file utils/strings.lsp in the first directory of the load
+// path that has it. With (require :utils.strings :as :s) the namespace utils.strings can be
+// referred to as s in the requiring namespace
+fn lisp_require(namespace: &WeakNamespace, modules: &RefCell<Modules>, arguments: &[Value]) -> Result<Value, EvalError> {
+    let (name, alias) = match arguments {
+        [name] => (module_name(name)?, None),
+        [name, Value::Keyword(option), alias] if option.name() == "as" => (module_name(name)?, Some(module_name(alias)?)),
+        [_, _, _] => return Err(InvalidArguments("require expects a module name, optionally followed by :as and an alias".into())),
+        _ => return Err(EvalError::wrong_arity("require", Arity::Between(1, 3), arguments.len())),
+    };
+    let mut namespace = namespace.upgrade()?;
+    if !modules.borrow().loaded.contains(&name) {
+        load_module(&mut namespace, modules, &name)?;
+    }
+    // Only a module that loaded gets an alias, a failed require leaves the namespace as it was
+    if let Some(alias) = alias {
+        namespace.add_alias(Symbol::intern(alias.as_bytes()), Symbol::intern(name.as_bytes()));
+    }
+    Ok(Value::Nil)
+}
+
+fn load_module(namespace: &mut GlobalNamespace, modules: &RefCell<Modules>, name: &str) -> Result<(), EvalError> {
+    let relative_path = PathBuf::from(name.replace('.', "/")).with_extension(EXTENSION);
+    let load_path = match namespace.get(Symbol::intern(LOAD_PATH)) {
+        Some(Value::Atom(load_path)) => Some(load_path.get()),
+        other => other,
+    };
+    let candidates: Vec<PathBuf> = match load_path {
+        Some(Value::Vector(load_path)) => load_path.iter().map(|directory| match directory {
+            Value::Str(directory) => Ok(Path::new(&**directory).join(&relative_path)),
+            other => Err(EvalError::wrong_type("require", "only strings in *load-path*", other)),
+        }).collect::<Result<_, EvalError>>()?,
+        Some(other) => return Err(EvalError::wrong_type("require", "*load-path* to hold a vector", &other)),
+        None => vec![],
+    };
+    let Some(path) = candidates.iter().find(|candidate| candidate.is_file()) else {
+        return Err(ModuleNotFound { name: name.into(), searched: candidates.iter().map(|candidate| candidate.display().to_string()).collect() });
+    };
+    load(namespace, modules, name.into(), path)?;
+    modules.borrow_mut().loaded.push(name.into());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::assert_matches;
+
+    use crate::test_support::{eval_str, TempDir};
+
+    use super::*;
+
+    // A fresh directory with the given files, and a namespace that has it on its load path
+    fn project(name: &str, files: &[(&str, &str)]) -> (TempDir, GlobalNamespace) {
+        let directory = TempDir::with_files(&format!("loader-{name}"), files);
+        let mut namespace = GlobalNamespace::default();
+        add_search_path(&mut namespace, &directory);
+        (directory, namespace)
+    }
+
+    #[test]
+    fn modules_are_found_on_the_load_path_and_loaded_once() {
+        let (directory, namespace) = &mut project("once", &[
+            ("utils/strings.lsp", "; shout\n(def loads (+ loads 1))\n(def shout\n  (fn [s] (str (upper-case s) \"!\")))"),
+            ("main.lsp", "(def x 1)\n(+ x 1)"),
+        ]);
+        eval_str("(def loads 0)", namespace).unwrap();
+        eval_str("(require :utils.strings)", namespace).unwrap();
+        eval_str("(require \"utils.strings\")", namespace).unwrap();
+        assert_eq!(eval_str("loads", namespace).unwrap(), Value::Num(1));
+        assert_eq!(eval_str("(shout \"hi\")", namespace).unwrap(), Value::Str("HI!".into()));
+        let main = directory.join("main.lsp").display().to_string();
+        assert_eq!(eval_str(&format!("(load-file {main:?})"), namespace).unwrap(), Value::Num(2));
+    }
+
+    #[test]
+    fn cycles_and_missing_files_name_the_files() {
+        let (directory, namespace) = &mut project("cycle", &[
+            ("a.lsp", "(require :b)"),
+            ("b.lsp", "(def b 1)\n(require :a)"),
+            ("broken.lsp", "(def x (+ 1 2)"),
+        ]);
+        assert_eq!(eval_str("(require :a)", namespace).unwrap_err().to_string(), "circular require: a -> b -> a");
+        assert_matches!(eval_str("(require :missing)", namespace), Err(ModuleNotFound { searched, .. }) if searched.len() == 1);
+        let broken = directory.join("broken.lsp").display().to_string();
+        assert_eq!(eval_str(&format!("(load-file {broken:?})"), namespace).unwrap_err().to_string(),
+                   format!("cannot load {broken}: missing right parenthesis in S expression"));
+    }
+
+    #[test]
+    fn required_namespaces_can_be_aliased() {
+        let (_, namespace) = &mut project("alias", &[
+            ("text/shout.lsp", "(ns text.shout)\n(def- suffix \"!\")\n(def shout (fn [s] (str (upper-case s) suffix)))"),
+        ]);
+        eval_str("(require :text.shout :as :t)", namespace).unwrap();
+        assert_eq!(eval_str("(t/shout \"hi\")", namespace).unwrap(), Value::Str("HI!".into()));
+        // The ns form in the file doesn't leak out of it
+        assert_matches!(eval_str("shout", namespace), Err(EvalError::UnboundSymbol { .. }));
+        assert_matches!(eval_str("t/suffix", namespace), Err(EvalError::PrivateSymbol { .. }));
+        eval_str("(swap! *load-path* conj \"elsewhere\")", namespace).unwrap();
+        assert_matches!(eval_str("(require :missing)", namespace), Err(ModuleNotFound { searched, .. }) if searched.len() == 2);
+    }
+
+    #[test]
+    fn failed_requires_leave_no_alias() {
+        let (_, namespace) = &mut project("failed-alias", &[
+            ("broken.lsp", "(ns broken)
+(def x (+ 1 2)"),
+            ("fine.lsp", "(ns fine)
+(def x 1)"),
+        ]);
+        assert_matches!(eval_str("(require :missing :as :m)", namespace), Err(ModuleNotFound { .. }));
+        // Even once there is a namespace by the module's name
+        eval_str("(in-ns missing)\n(def x 1)\n(in-ns user)", namespace).unwrap();
+        assert_matches!(eval_str("m/x", namespace), Err(EvalError::UnknownNamespace(alias)) if alias == "m");
+        assert_matches!(eval_str("(require :broken :as :b)", namespace), Err(CannotLoad { .. }));
+        assert_matches!(eval_str("b/x", namespace), Err(EvalError::UnknownNamespace(alias)) if alias == "b");
+        eval_str("(require :fine :as :f)", namespace).unwrap();
+        assert_eq!(eval_str("f/x", namespace).unwrap(), Value::Num(1));
+    }
+}
@@ -1,16 +1,21 @@
-#![feature(try_trait_v2)]
-#![feature(byte_slice_trim_ascii)]
-#![feature(slice_split_once)]
 
 use std::{env, io};
 use std::error::Error;
-use std::fs::File;
-use std::io::{BufRead, BufReader, Write};
+use std::fs;
+use std::io::{BufRead, Write};
+use std::path::{Path, PathBuf};
 use std::process::ExitCode;
+use std::rc::Rc;
 
-use jirsp::eval::{eval, EvalError, GlobalNamespace};
+use jirsp::backtrace::TracedError;
+use jirsp::eval::{eval_traced, GlobalNamespace};
+use jirsp::formatter::format_source;
+use jirsp::loader::add_search_path;
+use jirsp::manifest::{find_manifest, Manifest};
 use jirsp::parse_error::ParseError;
-use jirsp::tokenize::{AstNode, tokenize, Value};
+use jirsp::pretty::{pretty_ast, pretty_value, PrettyOptions};
+use jirsp::tokenize::{AstNode, tokenize_all, tokenize_source};
+use jirsp::value::{PrintMode, Value};
 use jirsp::tokenize::AstToken::Parsed;
 
 use crate::result::RispError;
@@ -18,15 +23,33 @@ use crate::result::RispError;
 mod result;
 
 
-fn get_input_handle(arguments: &[String]) -> Result<Box<dyn BufRead>, RispError> {
+// A leading --print=readable (the default) or --print=display picks how the REPL shows results
+fn get_print_mode(arguments: &[String]) -> Result<(PrintMode, &[String]), RispError> {
+    let Some(mode) = arguments.first().and_then(|argument| argument.strip_prefix("--print=")) else {
+        return Ok((PrintMode::Readable, arguments));
+    };
+    match mode {
+        "readable" => Ok((PrintMode::Readable, &arguments[1..])),
+        "display" => Ok((PrintMode::Display, &arguments[1..])),
+        other => Err(RispError::UnknownPrintMode(other.to_string())),
+    }
+}
+
+// The REPL reads stdin a line at a time, a file is read whole since its forms can span lines
+enum Input {
+    Repl(Box<dyn BufRead>),
+    File(Vec<u8>),
+}
+
+fn get_input(arguments: &[String]) -> Result<Input, RispError> {
     match arguments {
         [] => {
             let stdin_lock = io::stdin().lock();
-            Ok(Box::new(stdin_lock))
+            Ok(Input::Repl(Box::new(stdin_lock)))
         }
         [filepath] => {
-            let f = File::open(filepath).map_err(RispError::from)?;
-            Ok(Box::new(BufReader::new(f)))
+            let source = fs::read(filepath).map_err(RispError::from)?;
+            Ok(Input::File(source))
         }
         _ => {
             Err(RispError::TooManyArguments(arguments.len()))
@@ -34,8 +57,31 @@ fn get_input_handle(arguments: &[String]) -> Result<Box<dyn BufRead>, RispError>
     }
 }
 
-fn read(reader: &mut dyn BufRead) -> Option<Box<[u8]>> {
-    print!("user>");
+// Backtraces name the file being run, or the REPL when reading from stdin
+fn source_name(arguments: &[String]) -> Rc<str> {
+    arguments.first().map_or("repl", String::as_str).into()
+}
+
+// require looks next to the program being run (in the working directory for the REPL), then in
+// the package it is part of and that package's dependencies, then in the directories listed in RISP_PATH
+fn load_path(arguments: &[String]) -> Result<Vec<PathBuf>, RispError> {
+    let program_directory = arguments.first()
+        .and_then(|path| Path::new(path).parent())
+        .filter(|directory| !directory.as_os_str().is_empty())
+        .map_or_else(|| PathBuf::from("."), Path::to_path_buf);
+    let package = match find_manifest(&program_directory) {
+        Some(manifest_path) => Manifest::read(&manifest_path)?.load_path()?,
+        None => vec![],
+    };
+    let from_environment = env::var_os("RISP_PATH")
+        .map(|paths| env::split_paths(&paths).collect())
+        .unwrap_or_default();
+    Ok([vec![program_directory], package, from_environment].concat())
+}
+
+// The prompt is the name of the current namespace
+fn read(reader: &mut dyn BufRead, namespace: &GlobalNamespace) -> Option<Box<[u8]>> {
+    print!("{}>", namespace.current_namespace());
     io::stdout().flush().unwrap();
     let mut line: Vec<u8> = vec![];
     let char_count = reader.read_until(b'\n', &mut line).expect("For now, lets assume there is a line");
@@ -43,56 +89,144 @@ fn read(reader: &mut dyn BufRead) -> Option<Box<[u8]>> {
         // EOF reached
         None
     } else {
-        let trimmed = line.trim_ascii();
+        // Leading whitespace stays, so columns in backtraces match the input
+        let trimmed = line.trim_ascii_end();
         Some(trimmed.into())
     }
 }
 
-fn parse(line: &[u8]) -> Result<AstNode, ParseError> {
-    if let Parsed(node) = tokenize(line)? {
+fn parse(line: &[u8], source_name: &Rc<str>, line_number: usize) -> Result<AstNode, ParseError> {
+    if let Parsed(node) = tokenize_source(line, source_name.clone(), line_number)? {
         Ok(node)
     } else {
         Err(ParseError::NotAnSExpression)
     }
 }
 
-fn print(eval_result: &Result<Value, impl Error>) {
+fn print(eval_result: &Result<Value, impl Error>, options: &PrettyOptions) {
     match eval_result {
-        Ok(ref value) => println!("{}", value),
+        Ok(ref value) => println!("{}", pretty_value(value, options)),
         Err(ref parse_error) => println!("{}", parse_error)
     };
 }
 
-fn print_debug(eval_result: &Result<AstNode, impl Error>) {
+fn print_debug(eval_result: &Result<AstNode, impl Error>, options: &PrettyOptions) {
     match eval_result {
-        Ok(ref ast_node) => println!("{:?}", ast_node),
+        Ok(ref ast_node) => println!("{}", pretty_ast(ast_node, options)),
         Err(ref parse_error) => println!("{}", parse_error)
     };
 }
 
-fn risp(mut input_handle: Box<dyn BufRead>) {
-    let mut namespace = GlobalNamespace::default();
-    while let Some(line) = read(&mut input_handle) {
-        let result: Result<AstNode, ParseError> = parse(&line);
-        print_debug(&result);
+fn eval_and_print(node: &AstNode, namespace: &mut GlobalNamespace, options: &PrettyOptions) {
+    let result: Result<Value, TracedError> = eval_traced(node, namespace);
+    print(&result, options)
+}
+
+// A file that doesn't parse runs none of its forms
+fn run_file(source: &[u8], source_name: Rc<str>, namespace: &mut GlobalNamespace, options: &PrettyOptions) {
+    let nodes = match tokenize_all(source, source_name) {
+        Ok(nodes) => nodes,
+        Err(error) => return println!("{}", error),
+    };
+    for node in &nodes {
+        println!("{}", pretty_ast(node, options));
+        eval_and_print(node, namespace, options);
+    }
+}
+
+fn repl(mut input_handle: Box<dyn BufRead>, source_name: Rc<str>, namespace: &mut GlobalNamespace, options: &PrettyOptions) {
+    let mut line_number = 0;
+    while let Some(line) = read(&mut input_handle, namespace) {
+        line_number += 1;
+        let result: Result<AstNode, ParseError> = parse(&line, &source_name, line_number);
+        print_debug(&result, options);
         let Ok(node) = result else {
             continue;
         };
-        let result: Result<Value, EvalError> = eval(&node, &mut namespace);
-        print(&result)
+        eval_and_print(&node, namespace, options);
+    };
+}
+
+fn risp(input: Input, source_name: Rc<str>, load_path: &[PathBuf], print_mode: PrintMode) {
+    let mut namespace = GlobalNamespace::default();
+    load_path.iter().for_each(|directory| add_search_path(&mut namespace, directory));
+    let options = PrettyOptions { mode: print_mode, ..PrettyOptions::default() };
+    match input {
+        Input::Repl(input_handle) => repl(input_handle, source_name, &mut namespace, &options),
+        Input::File(source) => run_file(&source, source_name, &mut namespace, &options),
+    }
+}
+
+// Rewrites the files in place, or with --check only lists the ones that aren't formatted and
+// returns false if there are any
+fn fmt(arguments: &[String]) -> Result<bool, RispError> {
+    let (check, paths) = match arguments {
+        [flag, paths @ ..] if flag == "--check" => (true, paths),
+        paths => (false, paths),
     };
+    if paths.is_empty() {
+        return Err(RispError::NothingToFormat);
+    }
+    let mut all_formatted = true;
+    for path in paths {
+        let source = fs::read(path)?;
+        let formatted = format_source(&source).map_err(|error| RispError::CannotFormat(path.clone(), error))?;
+        if formatted.as_bytes() == source {
+            continue;
+        }
+        all_formatted = false;
+        if check {
+            println!("{} is not formatted", path);
+        } else {
+            fs::write(path, formatted)?;
+        }
+    }
+    Ok(!check || all_formatted)
 }
 
 fn main() -> ExitCode {
     let arguments: Vec<String> = env::args().collect();
-    match get_input_handle(&arguments[1..]) {
+    if arguments.get(1).is_some_and(|command| command == "fmt") {
+        return match fmt(&arguments[2..]) {
+            Ok(true) => ExitCode::SUCCESS,
+            Ok(false) => ExitCode::FAILURE,
+            Err(error) => {
+                println!("{}", error);
+                ExitCode::FAILURE
+            }
+        };
+    }
+    let input = get_print_mode(&arguments[1..])
+        .and_then(|(print_mode, arguments)| Ok((print_mode, source_name(arguments), load_path(arguments)?, get_input(arguments)?)));
+    match input {
         Err(error) => {
             println!("{}", error);
             ExitCode::FAILURE
         }
-        Ok(input_handle) => {
-            risp(input_handle);
+        Ok((print_mode, source_name, load_path, input)) => {
+            risp(input, source_name, &load_path, print_mode);
             ExitCode::SUCCESS
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use jirsp::symbol::Symbol;
+
+    use super::*;
+
+    #[test]
+    fn formatted_files_run_with_forms_across_lines() {
+        let source = b"(def area (fn [width height] (let [product (* width height)] (if (< product 0) (- 0 product) product))))\n(def result (area 3 (+ 1 1)))\n(def broken (fn [] (+ 1 :a)))";
+        let formatted = format_source(source).unwrap();
+        assert!(formatted.lines().count() > 3);
+        let mut namespace = GlobalNamespace::default();
+        run_file(formatted.as_bytes(), "c.lsp".into(), &mut namespace, &PrettyOptions::default());
+        assert_eq!(namespace.get(Symbol::intern(b"result")), Some(Value::Num(6)));
+        let broken = tokenize_all(b"(broken)", "c.lsp".into()).unwrap();
+        let error = eval_traced(&broken[0], &mut namespace).unwrap_err();
+        let span = error.backtrace[0].span.as_ref().unwrap();
+        assert_eq!((span.line, &*span.source.clone().unwrap()), (formatted.lines().position(|line| line.contains(":a")).unwrap() + 1, "c.lsp"));
+    }
+}
@@ -0,0 +1,244 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+use thiserror::Error;
+use toml::Table;
+
+// A package is a directory with a risp.toml like
+//
+//   [package]
+//   name = "app"
+//   source-roots = ["src", "lib"]
+//
+//   [dependencies]
+//   utils = { path = "../utils" }
+//   json = { path = "vendor/json" }
+//
+// Dependencies are only ever directories on disk, there is no registry to fetch them from. Any other
+// keys and sections, like a version, are fine but go unused
+pub const MANIFEST_FILE: &str = "risp.toml";
+
+#[derive(Error, Debug)]
+pub enum ManifestError {
+    #[error("cannot read {path}: {reason}")]
+    CannotRead { path: String, reason: String },
+    #[error("{path}:{line}: {message}")]
+    Syntax { path: String, line: usize, message: String },
+    #[error("{path}: dependency {name}: {message}")]
+    InvalidDependency { path: String, name: String, message: String },
+    #[error("{path}: the package has no name")]
+    MissingName { path: String },
+    #[error("dependency {name} of package {package} is not a directory: {path}")]
+    DependencyNotFound { package: String, name: String, path: String },
+    #[error("dependency {name} of package {package} is the package {actual}")]
+    NameMismatch { package: String, name: String, actual: String },
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Dependency {
+    pub name: String,
+    // Relative paths in the manifest are relative to the directory it is in
+    pub path: PathBuf,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Manifest {
+    pub name: String,
+    // Where the manifest is
+    pub directory: PathBuf,
+    // Where require looks for the package's own modules, the package directory if none are given
+    pub source_roots: Vec<PathBuf>,
+    pub dependencies: Vec<Dependency>,
+}
+
+// The parts of risp.toml a manifest is made of, any other keys and sections are left alone
+#[derive(Deserialize)]
+struct ManifestFile {
+    package: Option<PackageTable>,
+    // A table that keeps the order dependencies are written in, which is the order they are searched
+    #[serde(default)]
+    dependencies: Table,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct PackageTable {
+    name: Option<String>,
+    source_roots: Option<Vec<PathBuf>>,
+}
+
+#[derive(Deserialize)]
+#[serde(expecting = "a table like { path = \"...\" }")]
+struct DependencyTable {
+    path: PathBuf,
+}
+
+impl Manifest {
+    pub fn read(path: &Path) -> Result<Manifest, ManifestError> {
+        let source = fs::read_to_string(path)
+            .map_err(|error| ManifestError::CannotRead { path: path.display().to_string(), reason: error.to_string() })?;
+        Manifest::parse(&source, path)
+    }
+
+    // The path is where the manifest is, for error messages and to resolve relative paths against
+    pub fn parse(source: &str, path: &Path) -> Result<Manifest, ManifestError> {
+        let directory = path.parent().unwrap_or(Path::new("."));
+        let file: ManifestFile = toml::from_str(source).map_err(|error| ManifestError::Syntax {
+            path: path.display().to_string(),
+            line: error.span().map_or(1, |span| source[..span.start].matches('\n').count() + 1),
+            message: error.message().into(),
+        })?;
+        let package = file.package.unwrap_or(PackageTable { name: None, source_roots: None });
+        let dependencies = file.dependencies.into_iter().map(|(name, dependency)| match dependency.try_into::<DependencyTable>() {
+            Ok(dependency) => Ok(Dependency { path: directory.join(dependency.path), name }),
+            Err(error) => Err(ManifestError::InvalidDependency { path: path.display().to_string(), name, message: error.message().into() }),
+        }).collect::<Result<_, _>>()?;
+        Ok(Manifest {
+            name: package.name.ok_or_else(|| ManifestError::MissingName { path: path.display().to_string() })?,
+            directory: directory.to_path_buf(),
+            source_roots: match package.source_roots {
+                Some(roots) => roots.iter().map(|root| directory.join(root)).collect(),
+                None => vec![directory.to_path_buf()],
+            },
+            dependencies,
+        })
+    }
+
+    // The directories require searches, in order: the package's own source roots, then those of its
+    // dependencies and theirs, depth first. A package that is depended on twice is only searched once,
+    // and a vendored directory without a manifest is searched as it is
+    pub fn load_path(&self) -> Result<Vec<PathBuf>, ManifestError> {
+        let mut load_path = vec![];
+        // A dependency that leads back to the package itself adds nothing new
+        let mut visited = self.directory.canonicalize().into_iter().collect();
+        self.collect_load_path(&mut load_path, &mut visited)?;
+        Ok(load_path)
+    }
+
+    fn collect_load_path(&self, load_path: &mut Vec<PathBuf>, visited: &mut Vec<PathBuf>) -> Result<(), ManifestError> {
+        load_path.extend(self.source_roots.iter().cloned());
+        for dependency in &self.dependencies {
+            let not_found = || ManifestError::DependencyNotFound {
+                package: self.name.clone(),
+                name: dependency.name.clone(),
+                path: dependency.path.display().to_string(),
+            };
+            let directory = dependency.path.canonicalize().map_err(|_| not_found())?;
+            if !directory.is_dir() {
+                return Err(not_found());
+            }
+            if visited.contains(&directory) {
+                continue;
+            }
+            visited.push(directory);
+            let manifest_path = dependency.path.join(MANIFEST_FILE);
+            if !manifest_path.is_file() {
+                load_path.push(dependency.path.clone());
+                continue;
+            }
+            let manifest = Manifest::read(&manifest_path)?;
+            if manifest.name != dependency.name {
+                return Err(ManifestError::NameMismatch { package: self.name.clone(), name: dependency.name.clone(), actual: manifest.name });
+            }
+            manifest.collect_load_path(load_path, visited)?;
+        }
+        Ok(())
+    }
+}
+
+// The manifest of the package the directory is in, looking in its parents too
+pub fn find_manifest(directory: &Path) -> Option<PathBuf> {
+    let directory = directory.canonicalize().ok()?;
+    directory.ancestors().map(|ancestor| ancestor.join(MANIFEST_FILE)).find(|path| path.is_file())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::assert_matches;
+
+    use crate::test_support::TempDir;
+
+    use super::*;
+
+    fn workspace(name: &str, files: &[(&str, &str)]) -> TempDir {
+        TempDir::with_files(&format!("manifest-{name}"), files)
+    }
+
+    #[test]
+    fn parses_packages_and_dependencies() {
+        let manifest = Manifest::parse("\
+# the app
+[package]
+name = \"app\"  # trailing comment
+source-roots = [\"src\", \"lib\",]
+
+[dependencies]
+utils = { path = \"../utils\" }
+", Path::new("app/risp.toml")).unwrap();
+        assert_eq!(manifest, Manifest {
+            name: "app".into(),
+            directory: PathBuf::from("app"),
+            source_roots: vec![PathBuf::from("app/src"), PathBuf::from("app/lib")],
+            dependencies: vec![Dependency { name: "utils".into(), path: PathBuf::from("app/../utils") }],
+        });
+        assert_matches!(Manifest::parse("[package]\n", Path::new("risp.toml")), Err(ManifestError::MissingName { .. }));
+        let error = Manifest::parse("[package]\nname = \"app\"\n[dependencies]\nutils = \"1.0\"", Path::new("risp.toml")).unwrap_err();
+        assert_eq!(error.to_string(), "risp.toml: dependency utils: invalid type: string \"1.0\", expected a table like { path = \"...\" }");
+        assert_matches!(Manifest::parse("[package]\nname = \"app\n", Path::new("risp.toml")), Err(ManifestError::Syntax { line: 2, .. }));
+    }
+
+    #[test]
+    fn accepts_whatever_toml_allows() {
+        let manifest = Manifest::parse("\
+package.name = 'app'
+package.version = 1
+package.source-roots = [
+    'src',
+    \"lib\", # shared code
+]
+[dependencies]
+zebra.path = \"../zebra\"
+alpha = { path = '../alpha', version = \"0.1\" }
+
+[tool.editor]
+indent = 2
+", Path::new("app/risp.toml")).unwrap();
+        assert_eq!(manifest.name, "app");
+        assert_eq!(manifest.source_roots, vec![PathBuf::from("app/src"), PathBuf::from("app/lib")]);
+        let dependencies: Vec<&str> = manifest.dependencies.iter().map(|dependency| dependency.name.as_str()).collect();
+        assert_eq!(dependencies, ["zebra", "alpha"]);
+    }
+
+    #[test]
+    fn load_path_covers_dependencies_once() {
+        let root = workspace("resolve", &[
+            ("app/risp.toml", "[package]\nname = \"app\"\nsource-roots = [\"src\"]\n[dependencies]\nutils = { path = \"../utils\" }\njson = { path = \"vendor/json\" }"),
+            ("app/src/main.lsp", "(require :json)"),
+            ("app/vendor/json/json.lsp", "(def parse 1)"),
+            ("utils/risp.toml", "[package]\nname = \"utils\"\n[dependencies]\njson = { path = \"../app/vendor/json\" }"),
+        ]);
+        let manifest_path = find_manifest(&root.join("app/vendor")).unwrap();
+        assert_eq!(manifest_path, root.canonicalize().unwrap().join("app/risp.toml"));
+        let load_path = Manifest::read(&manifest_path).unwrap().load_path().unwrap();
+        let load_path: Vec<PathBuf> = load_path.iter().map(|directory| directory.canonicalize().unwrap()).collect();
+        let root = root.canonicalize().unwrap();
+        assert_eq!(load_path, vec![root.join("app/src"), root.join("utils"), root.join("app/vendor/json")]);
+
+        let cycle = workspace("cycle", &[
+            ("app/risp.toml", "[package]\nname = \"app\"\nsource-roots = [\"src\"]\n[dependencies]\nutils = { path = \"../utils\" }"),
+            ("utils/risp.toml", "[package]\nname = \"utils\"\n[dependencies]\napp = { path = \"../app\" }"),
+        ]);
+        let load_path = Manifest::read(&cycle.join("app/risp.toml")).unwrap().load_path().unwrap();
+        let load_path: Vec<PathBuf> = load_path.iter().map(|directory| directory.canonicalize().unwrap_or(directory.clone())).collect();
+        let cycle = cycle.canonicalize().unwrap();
+        assert_eq!(load_path, vec![cycle.join("app/src"), cycle.join("utils")]);
+
+        let broken = workspace("broken", &[
+            ("app/risp.toml", "[package]\nname = \"app\"\n[dependencies]\nutils = { path = \"../lib\" }\nmissing = { path = \"nowhere\" }"),
+            ("lib/risp.toml", "[package]\nname = \"lib\""),
+        ]);
+        let manifest = Manifest::read(&broken.join("app/risp.toml")).unwrap();
+        assert_eq!(manifest.load_path().unwrap_err().to_string(), "dependency utils of package app is the package lib");
+    }
+}
@@ -0,0 +1,137 @@
+use std::collections::HashSet;
+
+use crate::eval::EvalError;
+use crate::eval::EvalError::{PrivateSymbol, UnknownNamespace};
+use crate::symbol::{Symbol, SymbolMap};
+use crate::value::Value;
+
+// Where the builtins live, every namespace can use them without qualifying
+pub const CORE: &[u8] = b"core";
+// Where the REPL and programs start out
+pub const USER: &[u8] = b"user";
+
+// The bindings of one namespace and what other namespaces may see of them
+#[derive(Default)]
+struct Namespace {
+    bindings: SymbolMap<Value>,
+    private: HashSet<Symbol>,
+    // Set by (ns name (:export ...)), then only the listed names are public
+    exports: Option<HashSet<Symbol>>,
+    // Short names for other namespaces, added by (require :module :as :alias)
+    aliases: SymbolMap<Symbol>,
+}
+
+impl Namespace {
+    fn is_public(&self, name: Symbol) -> bool {
+        !self.private.contains(&name) && self.exports.as_ref().is_none_or(|exports| exports.contains(&name))
+    }
+}
+
+// Every namespace by name, and the current one that definitions go into
+pub struct Namespaces {
+    table: SymbolMap<Namespace>,
+    pub current: Symbol,
+}
+
+impl Default for Namespaces {
+    fn default() -> Self {
+        let mut table = SymbolMap::default();
+        table.insert(Symbol::intern(CORE), Namespace::default());
+        table.insert(Symbol::intern(USER), Namespace::default());
+        Namespaces { table, current: Symbol::intern(USER) }
+    }
+}
+
+impl Namespaces {
+    // Makes the namespace current, creating it if it doesn't exist yet
+    pub fn switch(&mut self, name: Symbol) {
+        self.table.entry(name).or_default();
+        self.current = name;
+    }
+
+    fn current_namespace(&mut self) -> &mut Namespace {
+        self.table.get_mut(&self.current).expect("The current namespace always exists")
+    }
+
+    pub fn define(&mut self, name: Symbol, value: Value, private: bool) {
+        let namespace = self.current_namespace();
+        namespace.bindings.insert(name, value);
+        if private {
+            namespace.private.insert(name);
+        } else {
+            namespace.private.remove(&name);
+        }
+    }
+
+    pub fn set_exports(&mut self, exports: impl IntoIterator<Item=Symbol>) {
+        self.current_namespace().exports = Some(exports.into_iter().collect());
+    }
+
+    pub fn add_alias(&mut self, alias: Symbol, target: Symbol) {
+        self.current_namespace().aliases.insert(alias, target);
+    }
+
+    // Unqualified symbols are looked up in the current namespace and then in core. Ok(None) means the
+    // symbol isn't bound, errors are for qualified symbols that can't be used from here
+    pub fn resolve(&self, symbol: Symbol) -> Result<Option<Value>, EvalError> {
+        let current = &self.table[&self.current];
+        let Some((namespace_symbol, name)) = symbol.qualified() else {
+            let binding = current.bindings.get(&symbol).or_else(|| self.table[&Symbol::CORE].bindings.get(&symbol));
+            return Ok(binding.cloned());
+        };
+        let target = current.aliases.get(&namespace_symbol).copied().unwrap_or(namespace_symbol);
+        let Some(namespace) = self.table.get(&target) else {
+            return Err(UnknownNamespace(namespace_symbol.to_string()));
+        };
+        match namespace.bindings.get(&name) {
+            Some(_) if target != self.current && !namespace.is_public(name) => {
+                Err(PrivateSymbol { name: name.to_string(), namespace: target.to_string() })
+            }
+            binding => Ok(binding.cloned()),
+        }
+    }
+
+    // What an unqualified symbol could refer to, for suggestions
+    pub fn visible_names(&self) -> Vec<Symbol> {
+        [self.current, Symbol::intern(CORE)].iter()
+            .flat_map(|namespace| self.table[namespace].bindings.keys().copied())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::assert_matches;
+
+    use crate::eval::GlobalNamespace;
+    use crate::test_support::eval_str;
+
+    use super::*;
+
+    #[test]
+    fn namespaces_keep_their_definitions_apart() {
+        let namespace = &mut GlobalNamespace::default();
+        eval_str("(ns text.shout)", namespace).unwrap();
+        eval_str("(def- suffix \"!\")", namespace).unwrap();
+        eval_str("(def shout (fn [s] (str (upper-case s) suffix)))", namespace).unwrap();
+        eval_str("(in-ns user)", namespace).unwrap();
+        eval_str("(def suffix \"?\")", namespace).unwrap();
+        // The function still sees the namespace it was defined in
+        assert_eq!(eval_str("(text.shout/shout \"hi\")", namespace).unwrap(), Value::Str("HI!".into()));
+        assert_matches!(eval_str("shout", namespace), Err(EvalError::UnboundSymbol { .. }));
+        assert_matches!(eval_str("text.shout/suffix", namespace), Err(PrivateSymbol { .. }));
+        assert_matches!(eval_str("nowhere/x", namespace), Err(UnknownNamespace(name)) if name == "nowhere");
+        assert_eq!(eval_str("(core/+ 1 2)", namespace).unwrap(), Value::Num(3));
+    }
+
+    #[test]
+    fn exports_limit_what_other_namespaces_see() {
+        let namespace = &mut GlobalNamespace::default();
+        eval_str("(ns geometry (:export area))", namespace).unwrap();
+        eval_str("(def square (fn [x] (* x x)))", namespace).unwrap();
+        eval_str("(def area (fn [side] (square side)))", namespace).unwrap();
+        eval_str("(in-ns user)", namespace).unwrap();
+        assert_eq!(eval_str("(geometry/area 3)", namespace).unwrap(), Value::Num(9));
+        assert_eq!(eval_str("(geometry/square 3)", namespace).unwrap_err().to_string(), "square is private to namespace geometry");
+    }
+}
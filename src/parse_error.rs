@@ -8,6 +8,14 @@ pub enum ParseError {
     MissingLeftParenthesis,
     #[error("missing right parenthesis in S expression")]
     MissingRightParenthesis,
+    #[error("missing closing {0} in collection literal")]
+    MissingClosingDelimiter(char),
+    #[error("expected a closing {0} but found {1}")]
+    MismatchedDelimiter(char, char),
+    #[error("unexpected closing {0} without a matching opening delimiter")]
+    UnexpectedClosingDelimiter(char),
+    #[error("map literals must contain an even number of forms")]
+    OddNumberOfMapForms,
     #[error("unparseable empty expression passed in")]
     EmptyExpression,
     #[error("forbidden char in symbol ({0})")]
@@ -16,8 +24,18 @@ pub enum ParseError {
     NotAnSExpression,
     #[error("the given atom is not a valid number ({0})")]
     CannotParseNumber(String),
+    #[error("the given number does not fit in an integer ({0})")]
+    NumberOutOfRange(String),
+    #[error("the given number is not a whole number ({0})")]
+    NotAWholeNumber(String),
     #[error("a double-quote string was opened, but not matched")]
     MissingDoubleQuote,
     #[error("a double-quote string was closed, but that wasn't the end of it")]
     StringDidntEnd,
+    #[error("the source contains invalid UTF-8")]
+    InvalidUtf8,
+    #[error("unknown escape sequence \\{0} in string")]
+    InvalidEscape(String),
+    #[error("invalid regex literal: {0}")]
+    InvalidRegex(String),
 }
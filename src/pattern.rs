@@ -0,0 +1,201 @@
+use std::cell::OnceCell;
+use std::fmt::{Debug, Display, Formatter};
+use std::hash::{Hash, Hasher};
+use std::rc::Rc;
+
+use regex::{Captures, Regex};
+
+use crate::eval::{apply, Arity, EvalError, GlobalNamespace};
+use crate::eval::EvalError::InvalidArguments;
+use crate::strings::expect_str;
+use crate::value::Value;
+
+// A compiled regex literal, #"..." in the source. Two patterns are equal when their sources are
+pub struct Pattern {
+    regex: Regex,
+    // re-matches has to match the whole input, which a plain search can't express, so it gets
+    // its own anchored copy the first time it is needed
+    anchored: OnceCell<Regex>,
+}
+
+// Quotes that aren't escaped yet (patterns made by re-pattern can have them) get a backslash, which
+// means the same to the regex engine but lets the source be printed as a literal that reads back
+fn escape_quotes(source: &str) -> String {
+    let mut escaped = String::with_capacity(source.len());
+    let mut preceding_backslashes = 0;
+    for c in source.chars() {
+        if c == '"' && preceding_backslashes % 2 == 0 {
+            escaped.push('\\');
+        }
+        preceding_backslashes = if c == '\\' { preceding_backslashes + 1 } else { 0 };
+        escaped.push(c);
+    }
+    escaped
+}
+
+impl Pattern {
+    pub fn new(source: &str) -> Result<Pattern, regex::Error> {
+        Ok(Pattern { regex: Regex::new(&escape_quotes(source))?, anchored: OnceCell::new() })
+    }
+
+    pub fn as_str(&self) -> &str {
+        self.regex.as_str()
+    }
+
+    pub fn regex(&self) -> &Regex {
+        &self.regex
+    }
+
+    fn anchored(&self) -> &Regex {
+        self.anchored.get_or_init(|| {
+            Regex::new(&format!("^(?:{})$", self.as_str())).expect("Wrapping a valid regex keeps it valid")
+        })
+    }
+}
+
+impl PartialEq for Pattern {
+    fn eq(&self, other: &Self) -> bool {
+        self.as_str() == other.as_str()
+    }
+}
+
+impl Eq for Pattern {}
+
+impl Hash for Pattern {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.as_str().hash(state);
+    }
+}
+
+impl Display for Pattern {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "#\"{}\"", self.as_str())
+    }
+}
+
+impl Debug for Pattern {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Pattern({})", self)
+    }
+}
+
+pub fn define_builtins(namespace: &mut GlobalNamespace) {
+    namespace.defn(b"re-pattern", lisp_re_pattern.into());
+    namespace.defn(b"re-find", lisp_re_find.into());
+    namespace.defn(b"re-matches", lisp_re_matches.into());
+    namespace.defn(b"re-seq", lisp_re_seq.into());
+    namespace.defn(b"re-replace", lisp_re_replace.into());
+}
+
+fn expect_pattern<'a>(function_name: &str, value: &'a Value) -> Result<&'a Pattern, EvalError> {
+    match value {
+        Value::Regex(pattern) => Ok(pattern),
+        other => Err(EvalError::wrong_type(function_name, "a regex", other)),
+    }
+}
+
+fn pattern_and_str<'a>(function_name: &str, arguments: &'a [Value]) -> Result<(&'a Pattern, &'a str), EvalError> {
+    let [pattern, string] = arguments else {
+        return Err(EvalError::wrong_arity(function_name, Arity::Exactly(2), arguments.len()));
+    };
+    Ok((expect_pattern(function_name, pattern)?, expect_str(function_name, string)?))
+}
+
+// Without groups a match is just the matched text, with groups it is a list of the whole match
+// followed by every group, where groups that didn't take part in the match are nil
+fn match_value(captures: &Captures) -> Value {
+    if captures.len() == 1 {
+        return Value::Str(captures[0].into());
+    }
+    captures.iter()
+        .map(|group| group.map_or(Value::Nil, |group| Value::Str(group.as_str().into())))
+        .collect::<Vec<Value>>()
+        .into()
+}
+
+fn lisp_re_pattern(arguments: &[Value]) -> Result<Value, EvalError> {
+    let [source] = arguments else {
+        return Err(EvalError::wrong_arity("re-pattern", Arity::Exactly(1), arguments.len()));
+    };
+    let pattern = Pattern::new(expect_str("re-pattern", source)?)
+        .map_err(|error| InvalidArguments(format!("re-pattern got an invalid regex: {error}")))?;
+    Ok(Value::Regex(Rc::new(pattern)))
+}
+
+fn lisp_re_find(arguments: &[Value]) -> Result<Value, EvalError> {
+    let (pattern, string) = pattern_and_str("re-find", arguments)?;
+    Ok(pattern.regex().captures(string).map_or(Value::Nil, |captures| match_value(&captures)))
+}
+
+fn lisp_re_matches(arguments: &[Value]) -> Result<Value, EvalError> {
+    let (pattern, string) = pattern_and_str("re-matches", arguments)?;
+    Ok(pattern.anchored().captures(string).map_or(Value::Nil, |captures| match_value(&captures)))
+}
+
+fn lisp_re_seq(arguments: &[Value]) -> Result<Value, EvalError> {
+    let (pattern, string) = pattern_and_str("re-seq", arguments)?;
+    Ok(pattern.regex().captures_iter(string).map(|captures| match_value(&captures)).collect::<Vec<Value>>().into())
+}
+
+// The replacement is either a string, where $1 or ${name} refer to groups, or a function that
+// gets each match the way re-find would return it
+fn lisp_re_replace(arguments: &[Value]) -> Result<Value, EvalError> {
+    let [pattern, string, replacement] = arguments else {
+        return Err(EvalError::wrong_arity("re-replace", Arity::Exactly(3), arguments.len()));
+    };
+    let pattern = expect_pattern("re-replace", pattern)?;
+    let string = expect_str("re-replace", string)?;
+    if let Value::Str(replacement) = replacement {
+        return Ok(Value::Str(pattern.regex().replace_all(string, replacement.as_ref()).into()));
+    }
+    let Value::Fn(_) = replacement else {
+        return Err(EvalError::wrong_type("re-replace", "a string or a function as replacement", replacement));
+    };
+    // replace_all can't bail out early, so remember the first failure and report it afterwards
+    let mut error = None;
+    let replaced = pattern.regex().replace_all(string, |captures: &Captures| {
+        if error.is_some() {
+            return String::new();
+        }
+        match apply(replacement, &[match_value(captures)]) {
+            Ok(Value::Str(replaced)) => replaced.to_string(),
+            Ok(other) => other.to_string(),
+            Err(replace_error) => {
+                error = Some(replace_error);
+                String::new()
+            }
+        }
+    });
+    match error {
+        Some(error) => Err(error),
+        None => Ok(Value::Str(replaced.into())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_support::eval_value;
+
+    use super::*;
+
+    #[test]
+    fn find_returns_groups_as_lists() {
+        assert_eq!(eval_value(r#"(re-find #"\d+" "abc 123 456")"#), Value::Str("123".into()));
+        assert_eq!(eval_value(r#"(re-find #"(\w+)=(\d+)?" "key= x")"#).to_string(), r#"("key=" "key" nil)"#);
+        assert_eq!(eval_value(r#"(re-find #"\d" "abc")"#), Value::Nil);
+    }
+
+    #[test]
+    fn matches_needs_the_whole_string() {
+        assert_eq!(eval_value(r#"(re-matches #"\d+|\d+x" "12x")"#), Value::Str("12x".into()));
+        assert_eq!(eval_value(r#"(re-matches #"\d+" "12x")"#), Value::Nil);
+    }
+
+    #[test]
+    fn seq_and_replace_walk_every_match() {
+        assert_eq!(eval_value(r#"(re-seq #"(\w)(\d)" "a1 b2 c")"#).to_string(), r#"(("a1" "a" "1") ("b2" "b" "2"))"#);
+        assert_eq!(eval_value(r#"(re-replace #"(\w+)@(\w+)" "me@host" "$2 at $1")"#), Value::Str("host at me".into()));
+        assert_eq!(eval_value(r#"(re-replace #"\d+" "1 22 333" count)"#), Value::Str("1 2 3".into()));
+        assert_eq!(eval_value(r#"(split "a1b22c" #"\d+")"#).to_string(), r#"["a" "b" "c"]"#);
+    }
+}
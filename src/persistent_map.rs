@@ -0,0 +1,368 @@
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::rc::Rc;
+
+// A hash array mapped trie: every level consumes 5 bits of the key hash, and nodes only store the
+// children that exist (tracked by a bitmap). Updates copy one path and share the rest.
+const BITS: u32 = 5;
+const MASK: u64 = (1 << BITS) - 1;
+const HASH_BITS: u32 = u64::BITS;
+
+enum Entry<K, V> {
+    Pair(K, V),
+    Node(Rc<MapNode<K, V>>),
+}
+
+impl<K: Clone, V: Clone> Clone for Entry<K, V> {
+    fn clone(&self) -> Self {
+        match self {
+            Entry::Pair(key, value) => Entry::Pair(key.clone(), value.clone()),
+            Entry::Node(node) => Entry::Node(node.clone()),
+        }
+    }
+}
+
+enum MapNode<K, V> {
+    Branch { bitmap: u32, entries: Vec<Entry<K, V>> },
+    // Once all the hash bits are used up, keys with the same hash just live side by side
+    Collision { pairs: Vec<(K, V)> },
+}
+
+fn hash_of<K: Hash>(key: &K) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    key.hash(&mut hasher);
+    hasher.finish()
+}
+
+fn bit_for(hash: u64, shift: u32) -> u32 {
+    1 << ((hash >> shift) & MASK)
+}
+
+fn index_for(bitmap: u32, bit: u32) -> usize {
+    (bitmap & (bit - 1)).count_ones() as usize
+}
+
+impl<K: Hash + Eq + Clone, V: Clone> MapNode<K, V> {
+    fn empty() -> MapNode<K, V> {
+        MapNode::Branch { bitmap: 0, entries: vec![] }
+    }
+
+    fn get(&self, shift: u32, hash: u64, key: &K) -> Option<&V> {
+        match self {
+            MapNode::Branch { bitmap, entries } => {
+                let bit = bit_for(hash, shift);
+                if bitmap & bit == 0 {
+                    return None;
+                }
+                match &entries[index_for(*bitmap, bit)] {
+                    Entry::Pair(found_key, value) => (found_key == key).then_some(value),
+                    Entry::Node(node) => node.get(shift + BITS, hash, key),
+                }
+            }
+            MapNode::Collision { pairs } => {
+                pairs.iter().find(|(found_key, _)| found_key == key).map(|(_, value)| value)
+            }
+        }
+    }
+
+    // Returns the new node and whether the key was not present before
+    fn insert(&self, shift: u32, hash: u64, key: K, value: V) -> (MapNode<K, V>, bool) {
+        match self {
+            MapNode::Branch { bitmap, entries } => {
+                let bit = bit_for(hash, shift);
+                let index = index_for(*bitmap, bit);
+                let mut entries = entries.clone();
+                if bitmap & bit == 0 {
+                    entries.insert(index, Entry::Pair(key, value));
+                    return (MapNode::Branch { bitmap: bitmap | bit, entries }, true);
+                }
+                let (new_entry, added) = match &entries[index] {
+                    Entry::Pair(found_key, _) if *found_key == key => (Entry::Pair(key, value), false),
+                    Entry::Pair(found_key, found_value) => {
+                        let found_hash = hash_of(found_key);
+                        let merged = Self::merge(shift + BITS, (found_hash, found_key.clone(), found_value.clone()), (hash, key, value));
+                        (Entry::Node(Rc::new(merged)), true)
+                    }
+                    Entry::Node(node) => {
+                        let (new_node, added) = node.insert(shift + BITS, hash, key, value);
+                        (Entry::Node(Rc::new(new_node)), added)
+                    }
+                };
+                entries[index] = new_entry;
+                (MapNode::Branch { bitmap: *bitmap, entries }, added)
+            }
+            MapNode::Collision { pairs } => {
+                let mut pairs = pairs.clone();
+                match pairs.iter().position(|(found_key, _)| *found_key == key) {
+                    Some(position) => {
+                        pairs[position] = (key, value);
+                        (MapNode::Collision { pairs }, false)
+                    }
+                    None => {
+                        pairs.push((key, value));
+                        (MapNode::Collision { pairs }, true)
+                    }
+                }
+            }
+        }
+    }
+
+    fn merge(shift: u32, first: (u64, K, V), second: (u64, K, V)) -> MapNode<K, V> {
+        let (first_hash, first_key, first_value) = first;
+        let (second_hash, second_key, second_value) = second;
+        if shift >= HASH_BITS {
+            return MapNode::Collision { pairs: vec![(first_key, first_value), (second_key, second_value)] };
+        }
+        let first_bit = bit_for(first_hash, shift);
+        let second_bit = bit_for(second_hash, shift);
+        if first_bit == second_bit {
+            let merged = Self::merge(shift + BITS, (first_hash, first_key, first_value), (second_hash, second_key, second_value));
+            return MapNode::Branch { bitmap: first_bit, entries: vec![Entry::Node(Rc::new(merged))] };
+        }
+        let first_entry = Entry::Pair(first_key, first_value);
+        let second_entry = Entry::Pair(second_key, second_value);
+        let entries = if first_bit < second_bit {
+            vec![first_entry, second_entry]
+        } else {
+            vec![second_entry, first_entry]
+        };
+        MapNode::Branch { bitmap: first_bit | second_bit, entries }
+    }
+
+    // Returns None when the key was not present
+    fn remove(&self, shift: u32, hash: u64, key: &K) -> Option<MapNode<K, V>> {
+        match self {
+            MapNode::Branch { bitmap, entries } => {
+                let bit = bit_for(hash, shift);
+                if bitmap & bit == 0 {
+                    return None;
+                }
+                let index = index_for(*bitmap, bit);
+                let mut entries = entries.clone();
+                let mut bitmap = *bitmap;
+                match &entries[index] {
+                    Entry::Pair(found_key, _) if found_key == key => {
+                        entries.remove(index);
+                        bitmap &= !bit;
+                    }
+                    Entry::Pair(_, _) => return None,
+                    Entry::Node(node) => {
+                        let new_node = node.remove(shift + BITS, hash, key)?;
+                        // Pull lonely pairs back up so the trie stays as shallow as possible
+                        entries[index] = match new_node.single_pair() {
+                            Some((key, value)) => Entry::Pair(key.clone(), value.clone()),
+                            None => Entry::Node(Rc::new(new_node)),
+                        };
+                    }
+                }
+                Some(MapNode::Branch { bitmap, entries })
+            }
+            MapNode::Collision { pairs } => {
+                let position = pairs.iter().position(|(found_key, _)| found_key == key)?;
+                let mut pairs = pairs.clone();
+                pairs.remove(position);
+                Some(MapNode::Collision { pairs })
+            }
+        }
+    }
+
+    fn single_pair(&self) -> Option<(&K, &V)> {
+        match self {
+            MapNode::Branch { entries, .. } => match entries.as_slice() {
+                [Entry::Pair(key, value)] => Some((key, value)),
+                _ => None,
+            },
+            MapNode::Collision { pairs } => match pairs.as_slice() {
+                [(key, value)] => Some((key, value)),
+                _ => None,
+            },
+        }
+    }
+}
+
+pub struct PersistentMap<K, V> {
+    len: usize,
+    root: Rc<MapNode<K, V>>,
+}
+
+impl<K, V> Clone for PersistentMap<K, V> {
+    fn clone(&self) -> Self {
+        PersistentMap { len: self.len, root: self.root.clone() }
+    }
+}
+
+impl<K: Hash + Eq + Clone, V: Clone> Default for PersistentMap<K, V> {
+    fn default() -> Self {
+        PersistentMap { len: 0, root: Rc::new(MapNode::empty()) }
+    }
+}
+
+impl<K: Hash + Eq + Clone, V: Clone> PersistentMap<K, V> {
+    pub fn new() -> PersistentMap<K, V> {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn get(&self, key: &K) -> Option<&V> {
+        self.root.get(0, hash_of(key), key)
+    }
+
+    pub fn contains_key(&self, key: &K) -> bool {
+        self.get(key).is_some()
+    }
+
+    pub fn insert(&self, key: K, value: V) -> PersistentMap<K, V> {
+        let (root, added) = self.root.insert(0, hash_of(&key), key, value);
+        PersistentMap {
+            len: if added { self.len + 1 } else { self.len },
+            root: Rc::new(root),
+        }
+    }
+
+    pub fn remove(&self, key: &K) -> PersistentMap<K, V> {
+        match self.root.remove(0, hash_of(key), key) {
+            Some(root) => PersistentMap { len: self.len - 1, root: Rc::new(root) },
+            None => self.clone(),
+        }
+    }
+
+    pub fn iter(&self) -> Iter<'_, K, V> {
+        Iter { nodes: vec![&self.root], entries: vec![], pairs: [].iter() }
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item=&K> {
+        self.iter().map(|(key, _)| key)
+    }
+
+    pub fn values(&self) -> impl Iterator<Item=&V> {
+        self.iter().map(|(_, value)| value)
+    }
+}
+
+pub struct Iter<'a, K, V> {
+    nodes: Vec<&'a MapNode<K, V>>,
+    entries: Vec<std::slice::Iter<'a, Entry<K, V>>>,
+    pairs: std::slice::Iter<'a, (K, V)>,
+}
+
+impl<'a, K, V> Iterator for Iter<'a, K, V> {
+    type Item = (&'a K, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some((key, value)) = self.pairs.next() {
+                return Some((key, value));
+            }
+            if let Some(entries) = self.entries.last_mut() {
+                match entries.next() {
+                    Some(Entry::Pair(key, value)) => return Some((key, value)),
+                    Some(Entry::Node(node)) => self.nodes.push(node),
+                    None => {
+                        self.entries.pop();
+                    }
+                }
+            }
+            match self.nodes.pop() {
+                Some(MapNode::Branch { entries, .. }) => self.entries.push(entries.iter()),
+                Some(MapNode::Collision { pairs }) => self.pairs = pairs.iter(),
+                None if self.entries.is_empty() => return None,
+                None => {}
+            }
+        }
+    }
+}
+
+impl<K: Hash + Eq + Clone, V: Clone> FromIterator<(K, V)> for PersistentMap<K, V> {
+    fn from_iter<I: IntoIterator<Item=(K, V)>>(iter: I) -> Self {
+        iter.into_iter().fold(PersistentMap::new(), |map, (key, value)| map.insert(key, value))
+    }
+}
+
+impl<K: Hash + Eq + Clone, V: Clone + PartialEq> PartialEq for PersistentMap<K, V> {
+    fn eq(&self, other: &Self) -> bool {
+        self.len == other.len && self.iter().all(|(key, value)| other.get(key) == Some(value))
+    }
+}
+
+impl<K: Hash + Eq + Clone, V: Clone + Hash> Hash for PersistentMap<K, V> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        // Iteration order depends on the trie shape, so combine entry hashes in an order independent way
+        let combined = self.iter()
+            .map(|(key, value)| hash_of(&(key, value)))
+            .fold(0u64, u64::wrapping_add);
+        state.write_usize(self.len);
+        state.write_u64(combined);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Clone, PartialEq, Eq, Debug)]
+    struct Colliding(u32);
+
+    impl Hash for Colliding {
+        fn hash<H: Hasher>(&self, state: &mut H) {
+            state.write_u32(self.0 % 3);
+        }
+    }
+
+    #[test]
+    fn inserted_values_can_be_read_back() {
+        let map: PersistentMap<usize, usize> = (0..5000).map(|key| (key, key * 2)).collect();
+        assert_eq!(map.len(), 5000);
+        for key in 0..5000 {
+            assert_eq!(map.get(&key), Some(&(key * 2)));
+        }
+        assert_eq!(map.get(&5000), None);
+        assert_eq!(map.iter().count(), 5000);
+    }
+
+    #[test]
+    fn older_versions_are_unchanged() {
+        let old: PersistentMap<usize, usize> = (0..100).map(|key| (key, key)).collect();
+        let new = old.insert(3, 42).insert(100, 100).remove(&7);
+        assert_eq!(old.len(), 100);
+        assert_eq!(old.get(&3), Some(&3));
+        assert_eq!(old.get(&7), Some(&7));
+        assert_eq!(new.len(), 100);
+        assert_eq!(new.get(&3), Some(&42));
+        assert_eq!(new.get(&7), None);
+    }
+
+    #[test]
+    fn removing_everything_leaves_an_empty_map() {
+        let map: PersistentMap<usize, usize> = (0..1000).map(|key| (key, key)).collect();
+        let emptied = (0..1000).fold(map, |map, key| map.remove(&key));
+        assert!(emptied.is_empty());
+        assert_eq!(emptied.iter().count(), 0);
+    }
+
+    #[test]
+    fn colliding_hashes_are_kept_apart() {
+        let map: PersistentMap<Colliding, u32> = (0..30).map(|key| (Colliding(key), key)).collect();
+        assert_eq!(map.len(), 30);
+        for key in 0..30 {
+            assert_eq!(map.get(&Colliding(key)), Some(&key));
+        }
+        let removed = map.remove(&Colliding(4)).remove(&Colliding(5));
+        assert_eq!(removed.len(), 28);
+        assert_eq!(removed.get(&Colliding(4)), None);
+        assert_eq!(removed.get(&Colliding(7)), Some(&7));
+    }
+
+    #[test]
+    fn equality_ignores_insertion_order() {
+        let forwards: PersistentMap<usize, usize> = (0..200).map(|key| (key, key)).collect();
+        let backwards: PersistentMap<usize, usize> = (0..200).rev().map(|key| (key, key)).collect();
+        assert!(forwards == backwards);
+        assert_eq!(hash_of(&forwards), hash_of(&backwards));
+    }
+}
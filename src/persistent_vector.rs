@@ -0,0 +1,227 @@
+use std::rc::Rc;
+
+// A bit-partitioned trie of 32-wide nodes, like Clojure's vector: updates copy one path of at most
+// log32(n) nodes and share everything else with the previous version.
+const BITS: u32 = 5;
+const WIDTH: usize = 1 << BITS;
+const MASK: usize = WIDTH - 1;
+
+enum Node<T> {
+    Branch(Rc<Vec<Node<T>>>),
+    Leaf(Rc<Vec<T>>),
+}
+
+impl<T> Clone for Node<T> {
+    fn clone(&self) -> Self {
+        match self {
+            Node::Branch(children) => Node::Branch(children.clone()),
+            Node::Leaf(values) => Node::Leaf(values.clone()),
+        }
+    }
+}
+
+impl<T> Node<T> {
+    fn children(&self) -> &[Node<T>] {
+        match self {
+            Node::Branch(children) => children,
+            Node::Leaf(_) => unreachable!("Leaves only live at the bottom level"),
+        }
+    }
+
+    fn values(&self) -> &[T] {
+        match self {
+            Node::Leaf(values) => values,
+            Node::Branch(_) => unreachable!("Branches never live at the bottom level"),
+        }
+    }
+}
+
+pub struct PersistentVector<T> {
+    len: usize,
+    shift: u32,
+    root: Node<T>,
+    // The last (up to 32) elements live outside the trie, so pushing is usually a cheap tail copy
+    tail: Rc<Vec<T>>,
+}
+
+impl<T> Clone for PersistentVector<T> {
+    fn clone(&self) -> Self {
+        PersistentVector {
+            len: self.len,
+            shift: self.shift,
+            root: self.root.clone(),
+            tail: self.tail.clone(),
+        }
+    }
+}
+
+impl<T> Default for PersistentVector<T> {
+    fn default() -> Self {
+        PersistentVector {
+            len: 0,
+            shift: BITS,
+            root: Node::Branch(Rc::new(vec![])),
+            tail: Rc::new(vec![]),
+        }
+    }
+}
+
+impl<T: Clone> PersistentVector<T> {
+    pub fn new() -> PersistentVector<T> {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    fn tail_offset(&self) -> usize {
+        self.len - self.tail.len()
+    }
+
+    pub fn get(&self, index: usize) -> Option<&T> {
+        if index >= self.len {
+            return None;
+        }
+        if index >= self.tail_offset() {
+            return self.tail.get(index - self.tail_offset());
+        }
+        let mut node = &self.root;
+        let mut level = self.shift;
+        while level > 0 {
+            node = &node.children()[(index >> level) & MASK];
+            level -= BITS;
+        }
+        node.values().get(index & MASK)
+    }
+
+    pub fn push_back(&self, value: T) -> PersistentVector<T> {
+        let mut pushed = self.clone();
+        if self.tail.len() < WIDTH {
+            Rc::make_mut(&mut pushed.tail).push(value);
+            pushed.len += 1;
+            return pushed;
+        }
+        // The tail is full, move it into the trie and start a new one
+        let tail_node = Node::Leaf(self.tail.clone());
+        let root_overflows = (self.len >> BITS) > (1 << self.shift);
+        if root_overflows {
+            let new_path = Self::new_path(self.shift, tail_node);
+            pushed.root = Node::Branch(Rc::new(vec![self.root.clone(), new_path]));
+            pushed.shift += BITS;
+        } else {
+            pushed.root = self.push_tail(self.shift, &self.root, tail_node);
+        }
+        pushed.tail = Rc::new(vec![value]);
+        pushed.len += 1;
+        pushed
+    }
+
+    fn new_path(level: u32, node: Node<T>) -> Node<T> {
+        if level == 0 {
+            node
+        } else {
+            Node::Branch(Rc::new(vec![Self::new_path(level - BITS, node)]))
+        }
+    }
+
+    fn push_tail(&self, level: u32, parent: &Node<T>, tail_node: Node<T>) -> Node<T> {
+        let sub_index = ((self.len - 1) >> level) & MASK;
+        let mut children = parent.children().to_vec();
+        let new_child = if level == BITS {
+            tail_node
+        } else if let Some(child) = children.get(sub_index) {
+            self.push_tail(level - BITS, child, tail_node)
+        } else {
+            Self::new_path(level - BITS, tail_node)
+        };
+        if sub_index < children.len() {
+            children[sub_index] = new_child;
+        } else {
+            children.push(new_child);
+        }
+        Node::Branch(Rc::new(children))
+    }
+
+    pub fn set(&self, index: usize, value: T) -> Option<PersistentVector<T>> {
+        if index >= self.len {
+            return None;
+        }
+        let mut updated = self.clone();
+        if index >= self.tail_offset() {
+            Rc::make_mut(&mut updated.tail)[index - self.tail_offset()] = value;
+        } else {
+            updated.root = Self::set_in(self.shift, &self.root, index, value);
+        }
+        Some(updated)
+    }
+
+    fn set_in(level: u32, node: &Node<T>, index: usize, value: T) -> Node<T> {
+        if level == 0 {
+            let mut values = node.values().to_vec();
+            values[index & MASK] = value;
+            return Node::Leaf(Rc::new(values));
+        }
+        let mut children = node.children().to_vec();
+        let sub_index = (index >> level) & MASK;
+        children[sub_index] = Self::set_in(level - BITS, &children[sub_index], index, value);
+        Node::Branch(Rc::new(children))
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item=&T> + '_ {
+        (0..self.len).map(|index| self.get(index).expect("Index is in bounds"))
+    }
+}
+
+impl<T: Clone> FromIterator<T> for PersistentVector<T> {
+    fn from_iter<I: IntoIterator<Item=T>>(iter: I) -> Self {
+        iter.into_iter().fold(PersistentVector::new(), |vector, value| vector.push_back(value))
+    }
+}
+
+impl<T: Clone + PartialEq> PartialEq for PersistentVector<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.len == other.len && self.iter().eq(other.iter())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pushed_values_can_be_read_back() {
+        let vector: PersistentVector<usize> = (0..5000).collect();
+        assert_eq!(vector.len(), 5000);
+        for index in 0..5000 {
+            assert_eq!(vector.get(index), Some(&index));
+        }
+        assert_eq!(vector.get(5000), None);
+    }
+
+    #[test]
+    fn older_versions_are_unchanged() {
+        let old: PersistentVector<usize> = (0..100).collect();
+        let new = old.push_back(100).set(3, 42).unwrap();
+        assert_eq!(old.len(), 100);
+        assert_eq!(old.get(3), Some(&3));
+        assert_eq!(new.len(), 101);
+        assert_eq!(new.get(3), Some(&42));
+        assert_eq!(new.get(100), Some(&100));
+    }
+
+    #[test]
+    fn set_works_inside_the_trie_and_the_tail() {
+        let vector: PersistentVector<usize> = (0..1100).collect();
+        let updated = vector.set(0, 7).unwrap().set(1099, 8).unwrap().set(1050, 9).unwrap();
+        assert_eq!(updated.iter().enumerate().filter(|(index, value)| index != *value).count(), 3);
+        assert_eq!(updated.get(0), Some(&7));
+        assert_eq!(updated.get(1099), Some(&8));
+        assert_eq!(updated.get(1050), Some(&9));
+        assert!(vector.set(1100, 0).is_none());
+    }
+}
@@ -0,0 +1,265 @@
+use crate::eval::{Arity, EvalError, GlobalNamespace, WeakNamespace};
+use crate::eval::EvalError::InvalidArguments;
+use crate::lazy_seq::{SeqIter, PRINT_LIMIT};
+use crate::tokenize::AstNode;
+use crate::value::{PrintMode, Value};
+
+// A collection is printed on one line if that fits in the width, otherwise every element goes on
+// its own line, indented to line up after the opening delimiter
+pub struct PrettyOptions {
+    pub width: usize,
+    // Collections longer than this are cut off with "...", lazy sequences never show more than PRINT_LIMIT
+    pub max_items: Option<usize>,
+    // Collections nested deeper than this are shown as (...)
+    pub max_depth: Option<usize>,
+    pub mode: PrintMode,
+}
+
+impl Default for PrettyOptions {
+    fn default() -> Self {
+        PrettyOptions { width: 80, max_items: Some(PRINT_LIMIT), max_depth: None, mode: PrintMode::Readable }
+    }
+}
+
+const TRUNCATED: &str = "...";
+
+enum Doc {
+    Atom(String),
+    Group {
+        open: &'static str,
+        items: Vec<Doc>,
+        // Put after every item but the last, maps use "," so each entry ends up as "key value,"
+        separator: &'static str,
+        close: &'static str,
+        flat_len: usize,
+    },
+}
+
+impl Doc {
+    fn group(open: &'static str, items: Vec<Doc>, separator: &'static str, close: &'static str) -> Doc {
+        let separators = items.len().saturating_sub(1) * (separator.len() + 1);
+        let flat_len = open.chars().count() + items.iter().map(Doc::flat_len).sum::<usize>() + separators + close.chars().count();
+        Doc::Group { open, items, separator, close, flat_len }
+    }
+
+    fn flat_len(&self) -> usize {
+        match self {
+            Doc::Atom(text) => text.chars().count(),
+            Doc::Group { flat_len, .. } => *flat_len,
+        }
+    }
+
+    fn write_flat(&self, output: &mut String) {
+        match self {
+            Doc::Atom(text) => output.push_str(text),
+            Doc::Group { open, items, separator, close, .. } => {
+                output.push_str(open);
+                for (index, item) in items.iter().enumerate() {
+                    if index > 0 {
+                        output.push_str(separator);
+                        output.push(' ');
+                    }
+                    item.write_flat(output);
+                }
+                output.push_str(close);
+            }
+        }
+    }
+
+    fn write(&self, output: &mut String, column: usize, width: usize) {
+        let Doc::Group { open, items, separator, close, flat_len } = self else {
+            return self.write_flat(output);
+        };
+        if column + flat_len <= width {
+            return self.write_flat(output);
+        }
+        output.push_str(open);
+        let item_column = column + open.chars().count();
+        for (index, item) in items.iter().enumerate() {
+            if index > 0 {
+                output.push_str(separator);
+                output.push('\n');
+                output.push_str(&" ".repeat(item_column));
+            }
+            item.write(output, item_column, width);
+        }
+        output.push_str(close);
+    }
+}
+
+fn limited_group<T>(open: &'static str, items: impl Iterator<Item=Result<T, EvalError>>, separator: &'static str, close: &'static str,
+                    max_items: Option<usize>, to_doc: impl Fn(T) -> Doc) -> Doc {
+    let mut docs = vec![];
+    for item in items {
+        if max_items.is_some_and(|max_items| docs.len() == max_items) {
+            docs.push(Doc::Atom(TRUNCATED.into()));
+            break;
+        }
+        match item {
+            Ok(item) => docs.push(to_doc(item)),
+            // Printing can't report evaluation errors, so just show where the sequence broke off
+            Err(_) => {
+                docs.push(Doc::Atom(TRUNCATED.into()));
+                break;
+            }
+        }
+    }
+    Doc::group(open, docs, separator, close)
+}
+
+fn value_doc(value: &Value, options: &PrettyOptions, depth: usize) -> Doc {
+    let delimiters = match value {
+        Value::List(_) | Value::LazySeq(_) => ("(", ")"),
+        Value::Vector(_) => ("[", "]"),
+        Value::Map(_) => ("{", "}"),
+        atom => return Doc::Atom(atom.printed(options.mode).to_string()),
+    };
+    if options.max_depth.is_some_and(|max_depth| depth >= max_depth) {
+        return Doc::Atom(format!("{}{TRUNCATED}{}", delimiters.0, delimiters.1));
+    }
+    let (open, close) = delimiters;
+    let to_doc = |item: Value| value_doc(&item, options, depth + 1);
+    match value {
+        Value::List(values) => limited_group(open, values.iter().cloned().map(Ok), "", close, options.max_items, to_doc),
+        Value::Vector(values) => limited_group(open, values.iter().cloned().map(Ok), "", close, options.max_items, to_doc),
+        Value::LazySeq(_) => {
+            let max_items = options.max_items.unwrap_or(PRINT_LIMIT).min(PRINT_LIMIT);
+            limited_group(open, SeqIter::new(value.clone()), "", close, Some(max_items), to_doc)
+        }
+        Value::Map(map) => {
+            let entry_doc = |(key, value): (&Value, &Value)| {
+                Doc::group("", vec![value_doc(key, options, depth + 1), value_doc(value, options, depth + 1)], "", "")
+            };
+            limited_group(open, map.iter().map(Ok::<_, EvalError>), ",", close, options.max_items, entry_doc)
+        }
+        _ => unreachable!("Atoms were handled above"),
+    }
+}
+
+// ASTs are shown in the same shape as their Debug output
+fn ast_doc(node: &AstNode, options: &PrettyOptions, depth: usize) -> Doc {
+    let (open, nodes) = match node {
+        AstNode::List(nodes, _) => ("List(", nodes),
+        AstNode::Vector(nodes) => ("Vector(", nodes),
+        AstNode::Map(nodes) => ("Map(", nodes),
+        atom => return Doc::Atom(format!("{:?}", atom)),
+    };
+    if options.max_depth.is_some_and(|max_depth| depth >= max_depth) {
+        return Doc::Atom(format!("{open}{TRUNCATED})"));
+    }
+    limited_group(open, nodes.iter().map(Ok::<_, EvalError>), "", ")", options.max_items, |node| ast_doc(node, options, depth + 1))
+}
+
+pub fn pretty_value(value: &Value, options: &PrettyOptions) -> String {
+    let mut output = String::new();
+    value_doc(value, options, 0).write(&mut output, 0, options.width);
+    output
+}
+
+pub fn pretty_ast(node: &AstNode, options: &PrettyOptions) -> String {
+    let mut output = String::new();
+    ast_doc(node, options, 0).write(&mut output, 0, options.width);
+    output
+}
+
+pub fn define_builtins(namespace: &mut GlobalNamespace) {
+    let weak_namespace = namespace.downgrade();
+    namespace.defn(b"pprint", (move |arguments: &[Value]| lisp_pprint(&weak_namespace, arguments)).into());
+}
+
+// Options come as a map like {:width 40 :max-items 10 :max-depth 3}, where nil lifts a limit
+fn pretty_options(options: &Value) -> Result<PrettyOptions, EvalError> {
+    let Value::Map(options) = options else {
+        return Err(EvalError::wrong_type("pprint", "its options as a map", options));
+    };
+    let mut pretty_options = PrettyOptions::default();
+    for (key, value) in options.iter() {
+        let number = || value.num().and_then(|number| usize::try_from(number).ok())
+            .ok_or_else(|| InvalidArguments(format!("pprint expects {key} to be a non-negative number, got {value}")));
+        let limit = || if let Value::Nil = value { Ok(None) } else { number().map(Some) };
+        match key {
+            Value::Keyword(keyword) if keyword.name() == "width" => pretty_options.width = number()?,
+            Value::Keyword(keyword) if keyword.name() == "max-items" => pretty_options.max_items = limit()?,
+            Value::Keyword(keyword) if keyword.name() == "max-depth" => pretty_options.max_depth = limit()?,
+            other => return Err(InvalidArguments(format!("pprint got an unknown option {other}, expected :width, :max-items or :max-depth"))),
+        }
+    }
+    Ok(pretty_options)
+}
+
+fn lisp_pprint(namespace: &WeakNamespace, arguments: &[Value]) -> Result<Value, EvalError> {
+    let (value, options) = match arguments {
+        [value] => (value, PrettyOptions::default()),
+        [value, options] => (value, pretty_options(options)?),
+        _ => return Err(EvalError::wrong_arity("pprint", Arity::Between(1, 2), arguments.len())),
+    };
+    namespace.upgrade()?.write_stdout(&format!("{}\n", pretty_value(value, &options)))?;
+    Ok(Value::Nil)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_support::eval_value;
+    use crate::tokenize::AstToken::Parsed;
+    use crate::tokenize::tokenize;
+
+    use super::*;
+
+    fn parse(source: &str) -> AstNode {
+        let Parsed(node) = tokenize(source.as_bytes()).unwrap() else { panic!("Expected a single form") };
+        node
+    }
+
+    fn pretty(source: &str, options: PrettyOptions) -> String {
+        pretty_value(&eval_value(source), &options)
+    }
+
+    #[test]
+    fn short_values_stay_on_one_line() {
+        let value = "[{:name \"risp\"} [:lisp :rust] #\"\\d+\"]";
+        assert_eq!(pretty(value, PrettyOptions::default()), value);
+    }
+
+    #[test]
+    fn long_collections_break_and_indent() {
+        let options = PrettyOptions { width: 20, ..PrettyOptions::default() };
+        assert_eq!(pretty("[[1 2 3] [4 5 6] (list \"seven\" \"eight\" \"nine\")]", options), "\
+[[1 2 3]
+ [4 5 6]
+ (\"seven\"
+  \"eight\"
+  \"nine\")]");
+        let options = PrettyOptions { width: 16, ..PrettyOptions::default() };
+        assert_eq!(pretty("{:k [1 2 3 4 5 6 7 8]}", options), "\
+{:k
+ [1
+  2
+  3
+  4
+  5
+  6
+  7
+  8]}");
+    }
+
+    #[test]
+    fn limits_truncate_long_and_deep_values() {
+        let options = PrettyOptions { max_items: Some(3), ..PrettyOptions::default() };
+        assert_eq!(pretty("(range)", options), "(0 1 2 ...)");
+        let options = PrettyOptions { max_depth: Some(2), ..PrettyOptions::default() };
+        assert_eq!(pretty("[1 [2 [3 [4]]] {:a {:b 1}}]", options), "[1 [2 [...]] {:a {...}}]");
+    }
+
+    #[test]
+    fn asts_keep_their_debug_shape() {
+        let options = PrettyOptions::default();
+        assert_eq!(pretty_ast(&parse("(def x [1 \"two\"])"), &options), "List(Sym(def) Sym(x) Vector(Num(1) Str(\"two\")))");
+        assert_eq!(pretty_ast(&parse("(fn [a b] (+ a b))"), &PrettyOptions { width: 20, ..options }), "\
+List(Sym(fn)
+     Vector(Sym(a)
+            Sym(b))
+     List(Sym(+)
+          Sym(a)
+          Sym(b)))");
+    }
+}
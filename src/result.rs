@@ -1,12 +1,21 @@
 use std::io;
 use thiserror::Error;
 
+use jirsp::manifest::ManifestError;
+use jirsp::parse_error::ParseError;
 
-const USAGE: &'static str = "Usage:
-    risp <filepath>
+
+const USAGE: &str = "Usage:
+    risp [--print=readable|display] <filepath>
         Interpret risp from a file
-    risp
-        Repl
+    risp [--print=readable|display]
+        Repl, printing results readably (the default) or for display
+    risp fmt [--check] <filepath>...
+        Format files in place, or with --check fail if any of them is not formatted
+
+require finds modules next to the file being run (or in the working directory for the repl), then
+in the source roots and path dependencies of the risp.toml package it is in, then in the
+directories listed in RISP_PATH
 ";
 
 #[derive(Error, Debug)]
@@ -15,4 +24,12 @@ pub enum RispError {
     TooManyArguments(usize),
     #[error("unable to open file {0}")]
     UnableToOpenFile(#[from] io::Error),
+    #[error("unknown print mode {0}, expected readable or display\n{}", USAGE)]
+    UnknownPrintMode(String),
+    #[error("no files to format given\n{}", USAGE)]
+    NothingToFormat,
+    #[error("cannot format {0}: {1}")]
+    CannotFormat(String, ParseError),
+    #[error("invalid package: {0}")]
+    InvalidManifest(#[from] ManifestError),
 }
\ No newline at end of file
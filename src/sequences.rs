@@ -0,0 +1,232 @@
+use std::cmp::Ordering;
+
+use crate::eval::{apply, Arity, EvalError, GlobalNamespace};
+use crate::eval::EvalError::InvalidArguments;
+use crate::lazy_seq::{first_and_rest, LazySeq, SeqIter};
+use crate::persistent_map::PersistentMap;
+use crate::persistent_vector::PersistentVector;
+use crate::value::Value;
+
+pub fn define_builtins(namespace: &mut GlobalNamespace) {
+    namespace.defn(b"map", lisp_map.into());
+    namespace.defn(b"filter", lisp_filter.into());
+    namespace.defn(b"reduce", lisp_reduce.into());
+    namespace.defn(b"apply", lisp_apply.into());
+    namespace.defn(b"sort-by", lisp_sort_by.into());
+    namespace.defn(b"group-by", lisp_group_by.into());
+}
+
+// The elements of anything that can be walked in order, maps yield [key value] vectors
+pub fn items(function_name: &str, collection: &Value) -> Result<Vec<Value>, EvalError> {
+    match collection {
+        Value::Nil => Ok(vec![]),
+        Value::List(list) => Ok(list.to_vec()),
+        Value::Vector(vector) => Ok(vector.iter().cloned().collect()),
+        Value::Map(map) => Ok(map.iter()
+            .map(|(key, value)| Value::Vector([key.clone(), value.clone()].into_iter().collect()))
+            .collect()),
+        Value::LazySeq(_) => SeqIter::new(collection.clone()).collect(),
+        other => Err(EvalError::wrong_type(function_name, "a collection", other)),
+    }
+}
+
+fn lisp_map(arguments: &[Value]) -> Result<Value, EvalError> {
+    let [function, collections @ ..] = arguments else {
+        return Err(EvalError::wrong_arity("map", Arity::AtLeast(2), arguments.len()));
+    };
+    if collections.is_empty() {
+        return Err(EvalError::wrong_arity("map", Arity::AtLeast(2), arguments.len()));
+    }
+    if collections.iter().any(|collection| matches!(collection, Value::LazySeq(_))) {
+        return Ok(lazy_map(function.clone(), collections.to_vec()));
+    }
+    let collections = collections.iter().map(|collection| items("map", collection)).collect::<Result<Vec<_>, _>>()?;
+    // With several collections, map walks them side by side and stops at the shortest one
+    let len = collections.iter().map(Vec::len).min().unwrap_or(0);
+    let mapped = (0..len)
+        .map(|index| {
+            let function_arguments: Vec<Value> = collections.iter().map(|items| items[index].clone()).collect();
+            apply(function, &function_arguments)
+        })
+        .collect::<Result<Vec<Value>, EvalError>>()?;
+    Ok(mapped.into())
+}
+
+// Mapping or filtering a lazy sequence stays lazy, so pipelines over infinite sequences work
+fn lazy_map(function: Value, collections: Vec<Value>) -> Value {
+    LazySeq::from_thunk(move || {
+        let mut firsts = vec![];
+        let mut rests = vec![];
+        for collection in &collections {
+            let Some((first, rest)) = first_and_rest(collection)? else {
+                return Ok(None);
+            };
+            firsts.push(first);
+            rests.push(rest);
+        }
+        Ok(Some((apply(&function, &firsts)?, lazy_map(function, rests))))
+    })
+}
+
+fn lazy_filter(predicate: Value, collection: Value) -> Value {
+    LazySeq::from_thunk(move || {
+        let mut current = collection;
+        while let Some((first, rest)) = first_and_rest(&current)? {
+            if apply(&predicate, std::slice::from_ref(&first))?.is_truthy() {
+                return Ok(Some((first, lazy_filter(predicate, rest))));
+            }
+            current = rest;
+        }
+        Ok(None)
+    })
+}
+
+fn lisp_filter(arguments: &[Value]) -> Result<Value, EvalError> {
+    let [predicate, collection] = arguments else {
+        return Err(EvalError::wrong_arity("filter", Arity::Exactly(2), arguments.len()));
+    };
+    if let Value::LazySeq(_) = collection {
+        return Ok(lazy_filter(predicate.clone(), collection.clone()));
+    }
+    let mut kept = vec![];
+    for item in items("filter", collection)? {
+        if apply(predicate, std::slice::from_ref(&item))?.is_truthy() {
+            kept.push(item);
+        }
+    }
+    Ok(kept.into())
+}
+
+fn lisp_reduce(arguments: &[Value]) -> Result<Value, EvalError> {
+    let (function, initial, collection) = match arguments {
+        [function, collection] => (function, None, collection),
+        [function, initial, collection] => (function, Some(initial.clone()), collection),
+        _ => return Err(EvalError::wrong_arity("reduce", Arity::Between(2, 3), arguments.len())),
+    };
+    let mut items = items("reduce", collection)?.into_iter();
+    let Some(initial) = initial.or_else(|| items.next()) else {
+        // Nothing to fold, so the function decides what an empty reduction is
+        return apply(function, &[]);
+    };
+    items.try_fold(initial, |accumulator, item| apply(function, &[accumulator, item]))
+}
+
+fn lisp_apply(arguments: &[Value]) -> Result<Value, EvalError> {
+    let [function, leading @ .., collection] = arguments else {
+        return Err(EvalError::wrong_arity("apply", Arity::AtLeast(2), arguments.len()));
+    };
+    let function_arguments: Vec<Value> = leading.iter().cloned().chain(items("apply", collection)?).collect();
+    apply(function, &function_arguments)
+}
+
+// Comparators can either return a number like compare, or a boolean meaning "left goes first"
+fn compare_with(comparator: Option<&Value>, left: &Value, right: &Value) -> Result<Ordering, EvalError> {
+    let Some(comparator) = comparator else {
+        return left.partial_cmp(right)
+            .ok_or_else(|| InvalidArguments(format!("cannot compare {} with {}", left.type_name(), right.type_name())));
+    };
+    match apply(comparator, &[left.clone(), right.clone()])? {
+        Value::Num(number) => Ok(number.cmp(&0)),
+        result if result.is_truthy() => Ok(Ordering::Less),
+        _ if apply(comparator, &[right.clone(), left.clone()])?.is_truthy() => Ok(Ordering::Greater),
+        _ => Ok(Ordering::Equal),
+    }
+}
+
+// A stable merge sort that stops at the first comparison that fails. Unlike slice::sort_by it
+// doesn't mind a comparator that isn't a total order, the order that gives is just unspecified
+fn merge_sort<T>(mut items: Vec<T>, compare: &mut impl FnMut(&T, &T) -> Result<Ordering, EvalError>) -> Result<Vec<T>, EvalError> {
+    if items.len() < 2 {
+        return Ok(items);
+    }
+    let right = merge_sort(items.split_off(items.len() / 2), compare)?;
+    let left = merge_sort(items, compare)?;
+    let mut merged = Vec::with_capacity(left.len() + right.len());
+    let (mut left, mut right) = (left.into_iter().peekable(), right.into_iter().peekable());
+    while let (Some(left_item), Some(right_item)) = (left.peek(), right.peek()) {
+        // Equal items keep their order, so the right one only goes first when it is strictly less
+        let next = if compare(right_item, left_item)?.is_lt() { right.next() } else { left.next() };
+        merged.extend(next);
+    }
+    merged.extend(left.chain(right));
+    Ok(merged)
+}
+
+fn lisp_sort_by(arguments: &[Value]) -> Result<Value, EvalError> {
+    let (key_function, comparator, collection) = match arguments {
+        [key_function, collection] => (key_function, None, collection),
+        [key_function, comparator, collection] => (key_function, Some(comparator), collection),
+        _ => return Err(EvalError::wrong_arity("sort-by", Arity::Between(2, 3), arguments.len())),
+    };
+    let keyed = items("sort-by", collection)?.into_iter()
+        .map(|item| Ok((apply(key_function, std::slice::from_ref(&item))?, item)))
+        .collect::<Result<Vec<(Value, Value)>, EvalError>>()?;
+    let sorted = merge_sort(keyed, &mut |(left, _), (right, _)| compare_with(comparator, left, right))?;
+    Ok(sorted.into_iter().map(|(_, item)| item).collect::<Vec<Value>>().into())
+}
+
+fn lisp_group_by(arguments: &[Value]) -> Result<Value, EvalError> {
+    let [key_function, collection] = arguments else {
+        return Err(EvalError::wrong_arity("group-by", Arity::Exactly(2), arguments.len()));
+    };
+    let mut groups: PersistentMap<Value, Value> = PersistentMap::new();
+    for item in items("group-by", collection)? {
+        let key = apply(key_function, std::slice::from_ref(&item))?;
+        let group = match groups.get(&key) {
+            Some(Value::Vector(group)) => group.push_back(item),
+            _ => PersistentVector::new().push_back(item),
+        };
+        groups = groups.insert(key, Value::Vector(group));
+    }
+    Ok(Value::Map(groups))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::assert_matches;
+
+    use crate::test_support::{eval_str, eval_value};
+
+    use super::*;
+
+    #[test]
+    fn map_and_filter_accept_builtins_and_closures() {
+        assert_eq!(eval_value("(map + [1 2 3] [10 20])").to_string(), "(11 22)");
+        assert_eq!(eval_value("(filter (fn [x] (> x 2)) [1 2 3 4])").to_string(), "(3 4)");
+    }
+
+    #[test]
+    fn reduce_and_apply_fold_arguments() {
+        assert_eq!(eval_value("(reduce + [1 2 3 4])"), Value::Num(10));
+        assert_eq!(eval_value("(reduce (fn [acc x] (conj acc (* x x))) [] [1 2 3])").to_string(), "[1 4 9]");
+        assert_eq!(eval_value("(apply + 1 2 [3 4])"), Value::Num(10));
+    }
+
+    #[test]
+    fn sort_by_takes_number_and_boolean_comparators() {
+        assert_eq!(eval_value("(sort-by (fn [x] x) [3 1 2])").to_string(), "(1 2 3)");
+        assert_eq!(eval_value("(sort-by (fn [x] x) > [3 1 2])").to_string(), "(3 2 1)");
+        assert_eq!(eval_value("(sort-by (fn [p] (get p :n)) (fn [a b] (compare b a)) [{:n 1} {:n 2}])").to_string(), "({:n 2} {:n 1})");
+        assert_eq!(eval_value("(sort-by first [[2 :a] [1 :b] [2 :c]])").to_string(), "([1 :b] [2 :a] [2 :c])");
+    }
+
+    #[test]
+    fn sort_by_copes_with_comparators_that_are_inconsistent_or_fail() {
+        let sorted = eval_value("(sort-by (fn [x] x) (fn [a b] true) (range 50))");
+        assert_eq!(eval_value("(sort-by (fn [x] x) (fn [a b] true) (range 50))"), sorted);
+        let mut numbers = SeqIter::new(sorted).map(|item| item.unwrap().num().unwrap()).collect::<Vec<isize>>();
+        numbers.sort();
+        assert_eq!(numbers, (0..50).collect::<Vec<isize>>());
+
+        let namespace = &mut GlobalNamespace::default();
+        eval_str("(def calls (atom 0))", namespace).unwrap();
+        let error = eval_str("(sort-by (fn [x] x) (fn [a b] (swap! calls (fn [n] (+ n 1))) (+ a :b)) (range 50))", namespace).unwrap_err();
+        assert_matches!(error, EvalError::WrongType { function, .. } if function == "+");
+        assert_eq!(eval_str("@calls", namespace).unwrap(), Value::Num(1));
+    }
+
+    #[test]
+    fn group_by_collects_vectors() {
+        assert_eq!(eval_value("(get (group-by (fn [x] (< x 3)) [1 2 3 4]) true)").to_string(), "[1 2]");
+    }
+}
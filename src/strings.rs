@@ -0,0 +1,192 @@
+use crate::eval::{Arity, EvalError, GlobalNamespace};
+use crate::eval::EvalError::InvalidArguments;
+use crate::sequences::items;
+use crate::value::{PrintMode, Value};
+
+// All positions and lengths are counted in chars (Unicode scalar values), never in bytes, so
+// "héllo" has 5 of them and (subs "héllo" 1 2) is "é"
+pub fn define_builtins(namespace: &mut GlobalNamespace) {
+    namespace.defn(b"str", lisp_str.into());
+    namespace.defn(b"subs", lisp_subs.into());
+    namespace.defn(b"split", lisp_split.into());
+    namespace.defn(b"join", lisp_join.into());
+    namespace.defn(b"trim", lisp_trim.into());
+    namespace.defn(b"upper-case", lisp_upper_case.into());
+    namespace.defn(b"lower-case", lisp_lower_case.into());
+    namespace.defn(b"replace", lisp_replace.into());
+    namespace.defn(b"starts-with?", lisp_starts_with.into());
+    namespace.defn(b"index-of", lisp_index_of.into());
+    namespace.defn(b"count", lisp_count.into());
+}
+
+pub fn expect_str<'a>(function_name: &str, value: &'a Value) -> Result<&'a str, EvalError> {
+    match value {
+        Value::Str(string) => Ok(string),
+        other => Err(EvalError::wrong_type(function_name, "a string", other)),
+    }
+}
+
+fn expect_index(function_name: &str, value: &Value) -> Result<usize, EvalError> {
+    value.num().and_then(|index| usize::try_from(index).ok())
+        .ok_or_else(|| EvalError::wrong_type(function_name, "a non-negative index", value))
+}
+
+// nil disappears, everything else is shown the way people want to read it, so strings come without quotes
+pub fn append_str(buffer: &mut String, value: &Value) {
+    if !matches!(value, Value::Nil) {
+        buffer.push_str(&value.printed(PrintMode::Display).to_string());
+    }
+}
+
+fn lisp_str(arguments: &[Value]) -> Result<Value, EvalError> {
+    let mut buffer = String::new();
+    arguments.iter().for_each(|argument| append_str(&mut buffer, argument));
+    Ok(Value::Str(buffer.into()))
+}
+
+fn byte_offset(string: &str, char_index: usize) -> Option<usize> {
+    string.char_indices().map(|(offset, _)| offset).chain([string.len()]).nth(char_index)
+}
+
+fn lisp_subs(arguments: &[Value]) -> Result<Value, EvalError> {
+    let (string, start, end) = match arguments {
+        [string, start] => (expect_str("subs", string)?, expect_index("subs", start)?, None),
+        [string, start, end] => (expect_str("subs", string)?, expect_index("subs", start)?, Some(expect_index("subs", end)?)),
+        _ => return Err(EvalError::wrong_arity("subs", Arity::Between(2, 3), arguments.len())),
+    };
+    let char_count = string.chars().count();
+    let end = end.unwrap_or(char_count);
+    if start > end || end > char_count {
+        return Err(InvalidArguments(format!("subs range {start}..{end} is out of bounds for a string of {char_count} chars")));
+    }
+    let start_offset = byte_offset(string, start).expect("Start is in bounds");
+    let end_offset = byte_offset(string, end).expect("End is in bounds");
+    Ok(Value::Str(string[start_offset..end_offset].into()))
+}
+
+fn lisp_split(arguments: &[Value]) -> Result<Value, EvalError> {
+    let [string, separator] = arguments else {
+        return Err(EvalError::wrong_arity("split", Arity::Exactly(2), arguments.len()));
+    };
+    let string = expect_str("split", string)?;
+    if let Value::Regex(pattern) = separator {
+        return Ok(Value::Vector(pattern.regex().split(string).map(|part| Value::Str(part.into())).collect()));
+    }
+    let separator = expect_str("split", separator)?;
+    if separator.is_empty() {
+        // Splitting on nothing gives every char on its own
+        return Ok(Value::Vector(string.chars().map(|c| Value::Str(c.to_string().into())).collect()));
+    }
+    Ok(Value::Vector(string.split(separator).map(|part| Value::Str(part.into())).collect()))
+}
+
+fn lisp_join(arguments: &[Value]) -> Result<Value, EvalError> {
+    let (separator, collection) = match arguments {
+        [collection] => ("", collection),
+        [separator, collection] => (expect_str("join", separator)?, collection),
+        _ => return Err(EvalError::wrong_arity("join", Arity::Between(1, 2), arguments.len())),
+    };
+    let mut buffer = String::new();
+    for (index, item) in items("join", collection)?.iter().enumerate() {
+        if index > 0 {
+            buffer.push_str(separator);
+        }
+        append_str(&mut buffer, item);
+    }
+    Ok(Value::Str(buffer.into()))
+}
+
+fn map_str(function_name: &str, arguments: &[Value], function: fn(&str) -> String) -> Result<Value, EvalError> {
+    let [string] = arguments else {
+        return Err(EvalError::wrong_arity(function_name, Arity::Exactly(1), arguments.len()));
+    };
+    Ok(Value::Str(function(expect_str(function_name, string)?).into()))
+}
+
+fn lisp_trim(arguments: &[Value]) -> Result<Value, EvalError> {
+    map_str("trim", arguments, |string| string.trim().to_string())
+}
+
+fn lisp_upper_case(arguments: &[Value]) -> Result<Value, EvalError> {
+    map_str("upper-case", arguments, str::to_uppercase)
+}
+
+fn lisp_lower_case(arguments: &[Value]) -> Result<Value, EvalError> {
+    map_str("lower-case", arguments, str::to_lowercase)
+}
+
+fn lisp_replace(arguments: &[Value]) -> Result<Value, EvalError> {
+    let [string, pattern, replacement] = arguments else {
+        return Err(EvalError::wrong_arity("replace", Arity::Exactly(3), arguments.len()));
+    };
+    let string = expect_str("replace", string)?;
+    let pattern = expect_str("replace", pattern)?;
+    let replacement = expect_str("replace", replacement)?;
+    Ok(Value::Str(string.replace(pattern, replacement).into()))
+}
+
+fn lisp_starts_with(arguments: &[Value]) -> Result<Value, EvalError> {
+    let [string, prefix] = arguments else {
+        return Err(EvalError::wrong_arity("starts-with?", Arity::Exactly(2), arguments.len()));
+    };
+    Ok(Value::Bool(expect_str("starts-with?", string)?.starts_with(expect_str("starts-with?", prefix)?)))
+}
+
+fn lisp_index_of(arguments: &[Value]) -> Result<Value, EvalError> {
+    let [string, substring] = arguments else {
+        return Err(EvalError::wrong_arity("index-of", Arity::Exactly(2), arguments.len()));
+    };
+    let string = expect_str("index-of", string)?;
+    let substring = expect_str("index-of", substring)?;
+    // find gives a byte offset, which has to be turned back into a char index
+    Ok(string.find(substring)
+        .map(|offset| Value::Num(string[..offset].chars().count() as isize))
+        .unwrap_or(Value::Nil))
+}
+
+fn lisp_count(arguments: &[Value]) -> Result<Value, EvalError> {
+    let count = match arguments {
+        [Value::Str(string)] => string.chars().count(),
+        [Value::Vector(vector)] => vector.len(),
+        [Value::Map(map)] => map.len(),
+        [Value::List(list)] => list.len(),
+        [collection] => items("count", collection)?.len(),
+        _ => return Err(EvalError::wrong_arity("count", Arity::Exactly(1), arguments.len())),
+    };
+    Ok(Value::Num(count as isize))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_support::{eval_str, eval_value};
+
+    use super::*;
+
+    #[test]
+    fn positions_count_chars_not_bytes() {
+        assert_eq!(eval_value("(count \"héllo wörld\")"), Value::Num(11));
+        assert_eq!(eval_value("(subs \"héllo\" 1 2)"), Value::Str("é".into()));
+        assert_eq!(eval_value("(subs \"日本語\" 1)"), Value::Str("本語".into()));
+        assert_eq!(eval_value("(index-of \"héllo\" \"llo\")"), Value::Num(2));
+        assert_eq!(eval_value("(index-of \"héllo\" \"x\")"), Value::Nil);
+    }
+
+    #[test]
+    fn case_conversion_is_unicode_aware() {
+        assert_eq!(eval_value("(upper-case \"straße\")"), Value::Str("STRASSE".into()));
+        assert_eq!(eval_value("(lower-case \"ÀÉÎ\")"), Value::Str("àéî".into()));
+    }
+
+    #[test]
+    fn str_split_and_join_round_trip() {
+        assert_eq!(eval_value("(str \"a\" 1 nil :k [1 2])"), Value::Str("a1:k[1 2]".into()));
+        assert_eq!(eval_value("(split \"a,b,,c\" \",\")").to_string(), "[\"a\" \"b\" \"\" \"c\"]");
+        assert_eq!(eval_value("(join \", \" (split \"a b c\" \" \"))"), Value::Str("a, b, c".into()));
+        assert_eq!(eval_value("(replace (trim \"  a-b  \") \"-\" \"+\")"), Value::Str("a+b".into()));
+    }
+
+    #[test]
+    fn out_of_range_subs_is_an_error() {
+        assert!(eval_str("(subs \"abc\" 2 5)", &mut GlobalNamespace::default()).is_err());
+    }
+}
@@ -0,0 +1,65 @@
+use crate::symbol::Symbol;
+
+const MAX_SUGGESTIONS: usize = 3;
+
+// Optimal string alignment distance: Levenshtein that also counts swapping two neighbouring chars
+// as a single edit, since that is the most common typo
+fn edit_distance(left: &str, right: &str) -> usize {
+    let left: Vec<char> = left.chars().collect();
+    let right: Vec<char> = right.chars().collect();
+    let mut table = vec![vec![0; right.len() + 1]; left.len() + 1];
+    table.iter_mut().enumerate().for_each(|(i, row)| row[0] = i);
+    table[0].iter_mut().enumerate().for_each(|(j, distance)| *distance = j);
+    for i in 1..=left.len() {
+        for j in 1..=right.len() {
+            let substitution = table[i - 1][j - 1] + usize::from(left[i - 1] != right[j - 1]);
+            table[i][j] = substitution.min(table[i - 1][j] + 1).min(table[i][j - 1] + 1);
+            if i > 1 && j > 1 && left[i - 1] == right[j - 2] && left[i - 2] == right[j - 1] {
+                table[i][j] = table[i][j].min(table[i - 2][j - 2] + 1);
+            }
+        }
+    }
+    table[left.len()][right.len()]
+}
+
+// The bound names closest to a misspelled one, best first. Short names only tolerate a single typo,
+// longer ones about one per three characters
+pub fn similar_names(name: &str, candidates: impl IntoIterator<Item=Symbol>) -> Vec<String> {
+    let max_distance = (name.chars().count() / 3).max(1);
+    let mut similar: Vec<(usize, &str)> = candidates.into_iter()
+        .map(|candidate| candidate.name())
+        .filter(|candidate| *candidate != name)
+        .map(|candidate| (edit_distance(name, candidate), candidate))
+        .filter(|(distance, _)| *distance <= max_distance)
+        .collect();
+    similar.sort();
+    similar.dedup();
+    similar.into_iter().take(MAX_SUGGESTIONS).map(|(_, candidate)| candidate.to_string()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::assert_matches;
+
+    use crate::eval::{EvalError, GlobalNamespace};
+    use crate::test_support::eval_str;
+
+    use super::*;
+
+    #[test]
+    fn closest_names_come_first() {
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+        assert_eq!(edit_distance("frist", "first"), 1);
+        let candidates = [b"filter".as_slice(), b"first", b"fist", b"map", b"reduce"].map(Symbol::intern);
+        assert_eq!(similar_names("frist", candidates), vec!["first", "fist"]);
+        assert_eq!(similar_names("mpa", candidates), vec!["map"]);
+        assert!(similar_names("unrelated", candidates).is_empty());
+    }
+
+    #[test]
+    fn unbound_symbols_suggest_local_and_global_names() {
+        let error = eval_str("(let [counter 1] (+ cuonter (frist [1])))", &mut GlobalNamespace::default()).unwrap_err();
+        assert_matches!(&error, EvalError::UnboundSymbol { name, suggestions } if name == "cuonter" && suggestions == &["counter"]);
+        assert_eq!(error.to_string(), "unable to resolve symbol: cuonter, did you mean counter?");
+    }
+}
@@ -0,0 +1,173 @@
+use std::collections::HashMap;
+use std::fmt::{Debug, Display, Formatter};
+use std::hash::{BuildHasherDefault, Hasher};
+use std::sync::{LazyLock, Mutex};
+
+// An interned symbol name, comparing and hashing a symbol is just comparing and hashing an id. The
+// top bit of the id says whether the name is qualified like str/join, so resolving an unqualified
+// symbol never has to look at its name
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Ord, PartialOrd)]
+pub struct Symbol(u32);
+
+const QUALIFIED: u32 = 1 << 31;
+
+// Symbol ids are already unique small integers, so maps keyed by symbol can skip real hashing
+#[derive(Default)]
+pub struct SymbolHasher(u64);
+
+impl Hasher for SymbolHasher {
+    fn finish(&self) -> u64 {
+        self.0
+    }
+
+    fn write(&mut self, _bytes: &[u8]) {
+        unreachable!("SymbolHasher only hashes symbol ids")
+    }
+
+    fn write_u32(&mut self, id: u32) {
+        // Spread the ids over the high bits too, hashbrown picks buckets from both ends of the hash
+        self.0 = u64::from(id).wrapping_mul(0x9E37_79B9_7F4A_7C15);
+    }
+}
+
+pub type SymbolMap<V> = HashMap<Symbol, V, BuildHasherDefault<SymbolHasher>>;
+
+#[derive(Default)]
+struct Interner {
+    ids: HashMap<&'static str, Symbol>,
+    names: Vec<&'static str>,
+    // The namespace and name of qualified symbols, split once when they are interned
+    parts: Vec<Option<(Symbol, Symbol)>>,
+}
+
+impl Interner {
+    fn intern(&mut self, name: &str) -> Symbol {
+        if let Some(symbol) = self.ids.get(name) {
+            return *symbol;
+        }
+        // A lone / is just a symbol, and so are names with nothing before or after it
+        let parts = name.split_once('/').filter(|(namespace, name)| !namespace.is_empty() && !name.is_empty())
+            .map(|(namespace, name)| (self.intern(namespace), self.intern(name)));
+        // Interned names live for the whole program, so leaking them lets us hand out &'static str
+        let name: &'static str = Box::leak(name.into());
+        let index: u32 = self.names.len().try_into().ok().filter(|index| index & QUALIFIED == 0).expect("Ran out of symbol ids");
+        let symbol = Symbol(if parts.is_some() { index | QUALIFIED } else { index });
+        self.names.push(name);
+        self.parts.push(parts);
+        self.ids.insert(name, symbol);
+        symbol
+    }
+}
+
+// Symbols the evaluator has to recognise get interned first, so they have fixed ids
+const WELL_KNOWN_SYMBOLS: &[&str] = &["nil", "true", "false", "def", "fn", "let", "if", "do", "&", "lazy-seq", "try*", "catch*", "finally", "handler-bind", "restart-case", "ns", "in-ns", "def-", "core"];
+
+static INTERNER: LazyLock<Mutex<Interner>> = LazyLock::new(|| {
+    let mut interner = Interner::default();
+    WELL_KNOWN_SYMBOLS.iter().for_each(|name| { interner.intern(name); });
+    Mutex::new(interner)
+});
+
+impl Symbol {
+    pub const NIL: Symbol = Symbol(0);
+    pub const TRUE: Symbol = Symbol(1);
+    pub const FALSE: Symbol = Symbol(2);
+    pub const DEF: Symbol = Symbol(3);
+    pub const FN: Symbol = Symbol(4);
+    pub const LET: Symbol = Symbol(5);
+    pub const IF: Symbol = Symbol(6);
+    pub const DO: Symbol = Symbol(7);
+    pub const AMPERSAND: Symbol = Symbol(8);
+    pub const LAZY_SEQ: Symbol = Symbol(9);
+    pub const TRY: Symbol = Symbol(10);
+    pub const CATCH: Symbol = Symbol(11);
+    pub const FINALLY: Symbol = Symbol(12);
+    pub const HANDLER_BIND: Symbol = Symbol(13);
+    pub const RESTART_CASE: Symbol = Symbol(14);
+    pub const NS: Symbol = Symbol(15);
+    pub const IN_NS: Symbol = Symbol(16);
+    pub const DEF_PRIVATE: Symbol = Symbol(17);
+    pub const CORE: Symbol = Symbol(18);
+
+    // The reader rejects names that aren't UTF-8, anything else is replaced rather than panicking
+    pub fn intern(name: &[u8]) -> Symbol {
+        let name = String::from_utf8_lossy(name);
+        INTERNER.lock().unwrap().intern(&name)
+    }
+
+    pub fn name(self) -> &'static str {
+        INTERNER.lock().unwrap().names[self.index()]
+    }
+
+    fn index(self) -> usize {
+        (self.0 & !QUALIFIED) as usize
+    }
+
+    // str/join is the symbol join in the namespace or alias str
+    pub fn qualified(self) -> Option<(Symbol, Symbol)> {
+        if self.0 & QUALIFIED == 0 {
+            return None;
+        }
+        INTERNER.lock().unwrap().parts[self.index()]
+    }
+}
+
+impl AsRef<[u8]> for Symbol {
+    fn as_ref(&self) -> &[u8] {
+        self.name().as_bytes()
+    }
+}
+
+impl From<&str> for Symbol {
+    fn from(value: &str) -> Self {
+        Symbol::intern(value.as_bytes())
+    }
+}
+
+impl Display for Symbol {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl Debug for Symbol {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Symbol({})", self.name())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn same_name_interns_to_same_symbol() {
+        assert_eq!(Symbol::intern(b"interned-twice"), Symbol::intern(b"interned-twice"));
+        assert_ne!(Symbol::intern(b"interned-a"), Symbol::intern(b"interned-b"));
+    }
+
+    #[test]
+    fn well_known_symbols_have_fixed_ids() {
+        assert_eq!(Symbol::intern(b"nil"), Symbol::NIL);
+        assert_eq!(Symbol::intern(b"&"), Symbol::AMPERSAND);
+        assert_eq!(Symbol::intern(b"core"), Symbol::CORE);
+    }
+
+    #[test]
+    fn symbol_keeps_its_name() {
+        let symbol = Symbol::intern(b"some-name");
+        assert_eq!(symbol.name(), "some-name");
+        assert_eq!(symbol.as_ref(), b"some-name");
+    }
+
+    #[test]
+    fn qualified_symbols_are_split_when_interned() {
+        let symbol = Symbol::intern(b"str/join");
+        assert_eq!(symbol.name(), "str/join");
+        assert_eq!(symbol.qualified(), Some((Symbol::intern(b"str"), Symbol::intern(b"join"))));
+        assert_eq!(Symbol::intern(b"str/join"), symbol);
+        for name in ["join", "/", "str/", "/join"] {
+            assert_eq!(Symbol::intern(name.as_bytes()).qualified(), None, "{name}");
+        }
+    }
+}
@@ -0,0 +1,52 @@
+use std::fs;
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+use std::{env, process};
+
+use crate::eval::{eval, EvalError, GlobalNamespace};
+use crate::tokenize::tokenize_all;
+use crate::value::Value;
+
+// Evaluates every form and returns the value of the last one. Closures only hold on to their
+// namespace weakly, so tests that realise lazy sequences keep theirs around
+pub fn eval_str(source: &str, namespace: &mut GlobalNamespace) -> Result<Value, EvalError> {
+    let nodes = tokenize_all(source.as_bytes(), "test".into()).unwrap();
+    nodes.iter().try_fold(Value::Nil, |_, node| eval(node, namespace))
+}
+
+// For forms that don't need anything defined first and don't fail
+pub fn eval_value(source: &str) -> Value {
+    eval_str(source, &mut GlobalNamespace::default()).unwrap()
+}
+
+// A directory under the system's temp directory with the given files in it, removed again when the
+// test is done with it. The name keeps tests running at the same time apart
+pub struct TempDir(PathBuf);
+
+impl TempDir {
+    pub fn with_files(name: &str, files: &[(&str, &str)]) -> TempDir {
+        let directory = env::temp_dir().join(format!("risp-{}-{name}", process::id()));
+        let _ = fs::remove_dir_all(&directory);
+        fs::create_dir_all(&directory).unwrap();
+        for (path, source) in files {
+            let path = directory.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, source).unwrap();
+        }
+        TempDir(directory)
+    }
+}
+
+impl Deref for TempDir {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
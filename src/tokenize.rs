@@ -1,10 +1,14 @@
+use std::cell::Cell;
 use std::fmt::{Debug, Display, Formatter};
+use std::rc::Rc;
 use std::str;
 use str::from_utf8;
 
 use crate::parse_error::ParseError;
-use crate::parse_error::ParseError::{CannotParseEmpty, CannotParseNumber, MissingDoubleQuote, MissingLeftParenthesis, MissingRightParenthesis, StringDidntEnd};
-use crate::tokenize::AstNode::{List, Num, Str, Sym};
+use crate::parse_error::ParseError::{CannotParseEmpty, CannotParseNumber, MissingDoubleQuote, MissingLeftParenthesis, MissingRightParenthesis, NotAWholeNumber, NumberOutOfRange, StringDidntEnd, InvalidUtf8, MissingClosingDelimiter, MismatchedDelimiter, OddNumberOfMapForms, UnexpectedClosingDelimiter, InvalidRegex, InvalidEscape};
+use crate::pattern::Pattern;
+use crate::symbol::Symbol;
+use crate::tokenize::AstNode::{Keyword, List, Map, Num, Regex, Str, Sym, Vector};
 use crate::tokenize::AstToken::{Parsed, ParsedRest};
 
 #[derive(Debug, Eq, PartialEq)]
@@ -14,129 +18,158 @@ pub enum AstToken<'a> {
 }
 
 
-#[derive(Eq, PartialEq, Clone)]
-pub enum AstNode {
-    List(Box<[AstNode]>),
-    Num(isize),
-    Sym(Box<[u8]>),
-    Str(Box<[u8]>),
+// Where a list was read from, lines and columns count from 1
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Span {
+    pub source: Option<Rc<str>>,
+    pub line: usize,
+    pub column: usize,
 }
 
-pub struct Sexpr {
-    atom: Box<[u8]>,
-
+impl Display for Span {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match &self.source {
+            Some(source) => write!(f, "{}:{}:{}", source, self.line, self.column),
+            None => write!(f, "{}:{}", self.line, self.column),
+        }
+    }
 }
 
-pub enum Value {
+#[derive(Clone)]
+pub enum AstNode {
+    // Lists are the only nodes that get evaluated as calls, so only they keep their span for backtraces
+    List(Box<[AstNode]>, Option<Span>),
     Num(isize),
-    Str(Box<[u8]>),
+    Sym(Symbol),
+    Str(Rc<str>),
+    Keyword(Symbol),
+    Vector(Box<[AstNode]>),
+    // Keys and values alternate, the reader makes sure there is an even number of them
+    Map(Box<[AstNode]>),
+    // Regex literals are compiled while reading, so a bad pattern is a parse error
+    Regex(Rc<Pattern>),
 }
 
-impl Value {
-    pub fn num(&self) -> Option<isize> {
-        match self {
-            Value::Num(num) => { Some(*num) }
-            Value::Str(_) => { None }
-        }
-    }
-}
-
-impl Display for Value {
-    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        match self {
-            Value::Num(number) => {
-                write!(f, "{}", number)?;
-                Ok(())
-            }
-            Value::Str(string_buffer) => {
-                write!(f, "\"{}\"", from_utf8(string_buffer).expect("Strings should always be UTF-8"))?;
-                Ok(())
-            }
-        }
+fn write_nodes(f: &mut Formatter<'_>, open: &str, nodes: &[AstNode], close: &str) -> std::fmt::Result {
+    write!(f, "{}", open)?;
+    let mut node_iter = nodes.iter();
+    if let Some(first_node) = node_iter.next() {
+        write!(f, "{}", first_node)?;
     }
+    node_iter.try_for_each(|node| write!(f, " {}", node))?;
+    write!(f, "{}", close)?;
+    Ok(())
 }
 
 impl Display for AstNode {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         match self {
-            List(nodes) => {
-                write!(f, "(", )?;
-                let mut node_iter = nodes.iter();
-                if let Some(first_node) = node_iter.next() {
-                    write!(f, "{}", first_node)?;
-                }
-                node_iter.map(|node| write!(f, " {}", node)).collect::<Result<_, _>>()?;
-                write!(f, ")", )?;
-                Ok(())
-            }
+            List(nodes, _) => write_nodes(f, "(", nodes, ")"),
+            Vector(nodes) => write_nodes(f, "[", nodes, "]"),
+            Map(nodes) => write_nodes(f, "{", nodes, "}"),
             Num(number) => {
                 write!(f, "{}", number)?;
                 Ok(())
             }
-            Sym(symbol_buffer) => {
-                write!(f, "{}", from_utf8(symbol_buffer).expect("Symbols should always be UTF-8"))?;
+            Sym(symbol) => {
+                write!(f, "{}", symbol)?;
+                Ok(())
+            }
+            Keyword(keyword) => {
+                write!(f, ":{}", keyword)?;
                 Ok(())
             }
-            Str(string_buffer) => {
-                write!(f, "\"{}\"", from_utf8(string_buffer).expect("Strings should always be UTF-8"))?;
+            Str(string) => write_escaped_str(f, string),
+            Regex(pattern) => {
+                write!(f, "{}", pattern)?;
                 Ok(())
             }
         }
     }
 }
 
+fn write_debug_nodes(f: &mut Formatter<'_>, name: &str, nodes: &[AstNode]) -> std::fmt::Result {
+    write!(f, "{}(", name)?;
+    let mut node_iter = nodes.iter();
+    if let Some(first_node) = node_iter.next() {
+        write!(f, "{:?}", first_node)?;
+    }
+    node_iter.try_for_each(|node| write!(f, " {:?},", node))?;
+    write!(f, ")", )?;
+    Ok(())
+}
+
 impl Debug for AstNode {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         match self {
-            List(nodes) => {
-                write!(f, "List(", )?;
-                let mut node_iter = nodes.iter();
-                if let Some(first_node) = node_iter.next() {
-                    write!(f, "{:?}", first_node)?;
-                }
-                node_iter.map(|node| write!(f, " {:?},", node)).collect::<Result<_, _>>()?;
-                write!(f, ")", )?;
-                Ok(())
-            }
+            List(nodes, _) => write_debug_nodes(f, "List", nodes),
+            Vector(nodes) => write_debug_nodes(f, "Vector", nodes),
+            Map(nodes) => write_debug_nodes(f, "Map", nodes),
             Num(number) => {
                 write!(f, "Num({})", number)?;
                 Ok(())
             }
-            Sym(symbol_buffer) => {
-                write!(f, "Sym({})", from_utf8(symbol_buffer).expect("Symbols should always be UTF-8"))?;
+            Sym(symbol) => {
+                write!(f, "Sym({})", symbol)?;
+                Ok(())
+            }
+            Keyword(keyword) => {
+                write!(f, "Keyword({})", keyword)?;
+                Ok(())
+            }
+            Str(string) => {
+                write!(f, "Str(")?;
+                write_escaped_str(f, string)?;
+                write!(f, ")")?;
                 Ok(())
             }
-            Str(string_buffer) => {
-                write!(f, "Str(\"{}\")", from_utf8(string_buffer).expect("Strings should always be UTF-8"))?;
+            Regex(pattern) => {
+                write!(f, "Regex({})", pattern)?;
                 Ok(())
             }
         }
     }
 }
 
+// Where a list was read doesn't matter for equality, (f x) is the same code wherever it is written
+impl PartialEq for AstNode {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (List(left, _), List(right, _)) => left == right,
+            (Vector(left), Vector(right)) => left == right,
+            (Map(left), Map(right)) => left == right,
+            (Num(left), Num(right)) => left == right,
+            (Sym(left), Sym(right)) => left == right,
+            (Str(left), Str(right)) => left == right,
+            (Keyword(left), Keyword(right)) => left == right,
+            (Regex(left), Regex(right)) => left == right,
+            _ => false,
+        }
+    }
+}
+
+impl Eq for AstNode {}
+
 impl AstNode {
     fn _new() -> AstNode {
         vec![].into()
     }
 
     fn try_parse_atom(buffer: &[u8]) -> Result<AstNode, ParseError> {
-        if let Some(bad_char) = buffer.iter().filter(is_atom_forbidden_char).next() {
+        if let Some(bad_char) = buffer.iter().find(is_atom_forbidden_char) {
             return Err(ParseError::ForbiddenCharInSymbol((*bad_char).into()));
         }
-        let first_char = buffer
-            .get(0)
-            .expect("We can't pass an empty atom");
-
-        let atom_is_number = first_char.is_ascii_digit() || *first_char == b'-';
-        if !atom_is_number { // Then it is a symbol
-            return Ok(Sym(buffer.into()));
+        from_utf8(buffer).map_err(|_| InvalidUtf8)?;
+        if let [b':', keyword @ ..] = buffer {
+            if !keyword.is_empty() {
+                return Ok(Keyword(Symbol::intern(keyword)));
+            }
         }
-        let buffer = from_utf8(buffer).expect("Has to be UTF-8");
-        if let Ok(number) = buffer.parse() {
-            Ok(Num(number))
-        } else {
-            Err(CannotParseNumber(buffer.to_string()))
+        if !is_number_literal(buffer) { // Then it is a symbol
+            return Ok(Sym(Symbol::intern(buffer)));
         }
+        let buffer = from_utf8(buffer).map_err(|_| InvalidUtf8)?;
+        parse_number(buffer).map(Num)
     }
 
     fn from_symbol(buffer: &[u8]) -> AstNode {
@@ -146,7 +179,7 @@ impl AstNode {
 
 impl From<Vec<AstNode>> for AstNode {
     fn from(value: Vec<AstNode>) -> Self {
-        List(value.into_boxed_slice())
+        List(value.into_boxed_slice(), None)
     }
 }
 
@@ -157,8 +190,173 @@ impl<const N: usize> From<&[u8; N]> for AstNode {
 }
 
 
-const SYMBOL_FORBIDDEN_CHARS: &[u8] = b"()\"\'";
+// Numbers start with a digit, optionally preceded by a sign, so `-` and `+` alone are still symbols
+fn is_number_literal(atom: &[u8]) -> bool {
+    match atom {
+        [b'-' | b'+', second_char, ..] => second_char.is_ascii_digit(),
+        [first_char, ..] => first_char.is_ascii_digit(),
+        [] => panic!("We can't pass an empty atom"),
+    }
+}
+
+// Underscores are only allowed in between digits -> "1_000" is fine, "_1", "1_" and "1__0" are not
+fn strip_underscores(digits: &str) -> Option<String> {
+    if digits.is_empty() || digits.starts_with('_') || digits.ends_with('_') || digits.contains("__") {
+        return None;
+    }
+    Some(digits.replace('_', ""))
+}
+
+enum NumberError {
+    InvalidDigits,
+    OutOfRange,
+    NotAWholeNumber,
+}
+
+fn parse_magnitude(digits: &str, radix: u32) -> Result<u128, NumberError> {
+    let digits = strip_underscores(digits).ok_or(NumberError::InvalidDigits)?;
+    if !digits.chars().all(|c| c.is_digit(radix)) {
+        return Err(NumberError::InvalidDigits);
+    }
+    u128::from_str_radix(&digits, radix).map_err(|_| NumberError::OutOfRange)
+}
+
+fn parse_exponent(exponent: &str) -> Result<i64, NumberError> {
+    let (is_negative, digits) = match exponent.as_bytes().first() {
+        Some(b'-') => (true, &exponent[1..]),
+        Some(b'+') => (false, &exponent[1..]),
+        _ => (false, exponent),
+    };
+    let magnitude = i64::try_from(parse_magnitude(digits, 10)?).map_err(|_| NumberError::OutOfRange)?;
+    Ok(if is_negative { -magnitude } else { magnitude })
+}
+
+// Decimal literals may carry a fraction and an exponent ("2.5e3"), as long as the result is a whole number
+fn parse_decimal_magnitude(literal: &str) -> Result<u128, NumberError> {
+    let (mantissa, exponent) = match literal.split_once(['e', 'E']) {
+        Some((mantissa, exponent)) => (mantissa, parse_exponent(exponent)?),
+        None => (literal, 0),
+    };
+    let (integer_digits, fraction_digits) = match mantissa.split_once('.') {
+        Some((integer_digits, fraction_digits)) => {
+            let fraction_digits = strip_underscores(fraction_digits).ok_or(NumberError::InvalidDigits)?;
+            (integer_digits, fraction_digits)
+        }
+        None => (mantissa, String::new()),
+    };
+    let integer_digits = strip_underscores(integer_digits).ok_or(NumberError::InvalidDigits)?;
+    let magnitude = parse_magnitude(&(integer_digits + &fraction_digits), 10)?;
+    // Exponents near the limits of i64 are out of range rather than overflowing
+    let scale = i64::try_from(fraction_digits.len()).ok()
+        .and_then(|fraction_length| exponent.checked_sub(fraction_length))
+        .ok_or(NumberError::OutOfRange)?;
+    let power_of_ten = |scale: i64| u32::try_from(scale).ok().and_then(|scale| 10u128.checked_pow(scale));
+    if magnitude == 0 {
+        return Ok(0);
+    }
+    if scale >= 0 {
+        power_of_ten(scale)
+            .and_then(|factor| magnitude.checked_mul(factor))
+            .ok_or(NumberError::OutOfRange)
+    } else {
+        match scale.checked_neg().and_then(power_of_ten) {
+            Some(divisor) if magnitude % divisor == 0 => Ok(magnitude / divisor),
+            _ => Err(NumberError::NotAWholeNumber),
+        }
+    }
+}
+
+// Parses "-0xFF", "0o17", "0b1010", "1_000_000" and "1e6" style literals
+pub fn parse_number(literal: &str) -> Result<isize, ParseError> {
+    let (is_negative, unsigned) = match literal.as_bytes().first() {
+        Some(b'-') => (true, &literal[1..]),
+        Some(b'+') => (false, &literal[1..]),
+        _ => (false, literal),
+    };
+    let radix_prefix = unsigned.get(..2).map(str::to_ascii_lowercase);
+    let magnitude = match radix_prefix.as_deref() {
+        Some("0x") => parse_magnitude(&unsigned[2..], 16),
+        Some("0o") => parse_magnitude(&unsigned[2..], 8),
+        Some("0b") => parse_magnitude(&unsigned[2..], 2),
+        _ => parse_decimal_magnitude(unsigned),
+    };
+    let signed = magnitude
+        .and_then(|magnitude| i128::try_from(magnitude).map_err(|_| NumberError::OutOfRange))
+        .map(|magnitude| if is_negative { -magnitude } else { magnitude })
+        .and_then(|signed| isize::try_from(signed).map_err(|_| NumberError::OutOfRange));
+    signed.map_err(|error| match error {
+        NumberError::InvalidDigits => CannotParseNumber(literal.to_string()),
+        NumberError::OutOfRange => NumberOutOfRange(literal.to_string()),
+        NumberError::NotAWholeNumber => NotAWholeNumber(literal.to_string()),
+    })
+}
+
+// Renders a number using the same prefixes the reader understands, so the output can be read back
+pub fn format_radix(number: isize, radix: u32) -> Option<String> {
+    let sign = if number < 0 { "-" } else { "" };
+    let magnitude = number.unsigned_abs();
+    match radix {
+        2 => Some(format!("{sign}0b{magnitude:b}")),
+        8 => Some(format!("{sign}0o{magnitude:o}")),
+        10 => Some(format!("{number}")),
+        16 => Some(format!("{sign}0x{magnitude:x}")),
+        _ => None,
+    }
+}
+
+const SYMBOL_FORBIDDEN_CHARS: &[u8] = b"()[]{}\"\'";
 
+const CLOSING_DELIMITERS: &[u8] = b")]}";
+
+fn closing_delimiter_for(opening_delimiter: u8) -> Option<u8> {
+    match opening_delimiter {
+        b'(' => Some(b')'),
+        b'[' => Some(b']'),
+        b'{' => Some(b'}'),
+        _ => None,
+    }
+}
+
+fn missing_closing_delimiter(closing_delimiter: u8) -> ParseError {
+    match closing_delimiter {
+        b')' => MissingRightParenthesis,
+        other => MissingClosingDelimiter(other.into()),
+    }
+}
+
+fn sequence_node(opening_delimiter: u8, nodes: Vec<AstNode>, span: Span) -> Result<AstNode, ParseError> {
+    match opening_delimiter {
+        b'[' => Ok(Vector(nodes.into_boxed_slice())),
+        b'{' if !nodes.len().is_multiple_of(2) => Err(OddNumberOfMapForms),
+        b'{' => Ok(Map(nodes.into_boxed_slice())),
+        _ => Ok(List(nodes.into_boxed_slice(), Some(span))),
+    }
+}
+
+
+// Commas count as whitespace, so maps can be written (and are printed) as {:a 1, :b 2}
+pub(crate) fn is_whitespace(c: &u8) -> bool {
+    c.is_ascii_whitespace() || *c == b','
+}
+
+// Comments run from a semicolon to the end of the line and are skipped like whitespace
+fn trim_whitespace_start(buffer: &[u8]) -> &[u8] {
+    let mut rest = buffer;
+    loop {
+        let start = rest.iter().position(|c| !is_whitespace(c)).unwrap_or(rest.len());
+        rest = &rest[start..];
+        let [b';', comment @ ..] = rest else {
+            return rest;
+        };
+        rest = &comment[comment.iter().position(|c| *c == b'\n').unwrap_or(comment.len())..];
+    }
+}
+
+fn trim_whitespace(buffer: &[u8]) -> &[u8] {
+    let trimmed = trim_whitespace_start(buffer);
+    let end = trimmed.iter().rposition(|c| !is_whitespace(c)).map_or(0, |last| last + 1);
+    &trimmed[..end]
+}
 
 fn is_atom_forbidden_char(c: &&u8) -> bool {
     SYMBOL_FORBIDDEN_CHARS.contains(*c)
@@ -166,117 +364,278 @@ fn is_atom_forbidden_char(c: &&u8) -> bool {
 
 
 fn get_cutting_index_for_symbol(trimmed_symbol_buffer: &[u8]) -> usize {
-    let first_closing_paren_idx = trimmed_symbol_buffer.iter().position(|&c| c == b')');
-    let first_whitespace_idx = trimmed_symbol_buffer.iter().position(u8::is_ascii_whitespace);
-    match (first_closing_paren_idx, first_whitespace_idx) {
-        (Some(pindx), Some(windx)) => {
-            // If the whitespace is right after the parens, remove parens
-            if windx == pindx + 1 {
-                pindx
-            } else {
-                windx
+    let first_whitespace_idx = trimmed_symbol_buffer.iter()
+        .position(|c| is_whitespace(c) || *c == b';')
+        .unwrap_or(trimmed_symbol_buffer.len());
+    let token = &trimmed_symbol_buffer[..first_whitespace_idx];
+    // Symbols can be directly followed by closing delimiters -> "x))" cuts before the parens,
+    // but anything else before the next whitespace makes it a bad symbol -> "x)z"
+    let is_closing_delimiter = |c: &u8| CLOSING_DELIMITERS.contains(c);
+    match token.iter().position(is_closing_delimiter) {
+        Some(pindx) if token[pindx..].iter().all(is_closing_delimiter) => pindx,
+        _ => first_whitespace_idx,
+    }
+}
+
+fn read_atom(buffer: &[u8]) -> Result<(AstNode, &[u8]), ParseError> {
+    let cutting_index = get_cutting_index_for_symbol(buffer);
+    let (to_parse, rest) = buffer.split_at(cutting_index);
+    Ok((AstNode::try_parse_atom(to_parse)?, rest))
+}
+
+// Reads the atom, string or regex the buffer starts with and returns it with the unread rest, which
+// lets the comment preserving reader of the formatter share the rules for everything but sequences
+pub(crate) fn read_literal(buffer: &[u8]) -> Result<(AstNode, &[u8]), ParseError> {
+    match buffer {
+        [b'"', string @ ..] => read_string(string).and_then(check_quoted_literal_end),
+        [b'#', b'"', regex @ ..] => read_regex(regex).and_then(check_quoted_literal_end),
+        atom => read_atom(atom),
+    }
+}
+
+fn literal_token(node: AstNode, rest: &[u8]) -> AstToken<'_> {
+    if trim_whitespace(rest).is_empty() {
+        Parsed(node)
+    } else {
+        ParsedRest((node, rest))
+    }
+}
+
+fn tokenize_literal(buffer: &[u8]) -> Result<AstToken<'_>, ParseError> {
+    let (node, rest) = read_literal(buffer)?;
+    Ok(literal_token(node, rest))
+}
+
+fn read_string(buffer: &[u8]) -> Result<(AstNode, &[u8]), ParseError> {
+    // Read until the first quote that isn't escaped
+    let mut bytes = vec![];
+    let mut index = 0;
+    loop {
+        match buffer.get(index) {
+            None => return Err(MissingDoubleQuote),
+            Some(b'"') => break,
+            Some(b'\\') => {
+                let (unescaped, escape_len) = unescape(&buffer[index + 1..])?;
+                bytes.extend_from_slice(unescaped.encode_utf8(&mut [0; 4]).as_bytes());
+                index += 1 + escape_len;
+            }
+            Some(byte) => {
+                bytes.push(*byte);
+                index += 1;
             }
         }
-        (None, Some(windx)) => {
-            windx
-        }
-        (Some(pinx), None) => {
-            pinx
-        }
-        (None, None) => { trimmed_symbol_buffer.len() }
     }
+    let full_string = String::from_utf8(bytes).map_err(|_| InvalidUtf8)?;
+    Ok((Str(full_string.into()), &buffer[index + 1..]))
 }
 
-fn tokenize_atom(buffer: &[u8]) -> Result<AstToken, ParseError> {
-    let trimmed = buffer.trim_ascii();
-    let cutting_index = get_cutting_index_for_symbol(trimmed);
-    let (to_parse, rest) = trimmed.split_at(cutting_index);
-    let trimmed_rest = rest.trim_ascii();
-    let node = AstNode::try_parse_atom(to_parse)?;
-    if trimmed_rest.is_empty() {
-        Ok(Parsed(node))
-    } else {
-        Ok(ParsedRest((node, rest)))
+// Takes what follows a backslash and returns the char it stands for and how many bytes it used up
+fn unescape(escape: &[u8]) -> Result<(char, usize), ParseError> {
+    match escape {
+        [] => Err(MissingDoubleQuote),
+        [b'n', ..] => Ok(('\n', 1)),
+        [b't', ..] => Ok(('\t', 1)),
+        [b'r', ..] => Ok(('\r', 1)),
+        [b'0', ..] => Ok(('\0', 1)),
+        [b'"', ..] => Ok(('"', 1)),
+        [b'\\', ..] => Ok(('\\', 1)),
+        [b'u', b'{', code_point @ ..] => {
+            let Some(closing_brace_index) = code_point.iter().position(|c| *c == b'}') else {
+                return Err(InvalidEscape(String::from_utf8_lossy(escape).into()));
+            };
+            from_utf8(&code_point[..closing_brace_index]).ok()
+                .and_then(|hex| u32::from_str_radix(hex, 16).ok())
+                .and_then(char::from_u32)
+                .map(|unescaped| (unescaped, closing_brace_index + 3))
+                .ok_or_else(|| InvalidEscape(String::from_utf8_lossy(&escape[..closing_brace_index + 3]).into()))
+        }
+        [other, ..] => Err(InvalidEscape(char::from(*other).into())),
     }
 }
 
-fn tokenize_string(buffer: &[u8]) -> Result<AstToken, ParseError> {
-    // Read until end quote
-    let Some((full_string, rest)) = buffer.split_once(|c| *c == b'"') else {
-        return Err(MissingDoubleQuote);
+// The inverse of tokenize_string, quotes the string and escapes whatever the reader would not
+// take back literally
+pub fn write_escaped_str(f: &mut Formatter<'_>, string: &str) -> std::fmt::Result {
+    write!(f, "\"")?;
+    string.chars().try_for_each(|c| match c {
+        '"' => write!(f, "\\\""),
+        '\\' => write!(f, "\\\\"),
+        '\n' => write!(f, "\\n"),
+        '\t' => write!(f, "\\t"),
+        '\r' => write!(f, "\\r"),
+        '\0' => write!(f, "\\0"),
+        c if c.is_control() => write!(f, "\\u{{{:x}}}", c as u32),
+        c => write!(f, "{}", c),
+    })?;
+    write!(f, "\"")?;
+    Ok(())
+}
+
+fn read_regex(buffer: &[u8]) -> Result<(AstNode, &[u8]), ParseError> {
+    // Backslashes are left for the regex engine, here they only stop \" from ending the literal
+    let mut index = 0;
+    let closing_quote_index = loop {
+        match buffer.get(index) {
+            None => return Err(MissingDoubleQuote),
+            Some(b'\\') => index += 2,
+            Some(b'"') => break index,
+            Some(_) => index += 1,
+        }
     };
-    let node = Str(full_string.into());
-    if rest.is_empty() {
-        return Ok(Parsed(node));
+    let source = from_utf8(&buffer[..closing_quote_index]).map_err(|_| InvalidUtf8)?;
+    let pattern = Pattern::new(source).map_err(|error| InvalidRegex(error.to_string()))?;
+    Ok((Regex(Rc::new(pattern)), &buffer[closing_quote_index + 1..]))
+}
+
+// After the closing quote there has to be whitespace, a comment, a closing delimiter or nothing at all
+fn check_quoted_literal_end((node, rest): (AstNode, &[u8])) -> Result<(AstNode, &[u8]), ParseError> {
+    match rest.first() {
+        None => Ok((node, rest)),
+        Some(c) if is_whitespace(c) || *c == b';' || CLOSING_DELIMITERS.contains(c) => Ok((node, rest)),
+        Some(_) => Err(StringDidntEnd),
     }
-    if rest[0].is_ascii_whitespace() {
-        return Ok(ParsedRest((node, rest)));
-    };
-    let rest = rest.trim_ascii_start();
-    let rest_first_char = rest.get(0).expect("We know it was not whitespace from before");
-    if *rest_first_char == b')' {
-        return Ok(ParsedRest((node, rest)));
-    } else {
-        return Err(StringDidntEnd);
+}
+
+// Turns positions in the buffer being read into spans, which needs the start of every line
+struct Reader<'a> {
+    source: &'a [u8],
+    name: Option<Rc<str>>,
+    first_line: usize,
+    line_starts: Vec<usize>,
+    // Where the innermost form that failed to read starts
+    error_offset: Cell<Option<usize>>,
+}
+
+impl<'a> Reader<'a> {
+    fn new(source: &'a [u8], name: Option<Rc<str>>, first_line: usize) -> Reader<'a> {
+        let line_starts = std::iter::once(0)
+            .chain(source.iter().enumerate().filter(|(_, c)| **c == b'\n').map(|(index, _)| index + 1))
+            .collect();
+        Reader { source, name, first_line, line_starts, error_offset: Cell::new(None) }
+    }
+
+    // The position has to be a subslice of the source
+    fn offset(&self, position: &[u8]) -> usize {
+        position.as_ptr().addr() - self.source.as_ptr().addr()
+    }
+
+    fn span(&self, position: &[u8]) -> Span {
+        let offset = self.offset(position);
+        let line = self.line_starts.partition_point(|start| *start <= offset) - 1;
+        // Columns count chars, so skip UTF-8 continuation bytes
+        let column = self.source[self.line_starts[line]..offset].iter().filter(|c| **c & 0xC0 != 0x80).count() + 1;
+        Span { source: self.name.clone(), line: self.first_line + line, column }
     }
 }
 
 // Assuming the token is a list without outer parens -> "x y (y z s) s (f (f)) (s (s ( )))"
 // Attempt to return token and rest -> "x", "y (y z s) s (f (f)) (s (s ( )))"
-pub fn tokenize(buffer: &[u8]) -> Result<AstToken, ParseError> {
-    let trimmed = buffer.trim_ascii();
+fn read<'a>(reader: &Reader, buffer: &'a [u8]) -> Result<AstToken<'a>, ParseError> {
+    read_form(reader, buffer).inspect_err(|_| {
+        // The innermost form fails first, the ones around it keep its offset
+        if reader.error_offset.get().is_none() {
+            reader.error_offset.set(Some(reader.offset(trim_whitespace(buffer))));
+        }
+    })
+}
+
+fn read_form<'a>(reader: &Reader, buffer: &'a [u8]) -> Result<AstToken<'a>, ParseError> {
+    let trimmed = trim_whitespace(buffer);
     let Some((first_char, rest)) = trimmed.split_first() else {
         return Err(CannotParseEmpty);
     };
     if *first_char == b')' {
         return Err(MissingLeftParenthesis);
     };
-    if *first_char == b'"' {
-        return tokenize_string(rest);
+    if CLOSING_DELIMITERS.contains(first_char) {
+        return Err(UnexpectedClosingDelimiter((*first_char).into()));
+    };
+    if *first_char == b'@' {
+        // @form is short for (deref form)
+        let deref = |node| List(Box::new([Sym(Symbol::intern(b"deref")), node]), Some(reader.span(trimmed)));
+        return Ok(match read(reader, rest)? {
+            Parsed(node) => Parsed(deref(node)),
+            ParsedRest((node, rest)) => ParsedRest((deref(node), rest)),
+        });
     }
-    if first_char != &b'(' {
+    let opening_delimiter = *first_char;
+    let Some(closing_delimiter) = closing_delimiter_for(opening_delimiter) else {
         // Thank god! we can tokenize this right away!
-        return tokenize_atom(trimmed);
+        return tokenize_literal(trimmed);
     };
     // Pain in the butt! Recursively tokenize -> skip left paren
-    let mut trimmed_rest = rest.trim_ascii();
+    let mut trimmed_rest = trim_whitespace(rest);
     if trimmed_rest.is_empty() {
-        return Err(MissingRightParenthesis);
+        return Err(missing_closing_delimiter(closing_delimiter));
     };
 
     let mut nodes = vec![];
     loop {
         let Some((first_char, after_first_char)) = trimmed_rest.split_first() else {
             // Cant be fully parsed since we expect a closing parenthesis
-            return Err(MissingRightParenthesis);
+            return Err(missing_closing_delimiter(closing_delimiter));
         };
-        if *first_char == b')' {
-            if after_first_char.is_empty() {
-                // Nice, we finished
-                return Ok(Parsed(nodes.into()));
-            } else {
-                return Ok(ParsedRest((nodes.into(), after_first_char.trim_ascii())));
-            }
+        if *first_char == closing_delimiter {
+            let node = sequence_node(opening_delimiter, nodes, reader.span(trimmed))?;
+            // Nice, we finished, unless there is more than whitespace and comments left
+            return Ok(literal_token(node, trim_whitespace(after_first_char)));
         };
-        match tokenize(trimmed_rest)? {
+        if CLOSING_DELIMITERS.contains(first_char) {
+            return Err(MismatchedDelimiter(closing_delimiter.into(), (*first_char).into()));
+        }
+        match read(reader, trimmed_rest)? {
             ParsedRest((node, rest)) => {
                 nodes.push(node);
                 // No closing paren for us, therefore we must parse another symbol (loop again)
-                trimmed_rest = rest.trim_ascii();
+                trimmed_rest = trim_whitespace(rest);
             }
             Parsed(_) => {
                 // If we fully parsed, it means we didn't find the closing parens as well, but we finished, error!
-                return Err(MissingRightParenthesis);
+                return Err(missing_closing_delimiter(closing_delimiter));
             }
         };
     };
 }
 
+pub fn tokenize(buffer: &[u8]) -> Result<AstToken<'_>, ParseError> {
+    read(&Reader::new(buffer, None, 1), buffer)
+}
+
+// Like tokenize, but the spans of lists name the source and count lines from first_line, for
+// buffers that are one part of a bigger file
+pub fn tokenize_source<'a>(buffer: &'a [u8], name: Rc<str>, first_line: usize) -> Result<AstToken<'a>, ParseError> {
+    read(&Reader::new(buffer, Some(name), first_line), buffer)
+}
+
+// Reads every form of a whole file, an empty file or one with only comments has none
+pub fn tokenize_all(buffer: &[u8], name: Rc<str>) -> Result<Vec<AstNode>, ParseError> {
+    tokenize_all_located(buffer, name).map_err(|(error, _)| error)
+}
+
+// Like tokenize_all, but an error comes with the byte offset of the innermost form it is about,
+// like the ( of a list that isn't closed or the start of a malformed number
+pub fn tokenize_all_located(buffer: &[u8], name: Rc<str>) -> Result<Vec<AstNode>, (ParseError, usize)> {
+    let reader = Reader::new(buffer, Some(name), 1);
+    let mut nodes = vec![];
+    let mut rest = buffer;
+    while !trim_whitespace(rest).is_empty() {
+        match read(&reader, rest).map_err(|error| (error, reader.error_offset.get().unwrap_or(0)))? {
+            Parsed(node) => return Ok([nodes, vec![node]].concat()),
+            ParsedRest((node, unread)) => {
+                nodes.push(node);
+                rest = unread;
+            }
+        }
+    }
+    Ok(nodes)
+}
+
 #[cfg(test)]
 mod tests {
-    use std::assert_matches::assert_matches;
+    use std::assert_matches;
 
     use crate::tokenize::AstToken::Parsed;
+    use crate::value::Value;
 
     use super::*;
 
@@ -286,6 +645,75 @@ mod tests {
         assert_matches!(result, Parsed(Num(-5124)));
     }
 
+    #[test]
+    fn radix_prefixed_numbers_tokenized() {
+        assert_matches!(tokenize(b"0xFF").unwrap(), Parsed(Num(255)));
+        assert_matches!(tokenize(b"-0x1f").unwrap(), Parsed(Num(-31)));
+        assert_matches!(tokenize(b"0o17").unwrap(), Parsed(Num(15)));
+        assert_matches!(tokenize(b"0b1010").unwrap(), Parsed(Num(10)));
+        assert_matches!(tokenize(b"0b1111_0000").unwrap(), Parsed(Num(240)));
+    }
+
+    #[test]
+    fn underscored_and_exponent_numbers_tokenized() {
+        assert_matches!(tokenize(b"1_000_000").unwrap(), Parsed(Num(1_000_000)));
+        assert_matches!(tokenize(b"1e6").unwrap(), Parsed(Num(1_000_000)));
+        assert_matches!(tokenize(b"2.5E3").unwrap(), Parsed(Num(2500)));
+        assert_matches!(tokenize(b"-1_5e+2").unwrap(), Parsed(Num(-1500)));
+        assert_matches!(tokenize(b"1200e-2").unwrap(), Parsed(Num(12)));
+    }
+
+    #[test]
+    fn errors_point_at_the_innermost_form() {
+        let offset = |source: &str| tokenize_all_located(source.as_bytes(), "test".into()).unwrap_err().1;
+        assert_eq!(offset("(a b) (f [1 2 3x])"), 14);
+        assert_eq!(offset("(a\n  (b c)\n  (d e)"), 0);
+        assert_eq!(offset("(f (g \"unclosed))"), 6);
+        assert_eq!(offset("  [1 #\"(\"]"), 5);
+    }
+
+    #[test]
+    fn atoms_must_be_utf8() {
+        assert_matches!(tokenize(b"(foo\xff 1)"), Err(InvalidUtf8));
+        assert_matches!(tokenize(b":\xc3"), Err(InvalidUtf8));
+        assert_matches!(tokenize("(caf\u{e9} 1)".as_bytes()), Ok(Parsed(_)));
+    }
+
+    #[test]
+    fn malformed_numbers_are_errors() {
+        assert_matches!(tokenize(b"0b102"), Err(CannotParseNumber(string)) if string == "0b102");
+        assert_matches!(tokenize(b"1__0"), Err(CannotParseNumber(_)));
+        assert_matches!(tokenize(b"1_"), Err(CannotParseNumber(_)));
+        assert_matches!(tokenize(b"0x"), Err(CannotParseNumber(_)));
+        assert_matches!(tokenize(b"15e-1"), Err(NotAWholeNumber(string)) if string == "15e-1");
+        assert_matches!(tokenize(b"0x1_0000_0000_0000_0000"), Err(NumberOutOfRange(_)));
+        assert_matches!(tokenize(b"1.11e-9223372036854775807"), Err(NumberOutOfRange(string)) if string == "1.11e-9223372036854775807");
+        assert_matches!(tokenize(b"1e-9223372036854775807"), Err(NotAWholeNumber(_)));
+        assert_matches!(tokenize(b"1.5e9223372036854775807"), Err(NumberOutOfRange(_)));
+    }
+
+    #[test]
+    fn lone_signs_are_symbols() {
+        assert_matches!(tokenize(b"-").unwrap(), Parsed(Sym(symbol_str)) if symbol_str.as_ref() == b"-");
+        assert_matches!(tokenize(b"-x").unwrap(), Parsed(Sym(symbol_str)) if symbol_str.as_ref() == b"-x");
+    }
+
+    #[test]
+    fn radix_formatting_can_be_read_back() {
+        for radix in [2, 8, 10, 16] {
+            let formatted = format_radix(-4242, radix).unwrap();
+            assert_eq!(parse_number(&formatted).unwrap(), -4242);
+        }
+        assert_eq!(format_radix(255, 16).unwrap(), "0xff");
+        assert_eq!(format_radix(255, 7), None);
+    }
+
+    #[test]
+    fn keywords_tokenized() {
+        assert_matches!(tokenize(b" :name ").unwrap(), Parsed(Keyword(keyword)) if keyword.name() == "name");
+        assert_matches!(tokenize(b":").unwrap(), Parsed(Sym(symbol)) if symbol.name() == ":");
+    }
+
     #[test]
     fn returns_error_when_empty() {
         let result = tokenize(b"");
@@ -308,13 +736,70 @@ mod tests {
     #[test]
     fn returns_empty_list_when_empty_list() {
         let result = tokenize(b"()").unwrap();
-        assert_matches!(result, Parsed(List(the_vec)) if the_vec.len() == 0);
+        assert_matches!(result, Parsed(List(the_vec, _)) if the_vec.is_empty());
     }
 
     #[test]
     fn returns_string_when_string() {
         let result = tokenize(b"\"asda asdas dasd\"").unwrap();
-        assert_matches!(result, Parsed(Str(the_str)) if *the_str == *b"asda asdas dasd");
+        assert_matches!(result, Parsed(Str(the_str)) if the_str.as_bytes() == b"asda asdas dasd");
+    }
+
+    #[test]
+    fn cloned_string_values_share_their_buffer() {
+        let Parsed(Str(the_str)) = tokenize(b"\"a large buffer\"").unwrap() else { panic!() };
+        let value = Value::Str(the_str.clone());
+        let Value::Str(cloned) = value.clone() else { panic!() };
+        assert!(Rc::ptr_eq(&the_str, &cloned));
+    }
+
+    #[test]
+    fn escapes_in_strings_are_read() {
+        assert_matches!(tokenize(br#""a\"b\\c\n\u{e9}""#).unwrap(), Parsed(Str(the_str)) if &*the_str == "a\"b\\c\né");
+        assert_matches!(tokenize(br#"("\"" x)"#).unwrap(), Parsed(List(nodes, _)) if nodes.len() == 2);
+        assert_matches!(tokenize(br#""\q""#), Err(InvalidEscape(escape)) if escape == "q");
+        assert_matches!(tokenize(br#""\u{110000}""#), Err(InvalidEscape(_)));
+        assert_matches!(tokenize(br#""abc\""#), Err(MissingDoubleQuote));
+    }
+
+    #[test]
+    fn comments_are_skipped() {
+        assert_matches!(tokenize(b"(+ 1 ; one\n 2) ; done").unwrap(), Parsed(List(nodes, _)) if nodes.len() == 3);
+        assert_matches!(tokenize(b"x;comment").unwrap(), Parsed(Sym(symbol)) if symbol.name() == "x");
+        assert_matches!(tokenize(b"\"a;b\";c").unwrap(), Parsed(Str(the_str)) if &*the_str == "a;b");
+        assert_matches!(tokenize(b"; only a comment"), Err(CannotParseEmpty));
+    }
+
+    #[test]
+    fn whole_files_are_read_form_by_form() {
+        let nodes = tokenize_all(b"; header\n(def x\n  1)\n:k \"s\" (f [x])  ; done\n", Rc::from("file.lsp")).unwrap();
+        assert_eq!(nodes.iter().map(AstNode::to_string).collect::<Vec<_>>(), ["(def x 1)", ":k", "\"s\"", "(f [x])"]);
+        let List(_, Some(span)) = &nodes[3] else { panic!() };
+        assert_eq!(span.to_string(), "file.lsp:4:8");
+        assert!(tokenize_all(b" ; nothing\n", Rc::from("empty.lsp")).unwrap().is_empty());
+        assert_matches!(tokenize_all(b"(f) x)", Rc::from("bad.lsp")), Err(MissingLeftParenthesis));
+    }
+
+    #[test]
+    fn at_sign_reads_as_deref() {
+        let deref: AstNode = vec![AstNode::from(b"deref"), AstNode::from(b"a")].into();
+        assert_eq!(tokenize(b"(+ @a 1)").unwrap(), Parsed(vec![AstNode::from(b"+"), deref, Num(1)].into()));
+        assert_matches!(tokenize(b"@"), Err(CannotParseEmpty));
+    }
+
+    #[test]
+    fn commas_are_whitespace() {
+        let Parsed(Map(nodes)) = tokenize(b"{:a 1, :b 2,}").unwrap() else { panic!() };
+        assert_eq!(nodes.len(), 4);
+        assert_matches!(tokenize(b"[1,2]").unwrap(), Parsed(Vector(nodes)) if nodes.len() == 2);
+    }
+
+    #[test]
+    fn regex_literals_keep_escaped_quotes() {
+        let Parsed(List(nodes, _)) = tokenize(br#"(re-find #"a\"b(\d)" x)"#).unwrap() else { panic!() };
+        assert_matches!(&nodes[1], Regex(pattern) if pattern.as_str() == r#"a\"b(\d)"#);
+        assert_matches!(tokenize(br#"#"a(""#), Err(InvalidRegex(_)));
+        assert_matches!(tokenize(br#"#"abc\""#), Err(MissingDoubleQuote));
     }
 
     #[test]
@@ -413,6 +898,37 @@ mod tests {
     }
 
 
+    #[test]
+    fn symbol_followed_by_several_closing_delimiters_is_cut() {
+        let result = tokenize(b"(a (b [c d]) e)").unwrap();
+        let expected: AstNode = vec![
+            b"a".into(),
+            vec![b"b".into(), Vector([b"c".into(), b"d".into()].into())].into(),
+            b"e".into(),
+        ].into();
+        assert_matches!(result, Parsed(ast_node) if ast_node == expected);
+    }
+
+    #[test]
+    fn vectors_and_maps_tokenized() {
+        let result = tokenize(b"{:a [1 2] :b {}}").unwrap();
+        let expected = Map([
+            Keyword(Symbol::intern(b"a")),
+            Vector([Num(1), Num(2)].into()),
+            Keyword(Symbol::intern(b"b")),
+            Map([].into()),
+        ].into());
+        assert_matches!(result, Parsed(ast_node) if ast_node == expected);
+    }
+
+    #[test]
+    fn mismatched_delimiters_are_errors() {
+        assert_matches!(tokenize(b"(a b]"), Err(MismatchedDelimiter(')', ']')));
+        assert_matches!(tokenize(b"[a b"), Err(MissingClosingDelimiter(']')));
+        assert_matches!(tokenize(b"}"), Err(UnexpectedClosingDelimiter('}')));
+        assert_matches!(tokenize(b"{:a}"), Err(OddNumberOfMapForms));
+    }
+
     #[test]
     fn list_gets_parsed_correctly() {
         let result = tokenize(b"(  a  b (x y) z)").unwrap();
@@ -0,0 +1,301 @@
+use std::cmp::Ordering;
+use std::fmt::{Debug, Display, Formatter};
+use std::hash::{Hash, Hasher};
+use std::rc::Rc;
+
+use crate::atom::Atom;
+use crate::host::HostObject;
+use crate::eval::EvalError::InvalidArguments;
+use crate::eval::{EvalError, LispFn};
+use crate::lazy_seq::{LazySeq, SeqIter};
+use crate::pattern::Pattern;
+use crate::persistent_map::PersistentMap;
+use crate::persistent_vector::PersistentVector;
+use crate::symbol::Symbol;
+use crate::tokenize::{AstNode, write_escaped_str};
+
+// Strings and collections are reference counted (vectors and maps are persistent and share structure),
+// so cloning a value, e.g. to pass it to a function, is O(1)
+#[derive(Clone)]
+pub enum Value {
+    Nil,
+    Bool(bool),
+    Num(isize),
+    Str(Rc<str>),
+    Keyword(Symbol),
+    List(Rc<[Value]>),
+    Vector(PersistentVector<Value>),
+    Map(PersistentMap<Value, Value>),
+    Fn(Rc<LispFn>),
+    LazySeq(Rc<LazySeq>),
+    Regex(Rc<Pattern>),
+    Atom(Rc<Atom>),
+    Host(Rc<HostObject>),
+}
+
+impl Value {
+    pub fn num(&self) -> Option<isize> {
+        match self {
+            Value::Num(num) => { Some(*num) }
+            _ => { None }
+        }
+    }
+
+    // Only nil and false are falsy, everything else counts as true
+    pub fn is_truthy(&self) -> bool {
+        !matches!(self, Value::Nil | Value::Bool(false))
+    }
+
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::Nil => "nil",
+            Value::Bool(_) => "boolean",
+            Value::Num(_) => "number",
+            Value::Str(_) => "string",
+            Value::Keyword(_) => "keyword",
+            Value::List(_) => "list",
+            Value::Vector(_) => "vector",
+            Value::Map(_) => "map",
+            Value::Fn(_) => "function",
+            Value::LazySeq(_) => "lazy sequence",
+            Value::Regex(_) => "regex",
+            Value::Atom(_) => "atom",
+            Value::Host(_) => "host object",
+        }
+    }
+}
+
+// Reads literal data without evaluating anything, so whatever the readable printer wrote comes back
+// as an equal value
+impl TryFrom<&AstNode> for Value {
+    type Error = EvalError;
+
+    fn try_from(node: &AstNode) -> Result<Self, Self::Error> {
+        match node {
+            AstNode::Num(number) => Ok(Value::Num(*number)),
+            AstNode::Str(string) => Ok(Value::Str(string.clone())),
+            AstNode::Keyword(keyword) => Ok(Value::Keyword(*keyword)),
+            AstNode::Regex(pattern) => Ok(Value::Regex(pattern.clone())),
+            AstNode::Sym(Symbol::NIL) => Ok(Value::Nil),
+            AstNode::Sym(Symbol::TRUE) => Ok(Value::Bool(true)),
+            AstNode::Sym(Symbol::FALSE) => Ok(Value::Bool(false)),
+            AstNode::Sym(symbol) => Err(InvalidArguments(format!("the symbol {symbol} cannot be read as data"))),
+            AstNode::List(nodes, _) => nodes.iter().map(Value::try_from).collect::<Result<Vec<Value>, EvalError>>().map(Value::from),
+            AstNode::Vector(nodes) => nodes.iter().map(Value::try_from).collect::<Result<_, EvalError>>().map(Value::Vector),
+            AstNode::Map(nodes) => nodes.chunks_exact(2)
+                .map(|entry| Ok((Value::try_from(&entry[0])?, Value::try_from(&entry[1])?)))
+                .collect::<Result<_, EvalError>>().map(Value::Map),
+        }
+    }
+}
+
+impl From<Vec<Value>> for Value {
+    fn from(value: Vec<Value>) -> Self {
+        Value::List(value.into())
+    }
+}
+
+impl PartialEq for Value {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Value::Nil, Value::Nil) => true,
+            (Value::Bool(left), Value::Bool(right)) => left == right,
+            (Value::Num(left), Value::Num(right)) => left == right,
+            (Value::Str(left), Value::Str(right)) => left == right,
+            (Value::Keyword(left), Value::Keyword(right)) => left == right,
+            (Value::List(left), Value::List(right)) => left == right,
+            (Value::Vector(left), Value::Vector(right)) => left == right,
+            (Value::Map(left), Value::Map(right)) => left == right,
+            (Value::Fn(left), Value::Fn(right)) => Rc::ptr_eq(left, right),
+            (Value::Atom(left), Value::Atom(right)) => Rc::ptr_eq(left, right),
+            (Value::Host(left), Value::Host(right)) => Rc::ptr_eq(left, right),
+            (Value::Regex(left), Value::Regex(right)) => left == right,
+            (Value::LazySeq(_), Value::List(_) | Value::LazySeq(_)) | (Value::List(_), Value::LazySeq(_)) => {
+                // Realising can fail, and a sequence that can't be realised isn't equal to anything
+                let mut left_iter = SeqIter::new(self.clone());
+                let mut right_iter = SeqIter::new(other.clone());
+                loop {
+                    match (left_iter.next(), right_iter.next()) {
+                        (None, None) => return true,
+                        (Some(Ok(left)), Some(Ok(right))) if left == right => {}
+                        _ => return false,
+                    }
+                }
+            }
+            _ => false,
+        }
+    }
+}
+
+impl Eq for Value {}
+
+// Numbers, strings, keywords and sequences of those have a natural order, anything else is unordered
+impl PartialOrd for Value {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        match (self, other) {
+            (Value::Nil, Value::Nil) => Some(Ordering::Equal),
+            (Value::Bool(left), Value::Bool(right)) => left.partial_cmp(right),
+            (Value::Num(left), Value::Num(right)) => left.partial_cmp(right),
+            (Value::Str(left), Value::Str(right)) => left.partial_cmp(right),
+            (Value::Keyword(left), Value::Keyword(right)) => left.name().partial_cmp(right.name()),
+            (Value::List(left), Value::List(right)) => left.iter().partial_cmp(right.iter()),
+            (Value::Vector(left), Value::Vector(right)) => left.iter().partial_cmp(right.iter()),
+            _ if self == other => Some(Ordering::Equal),
+            _ => None,
+        }
+    }
+}
+
+impl Hash for Value {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        if let Value::LazySeq(_) = self {
+            // Lazy sequences are equal to lists with the same elements, so they have to hash the same
+            let values = SeqIter::new(self.clone()).map_while(Result::ok).collect::<Vec<Value>>();
+            return Value::List(values.into()).hash(state);
+        }
+        std::mem::discriminant(self).hash(state);
+        match self {
+            Value::Nil => {}
+            Value::Bool(boolean) => boolean.hash(state),
+            Value::Num(number) => number.hash(state),
+            Value::Str(string) => string.hash(state),
+            Value::Keyword(keyword) => keyword.hash(state),
+            Value::List(values) => values.hash(state),
+            Value::Vector(values) => values.iter().for_each(|value| value.hash(state)),
+            Value::Map(map) => map.hash(state),
+            Value::Fn(function) => Rc::as_ptr(function).hash(state),
+            Value::Atom(atom) => Rc::as_ptr(atom).hash(state),
+            Value::Host(object) => Rc::as_ptr(object).hash(state),
+            Value::Regex(pattern) => pattern.hash(state),
+            Value::LazySeq(_) => unreachable!("Lazy sequences are hashed as lists"),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PrintMode {
+    // What pr-str and the REPL show: strings are quoted and escaped, so the output reads back via
+    // tokenize to an equal value (functions and cut off lazy sequences have no literal syntax though)
+    Readable,
+    // What str and println show: strings and regexes as their plain text, for people to read
+    Display,
+}
+
+pub struct Printed<'a> {
+    value: &'a Value,
+    mode: PrintMode,
+}
+
+impl Value {
+    pub fn printed(&self, mode: PrintMode) -> Printed<'_> {
+        Printed { value: self, mode }
+    }
+}
+
+fn write_sequence<'a>(f: &mut Formatter<'_>, open: &str, values: impl Iterator<Item=&'a Value>, close: &str, mode: PrintMode) -> std::fmt::Result {
+    write!(f, "{}", open)?;
+    let mut value_iter = values;
+    if let Some(first_value) = value_iter.next() {
+        write!(f, "{}", first_value.printed(mode))?;
+    }
+    value_iter.try_for_each(|value| write!(f, " {}", value.printed(mode)))?;
+    write!(f, "{}", close)?;
+    Ok(())
+}
+
+impl Display for Printed<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let mode = self.mode;
+        match self.value {
+            Value::Nil => {
+                write!(f, "nil")?;
+                Ok(())
+            }
+            Value::Bool(boolean) => {
+                write!(f, "{}", boolean)?;
+                Ok(())
+            }
+            Value::Num(number) => {
+                write!(f, "{}", number)?;
+                Ok(())
+            }
+            Value::Str(string) => match mode {
+                PrintMode::Readable => write_escaped_str(f, string),
+                PrintMode::Display => write!(f, "{}", string),
+            },
+            Value::Keyword(keyword) => {
+                write!(f, ":{}", keyword)?;
+                Ok(())
+            }
+            Value::List(values) => write_sequence(f, "(", values.iter(), ")", mode),
+            Value::Vector(values) => write_sequence(f, "[", values.iter(), "]", mode),
+            Value::Map(map) => {
+                write!(f, "{{")?;
+                let mut entry_iter = map.iter();
+                if let Some((key, value)) = entry_iter.next() {
+                    write!(f, "{} {}", key.printed(mode), value.printed(mode))?;
+                }
+                entry_iter.try_for_each(|(key, value)| write!(f, ", {} {}", key.printed(mode), value.printed(mode)))?;
+                write!(f, "}}")?;
+                Ok(())
+            }
+            Value::Fn(_) => {
+                write!(f, "#<function>")?;
+                Ok(())
+            }
+            Value::LazySeq(lazy_seq) => lazy_seq.fmt_with(f, mode),
+            Value::Regex(pattern) => match mode {
+                PrintMode::Readable => write!(f, "{}", pattern),
+                PrintMode::Display => write!(f, "{}", pattern.as_str()),
+            },
+            Value::Atom(atom) => write!(f, "#<atom {}>", atom.get().printed(mode)),
+            Value::Host(object) => write!(f, "{}", object),
+        }
+    }
+}
+
+impl Display for Value {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.printed(PrintMode::Readable))
+    }
+}
+
+impl Debug for Value {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_support::eval_value;
+    use crate::tokenize::AstToken::Parsed;
+    use crate::tokenize::tokenize;
+
+    use super::*;
+
+    #[test]
+    fn readable_output_reads_back_to_an_equal_value() {
+        let sources = [
+            r#"["quote \" backslash \\ tab \t" "\u{7}" "héllo"]"#,
+            r#"{:a [1 "x"], "key" (list nil true false), -5 #"a\"b"}"#,
+            r#"(take 3 (map str (range)))"#,
+            r#"(re-pattern "say \"hi\"")"#,
+        ];
+        for source in sources {
+            let value = eval_value(source);
+            let printed = value.to_string();
+            let Parsed(node) = tokenize(printed.as_bytes()).unwrap() else { panic!("{printed} did not read back") };
+            assert_eq!(Value::try_from(&node).unwrap(), value, "{printed}");
+        }
+    }
+
+    #[test]
+    fn display_mode_shows_plain_text() {
+        let value = eval_value(r#"[nil "a\"b" #"\d" {:k "v"}]"#);
+        assert_eq!(value.printed(PrintMode::Display).to_string(), r#"[nil a"b \d {:k v}]"#);
+        assert_eq!(value.printed(PrintMode::Readable).to_string(), r#"[nil "a\"b" #"\d" {:k "v"}]"#);
+        assert_eq!(eval_value(r#"(str "a\nb" nil 1)"#), Value::Str("a\nb1".into()));
+        assert_eq!(eval_value(r#"(pr-str "a\nb" nil 1)"#), Value::Str(r#""a\nb" nil 1"#.into()));
+    }
+}